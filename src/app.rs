@@ -20,8 +20,16 @@ use std::{fmt::Debug, io::Cursor, path::PathBuf, sync::Arc};
 use crate::{
     document::Document,
     edit_mode::{EditMode, Mode},
-    panels::{CursorInfo, ImageInfo, ImageOperationsPanel},
+    exif_editor::ExifEditor,
+    export::{ExportDialog, ExportOptions},
+    metadata,
+    panels::{CursorInfo, ImageInfo, ImageOperationsPanel, LayersPanel, MacrosPanel},
+    preview::Preview,
+    project::PROJECT_EXTENSION,
+    recording::{Macro, Macros},
     side_panel::ImageOperations,
+    text::{self, Fonts},
+    top_panel::PasteTarget,
 };
 
 /// New Image settings
@@ -60,6 +68,12 @@ pub(crate) struct AppSettings {
     /// New image settings
     #[serde(skip)]
     pub(crate) new_image: NewImage,
+    /// Export options
+    #[serde(default)]
+    pub(crate) export: ExportOptions,
+    /// Keep the raw sensor orientation instead of following the exif Orientation tag
+    #[serde(default)]
+    pub(crate) raw_orientation: bool,
 }
 
 impl Default for AppSettings {
@@ -69,6 +83,8 @@ impl Default for AppSettings {
             color_selection: Color32::from_black_alpha(50),
             image_info_as_window: false,
             new_image: NewImage::default(),
+            export: ExportOptions::default(),
+            raw_orientation: false,
         }
     }
 }
@@ -91,17 +107,32 @@ pub struct TarsierApp {
     /// clipboard
     #[serde(skip)]
     pub(crate) clipboard: BladvakClipBoard,
+    /// Macros
+    pub(crate) macros: Macros,
+    /// Where to paste the clipboard image
+    #[serde(skip)]
+    pub(crate) paste_target: PasteTarget,
+    /// Last copied pixels, to paste them without loss
+    #[serde(skip)]
+    pub(crate) copied: Option<DynamicImage>,
+    /// Export dialog
+    #[serde(skip)]
+    pub(crate) export: Option<ExportDialog>,
+    /// Exif editor
+    #[serde(skip)]
+    pub(crate) exif_editor: ExifEditor,
+    /// Preview of the pending operation
+    #[serde(skip)]
+    pub(crate) preview: Preview,
+    /// Fonts of the text tool
+    #[serde(skip)]
+    pub(crate) fonts: Fonts,
 }
 
 impl Default for TarsierApp {
     fn default() -> Self {
         let (img, _) = TarsierApp::load_default_image();
-        let document = Document {
-            saved_img: img.clone(),
-            img,
-            filename: PathBuf::from("tarsier.png"),
-            ..Default::default()
-        };
+        let document = Document::new(PathBuf::from("tarsier.png"), img);
         let mut documents = Documents::default();
         documents.push(document);
         Self {
@@ -111,6 +142,13 @@ impl Default for TarsierApp {
             settings: AppSettings::default(),
             grid: Grid::default(),
             clipboard: BladvakClipBoard::default(),
+            macros: Macros::default(),
+            paste_target: PasteTarget::default(),
+            copied: None,
+            export: None,
+            exif_editor: ExifEditor::default(),
+            preview: Preview::default(),
+            fonts: Fonts::default(),
         }
     }
 }
@@ -133,7 +171,7 @@ impl TarsierApp {
     }
 
     /// Cursor ui
-    pub(crate) fn cursor_ui(&mut self, ui: &mut egui::Ui) {
+    pub(crate) fn cursor_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         let Some(document) = self.documents.get_current_doc_mut() else {
             ui.label("No document");
             return;
@@ -143,8 +181,13 @@ impl TarsierApp {
                 // do nothing
             }
             EditMode::Drawing => {
-                let max_radius = document.img.width().max(document.img.height());
-                self.mode.drawing.show(ui, max_radius);
+                self.mode.drawing.show(ui, document);
+            }
+            EditMode::Eraser => {
+                self.eraser_ui(ui, error_manager);
+            }
+            EditMode::Bucket => {
+                self.mode.bucket.show(ui, &mut self.mode.drawing);
             }
             EditMode::Selection => {
                 self.selection_ui(ui);
@@ -154,6 +197,26 @@ impl TarsierApp {
                 ui.label(format!("x = {}, y = {} = {:?}", x, y, color.to_array()));
                 ui.color_edit_button_srgba(color);
             }
+            EditMode::Straighten => {
+                ui.label("Draw a line that should be horizontal");
+                self.image_operations.rotate.show_options(ui);
+            }
+            EditMode::Perspective => {
+                self.perspective_ui(ui, error_manager);
+            }
+            EditMode::Shape => {
+                self.mode.shape.show(ui);
+            }
+            EditMode::Text => {
+                self.text_ui(ui, error_manager);
+            }
+            EditMode::MagicWand => {
+                self.mode.magic_wand.show(ui);
+                if document.selection.rectangle.is_some() && ui.button("Clear selection").clicked()
+                {
+                    document.selection.clear();
+                }
+            }
         }
     }
 
@@ -164,31 +227,29 @@ impl TarsierApp {
         new_img: DynamicImage,
         opt_cursor: Option<Cursor<&[u8]>>,
     ) {
-        let exif = if let Some(bytes) = opt_cursor {
+        let mut new_img = new_img;
+        let mut exif = if let Some(bytes) = opt_cursor {
             let mut bufreader = std::io::BufReader::new(bytes);
             exif::Reader::new().read_from_container(&mut bufreader).ok()
         } else {
             None
         };
+        if !self.settings.raw_orientation
+            && let Some(raw_exif) = &exif
+        {
+            match metadata::auto_orient(&mut new_img, raw_exif) {
+                Ok(Some(upright_exif)) => exif = Some(upright_exif),
+                Ok(None) => {}
+                Err(e) => log::warn!("Cannot orient '{}': {e}", filename.display()),
+            }
+        }
         let new_document = Document {
-            saved_img: new_img.clone(),
-            img: new_img,
             exif,
-            filename,
-            ..Default::default()
+            ..Document::new(filename, new_img)
         };
         self.documents.push(new_document);
     }
 
-    /// Update image
-    pub(crate) fn update_image(&mut self, new_img: DynamicImage) {
-        let Some(document) = self.documents.get_current_doc_mut() else {
-            return;
-        };
-        document.img = new_img;
-        self.updated_image();
-    }
-
     /// Post update image
     pub(crate) fn updated_image(&mut self) {
         let Some(document) = self.documents.get_current_doc_mut() else {
@@ -196,7 +257,7 @@ impl TarsierApp {
         };
         document.texture = None;
         if self.settings.remove_selection_after_op {
-            document.selection.rectangle = None;
+            document.selection.clear();
         }
     }
 }
@@ -207,6 +268,8 @@ impl BladvakApp<'_> for TarsierApp {
             Box::new(ImageInfo),
             Box::new(ImageOperationsPanel),
             Box::new(CursorInfo),
+            Box::new(LayersPanel),
+            Box::new(MacrosPanel),
         ]
     }
 
@@ -219,6 +282,29 @@ impl BladvakApp<'_> for TarsierApp {
     }
 
     fn handle_file(&mut self, file: File) -> Result<(), AppError> {
+        if file.path.extension().is_some_and(|ext| ext == "json") {
+            let new_macro = Macro::from_json(&file.data)?;
+            self.macros.saved.push(new_macro);
+            return Ok(());
+        }
+        if text::is_font_file(&file.path) {
+            let name = file.path.file_stem().map_or_else(
+                || "Font".to_string(),
+                |stem| stem.to_string_lossy().into_owned(),
+            );
+            self.fonts.load(name.clone(), file.data)?;
+            self.mode.text.font = name;
+            return Ok(());
+        }
+        if file
+            .path
+            .extension()
+            .is_some_and(|ext| ext == PROJECT_EXTENSION)
+        {
+            let document = Document::from_project(&file.data)?;
+            self.documents.push(document);
+            return Ok(());
+        }
         let img_reader = ImageReader::new(Cursor::new(&file.data)).with_guessed_format()?;
         let img = match img_reader.decode() {
             Ok(img) => img,
@@ -245,6 +331,7 @@ impl BladvakApp<'_> for TarsierApp {
     fn central_panel(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         self.app_central_panel(ui, error_manager);
         self.show_new_image_modal(ui);
+        self.show_export_modal(ui, error_manager);
     }
 
     fn name() -> String {
@@ -281,6 +368,13 @@ impl BladvakApp<'_> for TarsierApp {
                 let absolute_path = fs::canonicalize(one_path)
                     .map_err(|e| format!("Unable to canonicalize path '{one_path}': {e}"))?;
                 let bytes = fs::read(&absolute_path)?;
+                if absolute_path
+                    .extension()
+                    .is_some_and(|ext| ext == PROJECT_EXTENSION)
+                {
+                    app.documents.push(Document::from_project(&bytes)?);
+                    continue;
+                }
                 let cursor: Cursor<&[u8]> = Cursor::new(bytes.as_ref());
                 let img_reader = ImageReader::new(cursor);
                 match img_reader.with_guessed_format()?.decode() {
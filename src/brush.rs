@@ -0,0 +1,662 @@
+//! Brush engine: soft and spaced dabs, composited on every color type
+
+use bladvak::eframe::egui::{self, Color32, Pos2, Vec2};
+use bladvak::errors::{AppError, ErrorManager};
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
+use std::sync::Arc;
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::edit_mode::{DrawingMode, SelectionState};
+use crate::layer::Layer;
+use crate::ops::{self, Region};
+use crate::shapes;
+
+/// Longest side of a bitmap tip
+const MAX_TIP_SIZE: u32 = 256;
+
+/// Shape of the brush
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum BrushTip {
+    /// Disc
+    #[default]
+    Round,
+    /// Square aligned with the pixels
+    Square,
+    /// Grayscale image taken from the selection
+    Bitmap,
+}
+
+impl BrushTip {
+    /// All the tips
+    const ALL: [Self; 3] = [Self::Round, Self::Square, Self::Bitmap];
+}
+
+impl std::fmt::Display for BrushTip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Round => write!(f, "Round"),
+            Self::Square => write!(f, "Square"),
+            Self::Bitmap => write!(f, "Bitmap"),
+        }
+    }
+}
+
+/// Grayscale image used as a brush tip, 255 paints fully
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub(crate) struct TipBitmap {
+    /// Width of the tip
+    width: u32,
+    /// Height of the tip
+    height: u32,
+    /// Paint of each pixel, row by row
+    pixels: Vec<u8>,
+}
+
+impl TipBitmap {
+    /// Tip from the selected pixels of the document: the dark and opaque pixels paint
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn from_selection(document: &Document) -> Option<Self> {
+        let region = document.selection_region()?;
+        if region.is_empty() {
+            return None;
+        }
+        let mut img = ops::crop(&document.composite(), region);
+        if img.width().max(img.height()) > MAX_TIP_SIZE {
+            img = img.resize(MAX_TIP_SIZE, MAX_TIP_SIZE, FilterType::Triangle);
+        }
+        let mask = document.selection_mask(region).map(|mask| {
+            DynamicImage::ImageLuma8(mask)
+                .resize_exact(img.width(), img.height(), FilterType::Triangle)
+                .to_luma8()
+        });
+        let (width, height) = img.dimensions();
+        let pixels = img
+            .to_rgba32f()
+            .enumerate_pixels()
+            .map(|(x, y, pixel)| {
+                let [red, green, blue, alpha] = pixel.0;
+                let luma = 0.2126 * red + 0.7152 * green + 0.0722 * blue;
+                let selected = mask
+                    .as_ref()
+                    .map_or(1.0, |mask| f32::from(mask.get_pixel(x, y)[0]) / 255.0);
+                ((1.0 - luma) * alpha * selected * 255.0)
+                    .round()
+                    .clamp(0.0, 255.0) as u8
+            })
+            .collect();
+        Some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Paint at a position relative to the center, the longest side spans from -1 to 1
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn sample(&self, pos: Vec2) -> f32 {
+        let half = self.width.max(self.height) as f32 / 2.0;
+        let x = pos.x * half + self.width as f32 / 2.0 - 0.5;
+        let y = pos.y * half + self.height as f32 / 2.0 - 0.5;
+        let get = |x: f32, y: f32| {
+            if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+                return 0.0;
+            }
+            f32::from(self.pixels[y as usize * self.width as usize + x as usize]) / 255.0
+        };
+        let (left, top) = (x.floor(), y.floor());
+        let (fx, fy) = (x - left, y - top);
+        let upper = egui::lerp(get(left, top)..=get(left + 1.0, top), fx);
+        let lower = egui::lerp(get(left, top + 1.0)..=get(left + 1.0, top + 1.0), fx);
+        egui::lerp(upper..=lower, fy)
+    }
+}
+
+/// Brush settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub(crate) struct Brush {
+    /// Shape of the brush
+    pub(crate) tip: BrushTip,
+    /// Part of the radius painted fully, the rest fades out
+    pub(crate) hardness: f32,
+    /// Maximum opacity of a stroke
+    pub(crate) opacity: f32,
+    /// Paint deposited by each dab, it builds up to the opacity where the dabs overlap
+    pub(crate) flow: f32,
+    /// Distance between the dabs, in percent of the diameter
+    pub(crate) spacing: f32,
+    /// Image of the bitmap tip
+    pub(crate) bitmap: Option<TipBitmap>,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tip: BrushTip::default(),
+            hardness: 0.8,
+            opacity: 1.0,
+            flow: 1.0,
+            spacing: 10.0,
+            bitmap: None,
+        }
+    }
+}
+
+impl Brush {
+    /// Settings ui, the bitmap tip is taken from the selection of `document`
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, document: &Document) {
+        ui.horizontal(|ui| {
+            for tip in BrushTip::ALL {
+                ui.selectable_value(&mut self.tip, tip, tip.to_string());
+            }
+        });
+        if self.tip == BrushTip::Bitmap {
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(
+                        document.selection.rectangle.is_some(),
+                        egui::Button::new("Tip from the selection"),
+                    )
+                    .on_hover_text("The dark and opaque pixels paint")
+                    .clicked()
+                {
+                    self.bitmap = TipBitmap::from_selection(document);
+                }
+                if let Some(bitmap) = &self.bitmap {
+                    ui.label(format!("{}x{}", bitmap.width, bitmap.height));
+                }
+            });
+        }
+        ui.add_enabled(
+            self.tip != BrushTip::Bitmap,
+            egui::Slider::new(&mut self.hardness, 0.0..=1.0).text("Hardness"),
+        );
+        ui.add(egui::Slider::new(&mut self.opacity, 0.0..=1.0).text("Opacity"))
+            .on_hover_text("Maximum opacity of a stroke");
+        ui.add(egui::Slider::new(&mut self.flow, 0.01..=1.0).text("Flow"))
+            .on_hover_text("Paint of each dab, it builds up where the dabs overlap");
+        ui.add(
+            egui::Slider::new(&mut self.spacing, 1.0..=200.0)
+                .text("Spacing")
+                .suffix(" %"),
+        )
+        .on_hover_text("Distance between the dabs, relative to the diameter");
+    }
+
+    /// Paint of a dab of `radius` at an offset from its center, from 0 to 1
+    fn dab(&self, offset: Vec2, radius: f32) -> f32 {
+        let edge = |distance: f32| {
+            // the last pixel is partly covered, and the soft part fades out smoothly
+            let covered = (radius + 0.5 - distance).clamp(0.0, 1.0);
+            let t = distance / radius;
+            if self.hardness >= 1.0 || t <= self.hardness {
+                covered
+            } else {
+                let u = ((t - self.hardness) / (1.0 - self.hardness)).min(1.0);
+                covered * (1.0 - u * u * (3.0 - 2.0 * u))
+            }
+        };
+        match (self.tip, &self.bitmap) {
+            (BrushTip::Square, _) => edge(offset.x.abs().max(offset.y.abs())),
+            (BrushTip::Bitmap, Some(bitmap)) => bitmap.sample(offset / radius),
+            (BrushTip::Round | BrushTip::Bitmap, _) => edge(offset.length()),
+        }
+    }
+}
+
+/// Eraser settings, the brush is shared with the drawing mode
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Eraser {
+    /// Color left by the eraser on the images without alpha channel
+    pub(crate) background: Color32,
+}
+
+impl Default for Eraser {
+    fn default() -> Self {
+        Self {
+            background: Color32::WHITE,
+        }
+    }
+}
+
+/// What a stroke leaves on the pixels
+#[derive(Debug, Clone, Copy)]
+enum Ink {
+    /// The pen color, blended over the pixels or replacing them
+    Pen {
+        /// Color, not premultiplied
+        color: [f32; 4],
+        /// Blend the color instead of replacing the pixels
+        blend: bool,
+    },
+    /// Transparency, or the background color on the images without alpha channel
+    Eraser {
+        /// Background color, not premultiplied
+        background: [f32; 4],
+    },
+}
+
+impl Ink {
+    /// Put the ink on a pixel with an opacity
+    fn apply(self, pixel: &mut [f32; 4], opacity: f32, has_alpha: bool) {
+        match self {
+            Self::Pen { color, blend: true } => {
+                let [red, green, blue, alpha] = color;
+                shapes::over(pixel, [red, green, blue, alpha * opacity]);
+            }
+            Self::Pen {
+                color,
+                blend: false,
+            } => {
+                for (channel, value) in pixel.iter_mut().zip(color) {
+                    *channel += (value - *channel) * opacity;
+                }
+            }
+            Self::Eraser { .. } if has_alpha => pixel[3] *= 1.0 - opacity,
+            Self::Eraser { background } => {
+                for (channel, value) in pixel[..3].iter_mut().zip(background) {
+                    *channel += (value - *channel) * opacity;
+                }
+            }
+        }
+    }
+}
+
+/// Brush stroke being painted on the active layer
+#[derive(Debug, Clone)]
+pub(crate) struct Stroke {
+    /// Pixels of the layer when the stroke started
+    before: DynamicImage,
+    /// Paint deposited on each pixel of the layer, from 0 to 1
+    paint: Vec<f32>,
+    /// Last position of the pointer, in canvas coordinates
+    last: Option<Pos2>,
+    /// Distance from the last dab to the last position
+    travelled: f32,
+}
+
+impl Stroke {
+    /// Start a stroke on a layer
+    fn new(layer: &Layer) -> Self {
+        let (width, height) = layer.img.dimensions();
+        Self {
+            before: layer.img.clone(),
+            paint: vec![0.0; width as usize * height as usize],
+            last: None,
+            travelled: 0.0,
+        }
+    }
+
+    /// Positions of the dabs up to `pos`
+    fn dabs_to(&mut self, pos: Pos2, spacing: f32, continuous: bool) -> Vec<Pos2> {
+        let Some(last) = self.last.replace(pos).filter(|_| continuous) else {
+            self.travelled = 0.0;
+            return vec![pos];
+        };
+        let distance = last.distance(pos);
+        if distance <= 0.0 {
+            return Vec::new();
+        }
+        let direction = (pos - last) / distance;
+        let mut dabs = Vec::new();
+        let mut next = spacing - self.travelled;
+        while next <= distance {
+            dabs.push(last + direction * next);
+            next += spacing;
+        }
+        self.travelled = distance - (next - spacing);
+        dabs
+    }
+
+    /// Add a dab centered at `center` on the canvas, and update the pixels below it
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_precision_loss)]
+    #[allow(clippy::cast_sign_loss)]
+    fn dab(
+        &mut self,
+        layer: &mut Layer,
+        selection: &SelectionState,
+        drawing: &DrawingMode,
+        ink: Ink,
+        center: Pos2,
+    ) -> Result<(), AppError> {
+        let radius = drawing.pen_radius;
+        let (width, height) = layer.img.dimensions();
+        let (offset_x, offset_y) = (layer.props.offset.0 as f32, layer.props.offset.1 as f32);
+        // bounds of the dab in the layer, with a pixel more for the anti-aliased edge
+        let reach = radius + 1.0;
+        let layer_bound =
+            |value: f32, offset: f32, size: u32| (value - offset).clamp(0.0, size as f32) as u32;
+        let min_x = layer_bound((center.x - reach).floor(), offset_x, width);
+        let min_y = layer_bound((center.y - reach).floor(), offset_y, height);
+        let max_x = layer_bound((center.x + reach).ceil(), offset_x, width);
+        let max_y = layer_bound((center.y + reach).ceil(), offset_y, height);
+        if min_x >= max_x || min_y >= max_y {
+            return Ok(());
+        }
+        let brush = &drawing.brush;
+        let has_alpha = layer.img.color().has_alpha();
+        let region = Region::new(min_x, min_y, max_x - min_x, max_y - min_y);
+        let mut patch = ops::crop(&self.before, region).to_rgba32f();
+        for (x, y, pixel) in patch.enumerate_pixels_mut() {
+            let (layer_x, layer_y) = (min_x + x, min_y + y);
+            let canvas = Pos2::new(layer_x as f32 + offset_x, layer_y as f32 + offset_y);
+            let idx = layer_y as usize * width as usize + layer_x as usize;
+            if canvas.x >= 0.0
+                && canvas.y >= 0.0
+                && selection.contains(canvas.x as u32, canvas.y as u32)
+            {
+                let added = brush.dab(canvas + Vec2::splat(0.5) - center, radius) * brush.flow;
+                self.paint[idx] += added * (1.0 - self.paint[idx]);
+            }
+            ink.apply(&mut pixel.0, self.paint[idx] * brush.opacity, has_alpha);
+        }
+        let patch = ops::convert(&DynamicImage::ImageRgba32F(patch), layer.img.color());
+        ops::paste(&mut layer.img, &patch, min_x, min_y)
+            .map_err(|e| AppError::new_with_source("Cannot paint the stroke", Arc::new(e)))
+    }
+}
+
+impl Document {
+    /// Paint with the brush from the last position of the stroke to `pos` on the canvas
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    pub(crate) fn brush_to(&mut self, drawing: &DrawingMode, pos: Pos2) -> Result<(), AppError> {
+        let ink = Ink::Pen {
+            color: drawing.pen_color.map(|c| f32::from(c) / 255.0),
+            blend: drawing.drawing_blend,
+        };
+        self.stroke_to(drawing, ink, pos)
+    }
+
+    /// Erase with the brush from the last position of the stroke to `pos` on the canvas
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    pub(crate) fn erase_to(
+        &mut self,
+        drawing: &DrawingMode,
+        eraser: Eraser,
+        pos: Pos2,
+    ) -> Result<(), AppError> {
+        let background = eraser
+            .background
+            .to_srgba_unmultiplied()
+            .map(|c| f32::from(c) / 255.0);
+        self.stroke_to(drawing, Ink::Eraser { background }, pos)
+    }
+
+    /// Put dabs of ink from the last position of the stroke to `pos` on the canvas
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    fn stroke_to(&mut self, drawing: &DrawingMode, ink: Ink, pos: Pos2) -> Result<(), AppError> {
+        if self.selection.stroke.is_none() {
+            self.begin_stroke();
+            self.selection.stroke = Some(Stroke::new(self.layer()));
+        }
+        let Some(mut stroke) = self.selection.stroke.take() else {
+            return Ok(());
+        };
+        let spacing = (drawing.brush.spacing / 100.0 * drawing.pen_radius * 2.0).max(0.5);
+        let layer = &mut self.layers[self.active_layer];
+        let res = stroke
+            .dabs_to(pos, spacing, drawing.drawing_continuous_line)
+            .into_iter()
+            .try_for_each(|center| stroke.dab(layer, &self.selection, drawing, ink, center));
+        self.selection.stroke = Some(stroke);
+        self.texture = None;
+        res
+    }
+
+    /// End the brush stroke and record it in the history
+    pub(crate) fn end_brush_stroke(&mut self) {
+        self.selection.stroke = None;
+        self.end_stroke();
+    }
+}
+
+impl TarsierApp {
+    /// Eraser ui, with the brush settings
+    pub(crate) fn eraser_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        self.mode.drawing.show_brush(ui, document);
+        let color_type = document.layer().img.color();
+        if color_type.has_alpha() {
+            ui.label("Erases to transparency");
+            return;
+        }
+        ui.horizontal(|ui| {
+            ui.label("Background");
+            ui.color_edit_button_srgba(&mut self.mode.eraser.background);
+        })
+        .response
+        .on_hover_text("The layer has no alpha channel, the eraser paints this color");
+        if ui.button("Add an alpha channel").clicked() {
+            let alpha = ops::with_alpha(color_type);
+            if let Err(e) = document.apply_op(None, None, |img| ops::convert(img, alpha)) {
+                error_manager.add_error(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, Rgba32FImage};
+
+    /// Red channel of a pixel of the canvas
+    fn red(document: &Document, x: u32, y: u32) -> u8 {
+        document.img().to_rgb8().get_pixel(x, y)[0]
+    }
+
+    /// White 20x20 document
+    fn white_document() -> Document {
+        let img = RgbImage::from_pixel(20, 20, Rgb([255, 255, 255]));
+        Document::new("a.png".into(), DynamicImage::ImageRgb8(img))
+    }
+
+    /// Black pen with a hard brush, one dab per call
+    fn hard_pen(radius: f32) -> DrawingMode {
+        DrawingMode {
+            drawing_continuous_line: false,
+            pen_radius: radius,
+            brush: Brush {
+                hardness: 1.0,
+                ..Brush::default()
+            },
+            ..DrawingMode::default()
+        }
+    }
+
+    /// Brush with a tip and a hardness
+    fn brush(tip: BrushTip, hardness: f32) -> Brush {
+        Brush {
+            tip,
+            hardness,
+            ..Brush::default()
+        }
+    }
+
+    #[test]
+    fn hardness_falloff() {
+        let soft = brush(BrushTip::Round, 0.5);
+        let at = |brush: &Brush, distance: f32| brush.dab(Vec2::new(distance, 0.0), 10.0);
+        assert!((at(&soft, 0.0) - 1.0).abs() < 1e-6);
+        assert!((at(&soft, 4.0) - 1.0).abs() < 1e-6);
+        // halfway in the soft part
+        assert!((at(&soft, 7.5) - 0.5).abs() < 1e-6);
+        assert!(at(&soft, 10.5).abs() < 1e-6);
+        let mut last = 1.0;
+        for step in 0..=22 {
+            #[allow(clippy::cast_precision_loss)]
+            let paint = at(&soft, step as f32 * 0.5);
+            assert!(paint <= last, "the paint fades out");
+            last = paint;
+        }
+        let hard = brush(BrushTip::Round, 1.0);
+        assert!((at(&hard, 9.0) - 1.0).abs() < 1e-6);
+        // the edge pixel is partly covered
+        assert!((at(&hard, 10.2) - 0.3).abs() < 1e-5);
+        let softest = brush(BrushTip::Round, 0.0);
+        assert!((at(&softest, 5.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn opacity_caps_flow_build_up() {
+        let mut document = white_document();
+        let mut drawing = hard_pen(2.0);
+        drawing.brush.flow = 0.25;
+        drawing.brush.opacity = 0.5;
+        let center = Pos2::new(10.5, 10.5);
+        assert!(document.brush_to(&drawing, center).is_ok());
+        // one dab: a quarter of the opacity
+        assert_eq!(red(&document, 10, 10), 223);
+        assert!(document.brush_to(&drawing, center).is_ok());
+        // two dabs: 1 - 0.75^2 of the opacity
+        assert_eq!(red(&document, 10, 10), 199);
+        for _ in 0..40 {
+            assert!(document.brush_to(&drawing, center).is_ok());
+        }
+        // the paint builds up to the opacity, not more
+        assert_eq!(red(&document, 10, 10), 128);
+        document.end_brush_stroke();
+        // a new stroke builds up again over the first one
+        assert!(document.brush_to(&drawing, center).is_ok());
+        assert_eq!(red(&document, 10, 10), 112);
+    }
+
+    #[test]
+    fn dab_spacing() {
+        let layer = Layer::new("layer", DynamicImage::new_rgb8(1, 1));
+        let mut stroke = Stroke::new(&layer);
+        assert_eq!(stroke.dabs_to(Pos2::ZERO, 4.0, true), vec![Pos2::ZERO]);
+        assert_eq!(
+            stroke.dabs_to(Pos2::new(10.0, 0.0), 4.0, true),
+            vec![Pos2::new(4.0, 0.0), Pos2::new(8.0, 0.0)]
+        );
+        // the distance since the last dab is kept
+        assert_eq!(
+            stroke.dabs_to(Pos2::new(13.0, 0.0), 4.0, true),
+            vec![Pos2::new(12.0, 0.0)]
+        );
+        assert!(stroke.dabs_to(Pos2::new(13.0, 0.0), 4.0, true).is_empty());
+        // the dabs are placed between the samples, at sub-pixel positions
+        let mut stroke = Stroke::new(&layer);
+        assert_eq!(stroke.dabs_to(Pos2::new(13.0, 0.0), 0.5, true).len(), 1);
+        let dabs = stroke.dabs_to(Pos2::new(13.0, 1.5), 0.5, true);
+        assert_eq!(dabs.len(), 3);
+        for (dab, y) in dabs.iter().zip([0.5, 1.0, 1.5]) {
+            assert!((dab.x - 13.0).abs() < 1e-6 && (dab.y - y).abs() < 1e-6);
+        }
+        // a new segment starts with a dab
+        assert_eq!(
+            stroke.dabs_to(Pos2::new(1.0, 1.0), 4.0, false),
+            vec![Pos2::new(1.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn stroke_between_samples_has_no_gap() {
+        let mut document = white_document();
+        let drawing = DrawingMode {
+            drawing_continuous_line: true,
+            ..hard_pen(1.0)
+        };
+        assert!(document.brush_to(&drawing, Pos2::new(2.5, 5.5)).is_ok());
+        assert!(document.brush_to(&drawing, Pos2::new(17.5, 5.5)).is_ok());
+        document.end_brush_stroke();
+        for x in 2..=17 {
+            assert_eq!(red(&document, x, 5), 0, "pixel {x}");
+        }
+        assert_eq!(red(&document, 10, 8), 255);
+        assert_eq!(red(&document, 19, 5), 255);
+    }
+
+    #[test]
+    fn square_and_bitmap_tips() {
+        let corner = Vec2::new(3.0, 3.0);
+        assert!((brush(BrushTip::Square, 1.0).dab(corner, 4.0) - 1.0).abs() < 1e-6);
+        assert!(brush(BrushTip::Round, 1.0).dab(corner, 4.0) < 0.3);
+        let bitmap = TipBitmap {
+            width: 2,
+            height: 2,
+            pixels: vec![255, 0, 255, 0],
+        };
+        let mut tip = brush(BrushTip::Bitmap, 1.0);
+        // without bitmap, the tip is round
+        assert!((tip.dab(corner, 4.0) - brush(BrushTip::Round, 1.0).dab(corner, 4.0)).abs() < 1e-6);
+        tip.bitmap = Some(bitmap);
+        assert!((tip.dab(Vec2::new(-2.0, 0.0), 4.0) - 1.0).abs() < 1e-6);
+        assert!(tip.dab(Vec2::new(2.0, 0.0), 4.0).abs() < 1e-6);
+        assert!((tip.dab(Vec2::ZERO, 4.0) - 0.5).abs() < 1e-6);
+        let mut document = white_document();
+        let mut drawing = hard_pen(4.0);
+        drawing.brush = tip;
+        assert!(document.brush_to(&drawing, Pos2::new(10.0, 10.0)).is_ok());
+        // the left half of the tip paints, interpolated between the pixels of the bitmap
+        assert_eq!(red(&document, 8, 10), 32);
+        assert_eq!(red(&document, 12, 10), 255);
+    }
+
+    #[test]
+    fn stroke_on_rgb16() {
+        let img = image::ImageBuffer::from_pixel(20, 20, Rgb([u16::MAX; 3]));
+        let mut document = Document::new("a.png".into(), DynamicImage::ImageRgb16(img));
+        let mut drawing = hard_pen(2.0);
+        drawing.brush.opacity = 0.5;
+        assert!(document.brush_to(&drawing, Pos2::new(10.5, 10.5)).is_ok());
+        let DynamicImage::ImageRgb16(img) = document.img() else {
+            panic!("the layer stays 16-bit");
+        };
+        // half of the white, with the 16-bit precision
+        assert!(img.get_pixel(10, 10)[0].abs_diff(32768) <= 1);
+        assert_eq!(img.get_pixel(0, 0)[0], u16::MAX);
+    }
+
+    #[test]
+    fn stroke_on_rgba32f() {
+        let mut img = Rgba32FImage::from_pixel(20, 20, Rgba([0.0; 4]));
+        img.put_pixel(0, 0, Rgba([2.0, 2.0, 2.0, 1.0]));
+        let mut document = Document::new("a.exr".into(), DynamicImage::ImageRgba32F(img));
+        let mut drawing = hard_pen(2.0);
+        drawing.drawing_blend = true;
+        drawing.pen_color = [255, 0, 0, 255];
+        drawing.brush.opacity = 0.5;
+        assert!(document.brush_to(&drawing, Pos2::new(10.5, 10.5)).is_ok());
+        let DynamicImage::ImageRgba32F(img) = document.img() else {
+            panic!("the layer stays float");
+        };
+        let [red, green, blue, alpha] = img.get_pixel(10, 10).0;
+        assert!((red - 1.0).abs() < 1e-6 && green.abs() < 1e-6 && blue.abs() < 1e-6);
+        assert!((alpha - 0.5).abs() < 1e-6);
+        // the values out of 0..1 are kept out of the dab
+        assert_eq!(img.get_pixel(0, 0), &Rgba([2.0, 2.0, 2.0, 1.0]));
+    }
+
+    #[test]
+    fn undo_during_stroke_ends_it() {
+        let img = RgbImage::from_pixel(20, 40, Rgb([255, 255, 255]));
+        let mut document = Document::new("a.png".into(), DynamicImage::ImageRgb8(img));
+        let drawing = DrawingMode::default();
+        assert!(document.brush_to(&drawing, Pos2::new(10.0, 5.0)).is_ok());
+        assert_eq!(red(&document, 10, 5), 0);
+        document.undo();
+        assert_eq!(red(&document, 10, 5), 255);
+        // the drag goes on after the undo: it is a new stroke
+        assert!(document.brush_to(&drawing, Pos2::new(10.0, 35.0)).is_ok());
+        document.end_brush_stroke();
+        assert_eq!(red(&document, 10, 5), 255);
+        assert_eq!(red(&document, 10, 20), 255);
+        assert_eq!(red(&document, 10, 35), 0);
+        document.undo();
+        assert_eq!(red(&document, 10, 35), 255);
+        document.redo();
+        assert_eq!(red(&document, 10, 35), 0);
+        assert_eq!(red(&document, 10, 5), 255);
+    }
+}
@@ -0,0 +1,196 @@
+//! Paint bucket: fill the pixels of a similar color connected to the clicked one
+
+use bladvak::eframe::egui;
+use bladvak::errors::AppError;
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::document::Document;
+use crate::edit_mode::DrawingMode;
+use crate::magic_wand::{self, ColorDistance, Features};
+use crate::ops::{self, Region};
+use crate::shapes;
+
+/// Paint bucket settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Bucket {
+    /// Maximum color difference, in percent
+    pub(crate) tolerance: f32,
+    /// Also fill through the diagonal neighbours
+    pub(crate) eight_connected: bool,
+    /// Compare the colors of all the visible layers instead of the active one
+    pub(crate) sample_all_layers: bool,
+    /// Partly fill the pixels on the edge of the filled area
+    pub(crate) anti_alias: bool,
+}
+
+impl Default for Bucket {
+    fn default() -> Self {
+        Self {
+            tolerance: 15.0,
+            eight_connected: false,
+            sample_all_layers: false,
+            anti_alias: true,
+        }
+    }
+}
+
+impl Bucket {
+    /// Settings ui, with the pen color and pixel mode
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, drawing: &mut DrawingMode) {
+        drawing.show_color(ui);
+        ui.add(egui::Slider::new(&mut self.tolerance, 0.0..=100.0).text("Tolerance"));
+        ui.horizontal(|ui| {
+            ui.label("Neighbours:");
+            ui.selectable_value(&mut self.eight_connected, false, "4");
+            ui.selectable_value(&mut self.eight_connected, true, "8")
+                .on_hover_text("Also fill through the corners of the pixels");
+        });
+        ui.horizontal(|ui| {
+            ui.label("Sample:");
+            ui.selectable_value(&mut self.sample_all_layers, false, "Current layer");
+            ui.selectable_value(&mut self.sample_all_layers, true, "All layers");
+        });
+        ui.checkbox(&mut self.anti_alias, "Anti-aliasing")
+            .on_hover_text("Partly fill the pixels on the edge, depending on their color");
+    }
+
+    /// Coverage of the fill from the pixel at (`x`, `y`) of the canvas, from 0 to 255,
+    /// the filled pixels are also in the selection
+    fn coverage(self, document: &Document, x: u32, y: u32) -> GrayImage {
+        let (width, height) = document.size();
+        let layer = document.layer();
+        let sample = if self.sample_all_layers {
+            document.composite().to_rgba32f()
+        } else if layer.props.offset == (0, 0)
+            && (layer.img.width(), layer.img.height()) == (width, height)
+        {
+            layer.img.to_rgba32f()
+        } else {
+            let img = layer.img.to_rgba32f();
+            // the pixels outside of the layer are transparent
+            image::Rgba32FImage::from_fn(width, height, |x, y| {
+                layer
+                    .to_layer_pos(x, y)
+                    .map_or(image::Rgba([0.0; 4]), |(x, y)| *img.get_pixel(x, y))
+            })
+        };
+        let Some(seed) = sample.get_pixel_checked(x, y) else {
+            return GrayImage::new(width, height);
+        };
+        let seed = Features::new(ColorDistance::Rgb, seed.0);
+        let differences: Vec<f32> = sample
+            .pixels()
+            .map(|pixel| seed.distance(&Features::new(ColorDistance::Rgb, pixel.0)))
+            .collect();
+        let difference = |x: u32, y: u32| differences[y as usize * width as usize + x as usize];
+        let tolerance = self.tolerance / 100.0;
+        let selection = &document.selection;
+        let mut coverage =
+            magic_wand::flood_fill(width, height, (x, y), self.eight_connected, |x, y| {
+                difference(x, y) <= tolerance && selection.contains(x, y)
+            });
+        if self.anti_alias
+            && tolerance > 0.0
+            && let Some(filled) = filled_region(&coverage)
+        {
+            let is_filled = |coverage: &GrayImage, x: u32, y: u32| {
+                coverage
+                    .get_pixel_checked(x, y)
+                    .is_some_and(|p| p[0] == 255)
+            };
+            // the pixels around the filled ones, one pixel outside of the bounding box
+            let min = (filled.x.saturating_sub(1), filled.y.saturating_sub(1));
+            let max = (
+                (filled.x + filled.width).min(width - 1),
+                (filled.y + filled.height).min(height - 1),
+            );
+            let mut edges = Vec::new();
+            for y in min.1..=max.1 {
+                for x in min.0..=max.0 {
+                    if coverage.get_pixel(x, y)[0] > 0 || !selection.contains(x, y) {
+                        continue;
+                    }
+                    let next_to_fill = (x.saturating_sub(1)..=x + 1).any(|nx| {
+                        (y.saturating_sub(1)..=y + 1).any(|ny| is_filled(&coverage, nx, ny))
+                    });
+                    if next_to_fill {
+                        // fully filled at the tolerance, not at all at one and a half tolerance
+                        let partial = (3.0 - 2.0 * difference(x, y) / tolerance).clamp(0.0, 1.0);
+                        edges.push((x, y, partial));
+                    }
+                }
+            }
+            for (x, y, partial) in edges {
+                #[allow(clippy::cast_possible_truncation)]
+                #[allow(clippy::cast_sign_loss)]
+                coverage.put_pixel(x, y, Luma([(partial * 255.0).round() as u8]));
+            }
+        }
+        coverage
+    }
+}
+
+impl Document {
+    /// Fill the area around the pixel at (`x`, `y`) of the canvas with the pen color, in the active layer
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    pub(crate) fn bucket_fill(
+        &mut self,
+        bucket: Bucket,
+        drawing: &DrawingMode,
+        x: u32,
+        y: u32,
+    ) -> Result<(), AppError> {
+        let coverage = bucket.coverage(self, x, y);
+        let Some(region) = filled_region(&coverage) else {
+            return Ok(());
+        };
+        let mask = self.selection_mask(region);
+        let layer = self.layer();
+        let layer_region = layer.to_layer_region(region);
+        let origin = (
+            i64::from(layer_region.x) + i64::from(layer.props.offset.0),
+            i64::from(layer_region.y) + i64::from(layer.props.offset.1),
+        );
+        let color = drawing.pen_color.map(|c| f32::from(c) / 255.0);
+        let blend = drawing.drawing_blend;
+        self.apply_op(Some(region), mask.as_ref(), |before| {
+            let mut pixels = before.to_rgba32f();
+            for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+                let (Ok(x), Ok(y)) = (
+                    u32::try_from(origin.0 + i64::from(x)),
+                    u32::try_from(origin.1 + i64::from(y)),
+                ) else {
+                    continue;
+                };
+                let Some(covered) = coverage.get_pixel_checked(x, y) else {
+                    continue;
+                };
+                let covered = f32::from(covered[0]) / 255.0;
+                if blend {
+                    let [red, green, blue, alpha] = color;
+                    shapes::over(&mut pixel.0, [red, green, blue, alpha * covered]);
+                } else {
+                    for (channel, value) in pixel.0.iter_mut().zip(color) {
+                        *channel += (value - *channel) * covered;
+                    }
+                }
+            }
+            ops::convert(&DynamicImage::ImageRgba32F(pixels), before.color())
+        })
+    }
+}
+
+/// Bounding box of the covered pixels
+fn filled_region(coverage: &GrayImage) -> Option<Region> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in coverage.enumerate_pixels() {
+        if pixel[0] > 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (min_x <= max_x).then(|| Region::new(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+}
@@ -1,6 +1,7 @@
 //! Central panel
 use bladvak::eframe::egui::{
-    self, Color32, ColorImage, Id, Image, ImageData, Modal, Pos2, Sense, TextureOptions, Vec2,
+    self, Color32, ColorImage, Id, Image, ImageData, Key, KeyboardShortcut, Modal, Modifiers, Pos2,
+    Rect, Sense, TextureOptions, Vec2,
 };
 use bladvak::errors::ErrorManager;
 use image::DynamicImage;
@@ -8,16 +9,27 @@ use imageproc::drawing::Canvas;
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::brush::BrushTip;
+use crate::magic_wand::SelectionCombine;
+use crate::perspective::Quad;
+use crate::selection;
+use crate::shapes::ShapeKind;
 use crate::{TarsierApp, edit_mode::EditMode};
 
 impl TarsierApp {
+    /// Undo shortcut
+    const UNDO_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+    /// Redo shortcut
+    const REDO_SHORTCUT: KeyboardShortcut =
+        KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+
     /// Show the central panel
     #[allow(clippy::too_many_lines)]
     #[allow(clippy::cast_precision_loss)]
     pub(crate) fn app_central_panel(
         &mut self,
         ui: &mut egui::Ui,
-        _error_manager: &mut ErrorManager,
+        error_manager: &mut ErrorManager,
     ) {
         let Some(document) = self.documents.get_current_doc_mut() else {
             egui::Area::new("center".into())
@@ -30,6 +42,45 @@ impl TarsierApp {
                 });
             return;
         };
+        let (undo, redo) = ui.input_mut(|i| {
+            // check redo first since undo would also match with shift
+            let redo = i.consume_shortcut(&Self::REDO_SHORTCUT);
+            let undo = i.consume_shortcut(&Self::UNDO_SHORTCUT);
+            (undo, redo)
+        });
+        if redo {
+            document.redo();
+        } else if undo {
+            document.undo();
+        }
+        if document.floating.is_some() {
+            if document.selection.rectangle.is_none() {
+                document.floating = None;
+            } else if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter)) {
+                if let Err(e) = document.commit_floating() {
+                    error_manager.add_error(e);
+                }
+            } else if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape)) {
+                document.cancel_floating();
+            }
+        }
+        if self.mode.current == EditMode::Shape && !document.selection.shape.is_empty() {
+            if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Enter)) {
+                let points = std::mem::take(&mut document.selection.shape);
+                if let Err(e) = document.draw_shape(self.mode.shape, &points) {
+                    error_manager.add_error(e);
+                }
+            } else if ui.input_mut(|i| i.consume_key(Modifiers::NONE, Key::Escape)) {
+                document.selection.shape.clear();
+            }
+        }
+        if self.mode.current == EditMode::Selection && document.selection.rectangle.is_some() {
+            let delta = ui.input_mut(nudge_delta);
+            if delta != Vec2::ZERO {
+                let size = document.size();
+                document.selection.nudge(delta, size);
+            }
+        }
         let mut rect = document.scene_rect;
         egui::Scene::new()
             .zoom_range(0.0..=f32::INFINITY)
@@ -43,17 +94,21 @@ impl TarsierApp {
                     self.grid.draw(&bg_r.rect, painter);
                 }
 
-                let size = [document.img.width() as _, document.img.height() as _];
-                let image_texture = document.texture.get_or_insert_with(|| {
-                    let image_buffer = document.img.to_rgba8();
+                let (width, height) = document.size();
+                let size = [width as _, height as _];
+                if document.texture.is_none() {
+                    let image_buffer = document.composite().to_rgba8();
                     let pixels = image_buffer.as_flat_samples();
                     let image = ColorImage::from_rgba_unmultiplied(size, pixels.as_slice());
-                    ui.ctx().load_texture(
+                    document.texture = Some(ui.ctx().load_texture(
                         "img",
                         ImageData::Color(Arc::new(image)),
                         TextureOptions::default(),
-                    )
-                });
+                    ));
+                }
+                let Some(image_texture) = &document.texture else {
+                    return;
+                };
                 let response = ui.add(
                     Image::new((image_texture.id(), image_texture.size_vec2()))
                         .sense(Sense::click_and_drag()),
@@ -77,17 +132,53 @@ impl TarsierApp {
                 let ecart_x = img_position.min.x + viewport.min.x;
                 let ecart_y = img_position.min.y + viewport.min.y;
 
-                let painter = ui.painter();
-                if let EditMode::Drawing = self.mode.current
+                if self.mode.current == EditMode::Selection
                     && let Some(pos) = response.hover_pos()
+                    && let Some(cursor) = document.selection.cursor_at(
+                        pos - img_position.min.to_vec2(),
+                        selection::handle_radius(ui),
+                    )
+                {
+                    ui.ctx().set_cursor_icon(cursor);
+                }
+
+                if self.mode.current == EditMode::Perspective {
+                    let canvas = Rect::from_min_size(Pos2::ZERO, img_position.size());
+                    document
+                        .selection
+                        .quad
+                        .get_or_insert_with(|| {
+                            Quad::new(document.selection.rectangle.unwrap_or(canvas))
+                        })
+                        .paint(ui, img_position, self.settings.color_selection);
+                }
+
+                let painter = ui.painter();
+                if self.mode.current == EditMode::Straighten
+                    && let Some([start, end]) = document.selection.line
                 {
-                    painter.circle(
-                        pos,
-                        self.mode.drawing.pen_radius as f32,
-                        Color32::TRANSPARENT,
-                        egui::Stroke::new(1.0, Color32::BLACK),
+                    let offset = img_position.min.to_vec2();
+                    painter.line_segment(
+                        [start + offset, end + offset],
+                        egui::Stroke::new(2.0, self.settings.color_selection),
                     );
                 }
+                if let EditMode::Drawing | EditMode::Eraser = self.mode.current
+                    && let Some(pos) = response.hover_pos()
+                {
+                    let radius = self.mode.drawing.pen_radius;
+                    let stroke = egui::Stroke::new(1.0, Color32::BLACK);
+                    if self.mode.drawing.brush.tip == BrushTip::Square {
+                        painter.rect_stroke(
+                            Rect::from_center_size(pos, Vec2::splat(radius * 2.0)),
+                            0.0,
+                            stroke,
+                            egui::StrokeKind::Middle,
+                        );
+                    } else {
+                        painter.circle(pos, radius, Color32::TRANSPARENT, stroke);
+                    }
+                }
 
                 if response.dragged() {
                     if let Some(pos) = response.interact_pointer_pos() {
@@ -98,71 +189,205 @@ impl TarsierApp {
                             pos.y.round().clamp(0.0, size[1] as f32),
                         );
                         match self.mode.current {
-                            EditMode::Cursor | EditMode::ColorSelection => {
+                            EditMode::Cursor
+                            | EditMode::ColorSelection
+                            | EditMode::MagicWand
+                            | EditMode::Bucket => {
                                 // no nothing
                             }
                             EditMode::Selection => {
-                                document.selection.rectangle =
-                                    if let Some(_rect) = document.selection.rectangle {
-                                        Some(egui::Rect::from_two_pos(
-                                            document.selection.start_selection,
-                                            correct_pos,
-                                        ))
-                                    } else {
-                                        document.selection.start_selection = correct_pos;
-                                        Some(egui::Rect::from_two_pos(correct_pos, correct_pos))
-                                    };
+                                if response.drag_started() {
+                                    let start = (pos
+                                        - response.total_drag_delta().unwrap_or_default())
+                                    .round();
+                                    let radius = selection::handle_radius(ui);
+                                    // dragging outside of the floating selection commits it
+                                    if document.selection.cursor_at(start, radius).is_none()
+                                        && let Err(e) = document.commit_floating()
+                                    {
+                                        error_manager.add_error(e);
+                                    }
+                                    document.selection.drag_started(
+                                        self.mode.selection_shape,
+                                        start,
+                                        radius,
+                                    );
+                                }
+                                document.selection.drag(
+                                    self.mode.selection_shape,
+                                    correct_pos,
+                                    self.mode.selection_ratio.value(),
+                                    ui.input(|i| i.modifiers.shift),
+                                    (width, height),
+                                );
                             }
-                            EditMode::Drawing => {
-                                #[allow(clippy::cast_possible_truncation)]
-                                if self.mode.drawing.drawing_continuous_line
-                                    && let Some(last_post) = document.selection.last_drawing_point
-                                {
-                                    let pts = points_between(last_post, correct_pos);
-                                    for p in pts {
-                                        #[allow(clippy::cast_sign_loss)]
-                                        self.draw_point(p.x as u32, p.y as u32);
+                            EditMode::Straighten => {
+                                let line = document.selection.line.get_or_insert([pos; 2]);
+                                if response.drag_started() {
+                                    line[0] = (pos
+                                        - response.total_drag_delta().unwrap_or_default())
+                                    .round();
+                                }
+                                line[1] = correct_pos;
+                            }
+                            EditMode::Shape => {
+                                let shape = &mut document.selection.shape;
+                                if self.mode.shape.kind != ShapeKind::Polygon {
+                                    if response.drag_started() {
+                                        let start =
+                                            pos - response.total_drag_delta().unwrap_or_default();
+                                        *shape = vec![start];
+                                    }
+                                    if let Some(&start) = shape.first() {
+                                        let shift = ui.input(|i| i.modifiers.shift);
+                                        *shape = vec![
+                                            start,
+                                            self.mode.shape.end_point(start, pos, shift),
+                                        ];
                                     }
-                                } else {
-                                    #[allow(clippy::cast_sign_loss)]
-                                    self.draw_point(correct_pos.x as u32, correct_pos.y as u32);
                                 }
-                                if let Some(document) = self.documents.get_current_doc_mut() {
-                                    document.selection.last_drawing_point = Some(correct_pos);
+                            }
+                            EditMode::Text => {
+                                if let Some(text) = &mut document.selection.text {
+                                    if response.drag_started() {
+                                        let start =
+                                            pos - response.total_drag_delta().unwrap_or_default();
+                                        text.grab(start);
+                                    }
+                                    text.drag(pos);
+                                }
+                            }
+                            EditMode::Perspective => {
+                                if let Some(quad) = &mut document.selection.quad {
+                                    if response.drag_started() {
+                                        let start =
+                                            pos - response.total_drag_delta().unwrap_or_default();
+                                        quad.grab(start, selection::handle_radius(ui));
+                                    }
+                                    quad.drag(correct_pos);
+                                }
+                            }
+                            EditMode::Drawing => {
+                                if let Err(e) = document.brush_to(&self.mode.drawing, pos) {
+                                    error_manager.add_error(e);
+                                }
+                            }
+                            EditMode::Eraser => {
+                                if let Err(e) =
+                                    document.erase_to(&self.mode.drawing, self.mode.eraser, pos)
+                                {
+                                    error_manager.add_error(e);
                                 }
                             }
                         }
                     }
                 } else {
-                    document.selection.last_drawing_point = None;
+                    document.end_brush_stroke();
+                    if response.drag_stopped() && self.mode.current == EditMode::Selection {
+                        document
+                            .selection
+                            .drag_stopped(self.mode.selection_shape, (width, height));
+                    }
+                    if let Some(quad) = &mut document.selection.quad {
+                        quad.release();
+                    }
+                    if let Some(text) = &mut document.selection.text {
+                        text.release();
+                    }
+                    if response.drag_stopped()
+                        && self.mode.current == EditMode::Shape
+                        && self.mode.shape.kind != ShapeKind::Polygon
+                    {
+                        let points = std::mem::take(&mut document.selection.shape);
+                        if points.len() == 2
+                            && points[0] != points[1]
+                            && let Err(e) = document.draw_shape(self.mode.shape, &points)
+                        {
+                            error_manager.add_error(e);
+                        }
+                    }
+                }
+                if response.drag_stopped()
+                    && self.mode.current == EditMode::Straighten
+                    && let Some(document) = self.documents.get_current_doc_mut()
+                    && let Some(degrees) = document.selection.line.take().and_then(straighten_angle)
+                {
+                    let operation = self.image_operations.rotate.operation(degrees);
+                    self.apply_operation(&operation, error_manager);
                 }
                 let Some(document) = self.documents.get_current_doc_mut() else {
                     return;
                 };
 
                 if response.clicked() {
-                    // document.selection.rectangle = None;
                     match self.mode.current {
-                        EditMode::Cursor => {
+                        EditMode::Cursor | EditMode::Straighten | EditMode::Perspective => {
                             // do nothing
                         }
+                        EditMode::Shape => {
+                            if self.mode.shape.kind == ShapeKind::Polygon
+                                && let Some(pos) = response.interact_pointer_pos()
+                            {
+                                let pos = pos - img_position.min.to_vec2();
+                                let shape = &mut document.selection.shape;
+                                if response.double_clicked() {
+                                    let points = std::mem::take(shape);
+                                    if points.len() >= 3
+                                        && let Err(e) =
+                                            document.draw_shape(self.mode.shape, &points)
+                                    {
+                                        error_manager.add_error(e);
+                                    }
+                                } else {
+                                    let shift = ui.input(|i| i.modifiers.shift);
+                                    let pos = shape.last().map_or(pos, |&last| {
+                                        self.mode.shape.end_point(last, pos, shift)
+                                    });
+                                    shape.push(pos);
+                                }
+                            }
+                        }
+                        EditMode::Text => {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let pos = pos - img_position.min.to_vec2();
+                                if let Err(e) = self.place_text(ui, pos) {
+                                    error_manager.add_error(e);
+                                }
+                            }
+                        }
                         EditMode::Selection => {
-                            document.selection.rectangle = None;
+                            if let Err(e) = document.commit_floating() {
+                                error_manager.add_error(e);
+                            }
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let pos = pos - img_position.min.to_vec2();
+                                document.selection.click(
+                                    self.mode.selection_shape,
+                                    pos.clamp(Pos2::ZERO, Pos2::new(width as f32, height as f32)),
+                                    response.double_clicked(),
+                                    (width, height),
+                                );
+                            }
                         }
                         EditMode::Drawing => {
                             if let Some(pos) = response.interact_pointer_pos() {
                                 let pos = pos
                                     - Vec2::new(ecart_x - viewport.min.x, ecart_y - viewport.min.y);
-                                let correct_pos = Pos2::new(
-                                    pos.x.round().clamp(0.0, size[0] as f32),
-                                    pos.y.round().clamp(0.0, size[1] as f32),
-                                );
-                                #[allow(clippy::cast_possible_truncation)]
-                                #[allow(clippy::cast_sign_loss)]
-                                self.draw_point(correct_pos.x as u32, correct_pos.y as u32);
-                                if let Some(document) = self.documents.get_current_doc_mut() {
-                                    document.selection.last_drawing_point = None;
+                                if let Err(e) = document.brush_to(&self.mode.drawing, pos) {
+                                    error_manager.add_error(e);
                                 }
+                                document.end_brush_stroke();
+                            }
+                        }
+                        EditMode::Eraser => {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let pos = pos - img_position.min.to_vec2();
+                                if let Err(e) =
+                                    document.erase_to(&self.mode.drawing, self.mode.eraser, pos)
+                                {
+                                    error_manager.add_error(e);
+                                }
+                                document.end_brush_stroke();
                             }
                         }
                         #[allow(clippy::cast_possible_truncation)]
@@ -173,8 +398,9 @@ impl TarsierApp {
                             {
                                 let x = pos.x.floor() as u32;
                                 let y = pos.y.floor() as u32;
-                                if x < document.img.width() && y < document.img.height() {
-                                    let c = document.img.get_pixel(x, y);
+                                let (width, height) = document.size();
+                                if x < width && y < height {
+                                    let c = document.composite().get_pixel(x, y);
                                     self.mode.color_selection = (
                                         x,
                                         y,
@@ -183,66 +409,86 @@ impl TarsierApp {
                                 }
                             }
                         }
+                        #[allow(clippy::cast_possible_truncation)]
+                        #[allow(clippy::cast_sign_loss)]
+                        EditMode::Bucket => {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let pos = pos - img_position.min.to_vec2();
+                                if pos.x >= 0.0
+                                    && pos.y >= 0.0
+                                    && let Err(e) = document.bucket_fill(
+                                        self.mode.bucket,
+                                        &self.mode.drawing,
+                                        pos.x as u32,
+                                        pos.y as u32,
+                                    )
+                                {
+                                    error_manager.add_error(e);
+                                }
+                            }
+                        }
+                        #[allow(clippy::cast_possible_truncation)]
+                        #[allow(clippy::cast_sign_loss)]
+                        EditMode::MagicWand => {
+                            if let Some(pos) = response.interact_pointer_pos() {
+                                let pos = pos - img_position.min.to_vec2();
+                                if pos.x >= 0.0 && pos.y >= 0.0 {
+                                    let mask = self.mode.magic_wand.select(
+                                        &document.composite(),
+                                        pos.x as u32,
+                                        pos.y as u32,
+                                    );
+                                    let combine = SelectionCombine::from_modifiers(
+                                        ui.input(|i| i.modifiers),
+                                        self.mode.magic_wand.combine,
+                                    );
+                                    document.selection.combine(mask, combine);
+                                }
+                            }
+                        }
                     }
                 }
                 let Some(document) = self.documents.get_current_doc_mut() else {
                     return;
                 };
-                if let Some(selection) = document.selection.rectangle {
-                    let min_pos = img_position.min;
-                    let rect_selection = egui::Rect::from_two_pos(
-                        Pos2::new(
-                            selection.min.x - viewport.min.x + ecart_x,
-                            selection.min.y - viewport.min.y + ecart_y,
-                        ),
-                        Pos2::new(
-                            selection.max.x - viewport.min.x + ecart_x,
-                            selection.max.y - viewport.min.y + ecart_y,
-                        ),
-                    );
-                    painter.rect_stroke(
-                        rect_selection,
-                        0.0,
-                        egui::Stroke::new(1.0, Color32::BLACK),
-                        egui::StrokeKind::Middle,
-                    );
-                    // rect above selection
-                    painter.rect_filled(
-                        egui::Rect::from_min_max(
-                            Pos2::new(min_pos.x, min_pos.y),
-                            Pos2::new(size[0] as f32 + min_pos.x, rect_selection.min.y),
-                        ),
-                        0.0,
-                        self.settings.color_selection,
-                    );
-                    // rect below selection
-                    painter.rect_filled(
-                        egui::Rect::from_min_max(
-                            Pos2::new(min_pos.x, rect_selection.max.y),
-                            Pos2::new(size[0] as f32 + min_pos.x, size[1] as f32 + min_pos.y),
-                        ),
-                        0.0,
-                        self.settings.color_selection,
-                    );
-                    // rect left of selection
-                    painter.rect_filled(
-                        egui::Rect::from_min_max(
-                            Pos2::new(min_pos.x, rect_selection.min.y),
-                            Pos2::new(rect_selection.min.x, rect_selection.max.y),
-                        ),
-                        0.0,
-                        self.settings.color_selection,
-                    );
-                    // rect right of selection
-                    painter.rect_filled(
-                        egui::Rect::from_min_max(
-                            Pos2::new(rect_selection.max.x, rect_selection.min.y),
-                            Pos2::new(size[0] as f32 + min_pos.x, rect_selection.max.y),
-                        ),
-                        0.0,
+                self.preview
+                    .paint(ui, document, img_position, self.settings.color_selection);
+                if self.mode.current == EditMode::Shape && !document.selection.shape.is_empty() {
+                    let mut points = document.selection.shape.clone();
+                    // the polygon follows the pointer to its next vertex
+                    if self.mode.shape.kind == ShapeKind::Polygon
+                        && let (Some(pos), Some(&last)) = (response.hover_pos(), points.last())
+                    {
+                        let shift = ui.input(|i| i.modifiers.shift);
+                        let pos = pos - img_position.min.to_vec2();
+                        points.push(self.mode.shape.end_point(last, pos, shift));
+                    }
+                    self.mode.shape.paint(ui, &points, img_position);
+                }
+                if self.mode.current == EditMode::Text
+                    && let Some(text) = &document.selection.text
+                {
+                    self.fonts.paint(
+                        ui,
+                        &self.mode.text,
+                        text,
+                        img_position,
                         self.settings.color_selection,
                     );
                 }
+                if let Some(floating) = &mut document.floating
+                    && let Some(rect) = document.selection.rectangle
+                {
+                    floating.paint(ui, rect.translate(img_position.min.to_vec2()));
+                }
+                document.selection.paint(
+                    ui,
+                    self.mode.selection_shape,
+                    img_position,
+                    response.dragged(),
+                    self.mode.current == EditMode::Selection,
+                    self.settings.color_selection,
+                );
             });
         if let Some(document) = self.documents.get_current_doc_mut() {
             document.scene_rect = rect;
@@ -295,38 +541,40 @@ impl TarsierApp {
     }
 }
 
-/// Calculate all points between two points
-fn points_between(a: Pos2, b: Pos2) -> Vec<Pos2> {
-    let mut points = Vec::new();
-
-    let (mut x1, mut y1) = (a.x, a.y);
-    let (x2, y2) = (b.x, b.y);
-
-    let dx = (x2 - x1).abs();
-    let dy = -(y2 - y1).abs();
-    let sx = if x1 < x2 { 1.0 } else { -1.0 };
-    let sy = if y1 < y2 { 1.0 } else { -1.0 };
-    let mut err = dx + dy;
-
-    let error_margin = 0.01;
-    loop {
-        points.push(Pos2 { x: x1, y: y1 });
-
-        if (x1 - x2).abs() < error_margin && (y1 - y2).abs() < error_margin {
-            break;
-        }
-
-        let e2 = 2.0 * err;
+/// Clockwise rotation in degrees making a line horizontal, `None` for a line too short
+fn straighten_angle([start, end]: [Pos2; 2]) -> Option<f32> {
+    let delta = end - start;
+    if delta.length() < 2.0 {
+        return None;
+    }
+    // the direction of the line does not matter
+    let degrees = delta.y.atan2(delta.x).to_degrees();
+    let degrees = if degrees > 90.0 {
+        degrees - 180.0
+    } else if degrees <= -90.0 {
+        degrees + 180.0
+    } else {
+        degrees
+    };
+    Some(-degrees)
+}
 
-        if e2 >= dy {
-            err += dy;
-            x1 += sx;
-        }
-        if e2 <= dx {
-            err += dx;
-            y1 += sy;
+/// Move of the selection with the arrow keys, by 10 pixels with shift
+fn nudge_delta(input: &mut egui::InputState) -> Vec2 {
+    let arrows = [
+        (Key::ArrowLeft, Vec2::new(-1.0, 0.0)),
+        (Key::ArrowRight, Vec2::new(1.0, 0.0)),
+        (Key::ArrowUp, Vec2::new(0.0, -1.0)),
+        (Key::ArrowDown, Vec2::new(0.0, 1.0)),
+    ];
+    let mut delta = Vec2::ZERO;
+    for (key, direction) in arrows {
+        // check shift first since the plain key would also match with shift
+        if input.consume_key(Modifiers::SHIFT, key) {
+            delta += direction * 10.0;
+        } else if input.consume_key(Modifiers::NONE, key) {
+            delta += direction;
         }
     }
-
-    points
+    delta
 }
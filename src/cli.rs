@@ -0,0 +1,608 @@
+//! Headless batch processing
+
+use image::{ColorType, ImageFormat, ImageReader, imageops::FilterType};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::ops::{Anchor, Channel, Interpolation, Operation, ResizeMode, RotateFit};
+
+/// Usage of the batch command
+const USAGE: &str = "Usage: tarsier batch [OPTIONS] -o <DIR> <FILES>...
+
+Apply operations, in the given order, to every file and save the result in <DIR>.
+
+Options:
+  -o, --output <DIR>          Output directory
+  -f, --format <EXT>          Output format (default: same as the input file)
+      --resize <W>x<H>[:FILTER[:MODE]]
+                              Resize (FILTER: nearest, triangle, catmullrom, gaussian, lanczos3)
+                              (MODE: fit within the size, exact, fill and crop; default: fit)
+      --scale <PERCENT>[:FILTER]
+                              Scale by a percentage
+      --long-edge <SIZE>[:FILTER]
+                              Resize so that the longest edge has this size
+      --convert <COLOR>       Convert (l8, l16, la8, la16, rgb8, rgb16, rgb32f, rgba8, rgba16, rgba32f)
+      --grayscale             Grayscale
+      --invert                Invert colors
+      --blur <SIGMA>          Gaussian blur
+      --hue-rotate <DEG>      Hue rotation
+      --brighten <VALUE>      Brighten
+      --contrast <VALUE>      Contrast
+      --median <RADIUS>       Median filter
+      --cut-color <RRGGBB>[:TOLERANCE]
+                              Make transparent the pixels near a color
+      --sobel                 Sobel outline
+      --edge-detection        Edge detection
+      --channel <red|green|blue>
+                              Keep only one channel
+      --rotate90              Rotate 90 degrees clockwise
+      --rotate180             Rotate 180 degrees
+      --rotate270             Rotate 90 degrees counter-clockwise
+      --fliph                 Flip horizontally
+      --flipv                 Flip vertically
+      --rotate <DEG>[:FIT]    Rotate clockwise by any angle, bilinear, transparent fill
+                              (FIT: expand, crop, keep)
+      --canvas <W>x<H>[:ANCHOR]
+                              Change the canvas size, transparent fill
+                              (ANCHOR: top-left, top, top-right, left, center, right,
+                              bottom-left, bottom, bottom-right)
+      --trim <TOLERANCE>      Remove the borders of the color of the top left pixel
+      --overwrite             Allow replacing the input files
+  -h, --help                  Print help";
+
+/// Exit code on success
+const EXIT_SUCCESS: i32 = 0;
+/// Exit code when some files failed
+const EXIT_FILE_ERROR: i32 = 1;
+/// Exit code when the arguments are invalid
+const EXIT_USAGE_ERROR: i32 = 2;
+
+/// Batch settings
+#[derive(Debug, Default)]
+struct Batch {
+    /// Operations to apply
+    operations: Vec<Operation>,
+    /// Output directory
+    output: Option<PathBuf>,
+    /// Output format
+    format: Option<ImageFormat>,
+    /// Input files
+    files: Vec<PathBuf>,
+    /// Allow replacing the input files
+    overwrite: bool,
+}
+
+/// Run the batch command if the arguments ask for it
+///
+/// Return the exit code, or `None` when the gui must be launched
+#[must_use]
+pub fn run_from_args(args: &[String]) -> Option<i32> {
+    match args.get(1).map(String::as_str) {
+        Some("batch") => Some(run(&args[2..])),
+        _ => None,
+    }
+}
+
+/// Run the batch command
+fn run(args: &[String]) -> i32 {
+    let batch = match parse_args(args) {
+        Ok(Some(batch)) => batch,
+        Ok(None) => {
+            println!("{USAGE}");
+            return EXIT_SUCCESS;
+        }
+        Err(e) => {
+            eprintln!("error: {e}\n\n{USAGE}");
+            return EXIT_USAGE_ERROR;
+        }
+    };
+    let Some(output) = &batch.output else {
+        eprintln!("error: missing output directory\n\n{USAGE}");
+        return EXIT_USAGE_ERROR;
+    };
+    if batch.files.is_empty() {
+        eprintln!("error: no input file\n\n{USAGE}");
+        return EXIT_USAGE_ERROR;
+    }
+    if let Err(e) = std::fs::create_dir_all(output) {
+        eprintln!("error: cannot create '{}': {e}", output.display());
+        return EXIT_FILE_ERROR;
+    }
+    let mut failed = 0;
+    let mut written = HashSet::new();
+    let inputs: HashSet<PathBuf> = batch
+        .files
+        .iter()
+        .filter_map(|file| file.canonicalize().ok())
+        .collect();
+    for file in &batch.files {
+        let res = output_path(&batch, file, output).and_then(|(out_path, format)| {
+            if !written.insert(out_path.clone()) {
+                return Err(format!(
+                    "'{}' is already written by another file",
+                    out_path.display()
+                ));
+            }
+            if !batch.overwrite
+                && out_path
+                    .canonicalize()
+                    .is_ok_and(|path| inputs.contains(&path))
+            {
+                return Err(format!(
+                    "'{}' is an input file, use --overwrite to replace it",
+                    out_path.display()
+                ));
+            }
+            process_file(&batch, file, &out_path, format)?;
+            Ok(out_path)
+        });
+        match res {
+            Ok(out_path) => println!("{} -> {}", file.display(), out_path.display()),
+            Err(e) => {
+                eprintln!("{}: {e}", file.display());
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("{failed}/{} file(s) failed", batch.files.len());
+        EXIT_FILE_ERROR
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+/// Path and format of the result of a file in the output directory
+fn output_path(
+    batch: &Batch,
+    file: &Path,
+    output: &Path,
+) -> Result<(PathBuf, ImageFormat), String> {
+    let file_name = file.file_name().ok_or("invalid file name")?;
+    let mut out_path = output.join(file_name);
+    let format = match batch.format {
+        Some(format) => {
+            out_path.set_extension(format.extensions_str()[0]);
+            format
+        }
+        None => ImageFormat::from_path(file).map_err(|e| format!("unknown format: {e}"))?,
+    };
+    Ok((out_path, format))
+}
+
+/// Apply the operations on one file and save it to `out_path`
+fn process_file(
+    batch: &Batch,
+    file: &Path,
+    out_path: &Path,
+    format: ImageFormat,
+) -> Result<(), String> {
+    let mut img = ImageReader::open(file)
+        .map_err(|e| format!("cannot open file: {e}"))?
+        .with_guessed_format()
+        .map_err(|e| format!("cannot read file: {e}"))?
+        .decode()
+        .map_err(|e| format!("cannot decode image: {e}"))?;
+    for operation in &batch.operations {
+        img = operation.apply(&img);
+    }
+    img.save_with_format(out_path, format)
+        .map_err(|e| format!("cannot write image: {e}"))
+}
+
+/// Parse the arguments, `None` if the help is asked
+fn parse_args(args: &[String]) -> Result<Option<Batch>, String> {
+    let mut batch = Batch::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| {
+            iter.next()
+                .map(String::as_str)
+                .ok_or(format!("missing value for '{name}'"))
+        };
+        let operation = match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" | "--output" => {
+                batch.output = Some(PathBuf::from(value(arg)?));
+                continue;
+            }
+            "--overwrite" => {
+                batch.overwrite = true;
+                continue;
+            }
+            "-f" | "--format" => {
+                let ext = value(arg)?;
+                batch.format = Some(
+                    ImageFormat::from_extension(ext).ok_or(format!("unknown format '{ext}'"))?,
+                );
+                continue;
+            }
+            "--resize" => parse_resize(value(arg)?)?,
+            "--scale" => {
+                let (percent, filter) = parse_with_filter(value(arg)?)?;
+                Operation::Scale {
+                    percent: parse_number(arg, percent)?,
+                    filter,
+                }
+            }
+            "--long-edge" => {
+                let (size, filter) = parse_with_filter(value(arg)?)?;
+                Operation::LongEdge {
+                    size: parse_number(arg, size)?,
+                    filter,
+                }
+            }
+            "--convert" => Operation::Convert(parse_color_type(value(arg)?)?),
+            "--grayscale" => Operation::Grayscale,
+            "--invert" => Operation::Invert,
+            "--blur" => Operation::Blur(parse_number(arg, value(arg)?)?),
+            "--hue-rotate" => Operation::HueRotate(parse_number(arg, value(arg)?)?),
+            "--brighten" => Operation::Brighten(parse_number(arg, value(arg)?)?),
+            "--contrast" => Operation::Contrast(parse_number(arg, value(arg)?)?),
+            "--median" => Operation::MedianFilter(parse_number(arg, value(arg)?)?),
+            "--cut-color" => parse_cut_color(value(arg)?)?,
+            "--sobel" => Operation::SobelOutline,
+            "--edge-detection" => Operation::EdgeDetection,
+            "--channel" => Operation::Channel(match value(arg)? {
+                "red" => Channel::Red,
+                "green" => Channel::Green,
+                "blue" => Channel::Blue,
+                other => return Err(format!("unknown channel '{other}'")),
+            }),
+            "--rotate90" => Operation::Rotate90,
+            "--rotate180" => Operation::Rotate180,
+            "--rotate270" => Operation::Rotate270,
+            "--fliph" => Operation::FlipHorizontal,
+            "--flipv" => Operation::FlipVertical,
+            "--rotate" => parse_rotate(value(arg)?)?,
+            "--canvas" => parse_canvas(value(arg)?)?,
+            "--trim" => Operation::Trim {
+                tolerance: parse_number(arg, value(arg)?)?,
+            },
+            other if other.starts_with('-') => return Err(format!("unknown option '{other}'")),
+            file => {
+                batch.files.push(PathBuf::from(file));
+                continue;
+            }
+        };
+        batch.operations.push(operation);
+    }
+    Ok(Some(batch))
+}
+
+/// Parse a number argument
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{value}' for '{name}'"))
+}
+
+/// Parse `<VALUE>[:FILTER]`
+fn parse_with_filter(value: &str) -> Result<(&str, FilterType), String> {
+    match value.split_once(':') {
+        Some((value, filter)) => Ok((value, parse_filter_type(filter)?)),
+        None => Ok((value, FilterType::Lanczos3)),
+    }
+}
+
+/// Parse `<W>x<H>[:FILTER[:MODE]]`
+fn parse_resize(value: &str) -> Result<Operation, String> {
+    let mut parts = value.splitn(3, ':');
+    let size = parts.next().unwrap_or(value);
+    let filter = parts
+        .next()
+        .map(parse_filter_type)
+        .transpose()?
+        .unwrap_or(FilterType::Lanczos3);
+    let mode = match parts.next().map(str::to_lowercase).as_deref() {
+        None | Some("fit") => ResizeMode::Fit,
+        Some("exact") => ResizeMode::Exact,
+        Some("fill") => ResizeMode::Fill,
+        Some(mode) => return Err(format!("unknown resize mode '{mode}'")),
+    };
+    let (width, height) = size
+        .split_once('x')
+        .ok_or(format!("invalid size '{size}', expected <W>x<H>"))?;
+    Ok(Operation::Resize {
+        width: parse_number("--resize", width)?,
+        height: parse_number("--resize", height)?,
+        filter,
+        mode,
+    })
+}
+
+/// Parse `<DEG>[:FIT]`
+fn parse_rotate(value: &str) -> Result<Operation, String> {
+    let (degrees, fit) = match value.split_once(':') {
+        Some((degrees, fit)) => (degrees, fit),
+        None => (value, "expand"),
+    };
+    let fit = match fit.to_lowercase().as_str() {
+        "expand" => RotateFit::Expand,
+        "crop" => RotateFit::Crop,
+        "keep" => RotateFit::Keep,
+        _ => return Err(format!("unknown fit '{fit}'")),
+    };
+    Ok(Operation::Rotate {
+        degrees: parse_number("--rotate", degrees)?,
+        interpolation: Interpolation::Bilinear,
+        fit,
+        fill: [0; 4],
+    })
+}
+
+/// Parse `<W>x<H>[:ANCHOR]`
+fn parse_canvas(value: &str) -> Result<Operation, String> {
+    let (size, anchor) = match value.split_once(':') {
+        Some((size, anchor)) => (size, anchor),
+        None => (value, "center"),
+    };
+    let anchor = match anchor.to_lowercase().as_str() {
+        "top-left" => Anchor::TopLeft,
+        "top" => Anchor::Top,
+        "top-right" => Anchor::TopRight,
+        "left" => Anchor::Left,
+        "center" => Anchor::Center,
+        "right" => Anchor::Right,
+        "bottom-left" => Anchor::BottomLeft,
+        "bottom" => Anchor::Bottom,
+        "bottom-right" => Anchor::BottomRight,
+        _ => return Err(format!("unknown anchor '{anchor}'")),
+    };
+    let (width, height) = size
+        .split_once('x')
+        .ok_or(format!("invalid size '{size}', expected <W>x<H>"))?;
+    Ok(Operation::CanvasSize {
+        width: parse_number("--canvas", width)?,
+        height: parse_number("--canvas", height)?,
+        anchor,
+        fill: [0; 4],
+    })
+}
+
+/// Parse `<RRGGBB>[:TOLERANCE]`
+fn parse_cut_color(value: &str) -> Result<Operation, String> {
+    let (color, tolerance) = match value.split_once(':') {
+        Some((color, tolerance)) => (color, parse_number("--cut-color", tolerance)?),
+        None => (value, 0),
+    };
+    let color = color.trim_start_matches('#');
+    let hex = u32::from_str_radix(color, 16)
+        .ok()
+        .filter(|_| color.len() == 6)
+        .ok_or(format!("invalid color '{color}', expected RRGGBB"))?;
+    let [_, r, g, b] = hex.to_be_bytes();
+    Ok(Operation::CutColor {
+        color: [r, g, b],
+        tolerance,
+    })
+}
+
+/// Parse a filter type
+fn parse_filter_type(value: &str) -> Result<FilterType, String> {
+    match value.to_lowercase().as_str() {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" => Ok(FilterType::Triangle),
+        "catmullrom" => Ok(FilterType::CatmullRom),
+        "gaussian" => Ok(FilterType::Gaussian),
+        "lanczos3" => Ok(FilterType::Lanczos3),
+        _ => Err(format!("unknown filter '{value}'")),
+    }
+}
+
+/// Parse a color type
+fn parse_color_type(value: &str) -> Result<ColorType, String> {
+    match value.to_lowercase().as_str() {
+        "l8" => Ok(ColorType::L8),
+        "l16" => Ok(ColorType::L16),
+        "la8" => Ok(ColorType::La8),
+        "la16" => Ok(ColorType::La16),
+        "rgb8" => Ok(ColorType::Rgb8),
+        "rgb16" => Ok(ColorType::Rgb16),
+        "rgb32f" => Ok(ColorType::Rgb32F),
+        "rgba8" => Ok(ColorType::Rgba8),
+        "rgba16" => Ok(ColorType::Rgba16),
+        "rgba32f" => Ok(ColorType::Rgba32F),
+        _ => Err(format!("unknown color type '{value}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    /// Arguments from a command line
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    /// Empty temporary directory for a test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tarsier-cli-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        dir
+    }
+
+    /// Write a small png image
+    fn write_png(path: &Path) {
+        assert!(
+            path.parent()
+                .is_some_and(|dir| std::fs::create_dir_all(dir).is_ok())
+        );
+        let img = DynamicImage::ImageRgb8(RgbImage::new(4, 2));
+        assert!(img.save(path).is_ok());
+    }
+
+    #[test]
+    fn parse_args_in_order() {
+        let Ok(Some(batch)) = parse_args(&args(
+            "--invert -o out a.png --blur 1.5 -f jpg --rotate90 b.png",
+        )) else {
+            panic!("valid arguments");
+        };
+        assert_eq!(
+            batch.operations,
+            [Operation::Invert, Operation::Blur(1.5), Operation::Rotate90]
+        );
+        assert_eq!(batch.output, Some(PathBuf::from("out")));
+        assert_eq!(batch.format, Some(ImageFormat::Jpeg));
+        assert_eq!(
+            batch.files,
+            [PathBuf::from("a.png"), PathBuf::from("b.png")]
+        );
+    }
+
+    #[test]
+    fn parse_args_errors() {
+        assert!(matches!(parse_args(&args("a.png -h")), Ok(None)));
+        assert!(parse_args(&args("--unknown")).is_err());
+        assert!(parse_args(&args("-o")).is_err());
+        assert!(parse_args(&args("--blur big")).is_err());
+        assert!(parse_args(&args("-f xyz")).is_err());
+        assert!(parse_args(&args("--channel alpha")).is_err());
+        assert!(parse_args(&args("--convert rgb4")).is_err());
+    }
+
+    #[test]
+    fn parse_resize_options() {
+        assert_eq!(
+            parse_resize("100x50"),
+            Ok(Operation::Resize {
+                width: 100,
+                height: 50,
+                filter: FilterType::Lanczos3,
+                mode: ResizeMode::Fit,
+            })
+        );
+        assert_eq!(
+            parse_resize("10x20:nearest:fill"),
+            Ok(Operation::Resize {
+                width: 10,
+                height: 20,
+                filter: FilterType::Nearest,
+                mode: ResizeMode::Fill,
+            })
+        );
+        assert!(parse_resize("100").is_err());
+        assert!(parse_resize("100x50:box").is_err());
+        assert!(parse_resize("100x50:nearest:stretch").is_err());
+        assert!(parse_resize("-1x50").is_err());
+    }
+
+    #[test]
+    fn parse_canvas_options() {
+        assert_eq!(
+            parse_canvas("30x40"),
+            Ok(Operation::CanvasSize {
+                width: 30,
+                height: 40,
+                anchor: Anchor::Center,
+                fill: [0; 4],
+            })
+        );
+        assert_eq!(
+            parse_canvas("30x40:Bottom-Right"),
+            Ok(Operation::CanvasSize {
+                width: 30,
+                height: 40,
+                anchor: Anchor::BottomRight,
+                fill: [0; 4],
+            })
+        );
+        assert!(parse_canvas("30x40:middle").is_err());
+        assert!(parse_canvas("30*40").is_err());
+    }
+
+    #[test]
+    fn parse_cut_color_options() {
+        assert_eq!(
+            parse_cut_color("#ff8000:12"),
+            Ok(Operation::CutColor {
+                color: [255, 128, 0],
+                tolerance: 12,
+            })
+        );
+        assert_eq!(
+            parse_cut_color("00ff00"),
+            Ok(Operation::CutColor {
+                color: [0, 255, 0],
+                tolerance: 0,
+            })
+        );
+        assert!(parse_cut_color("fff").is_err());
+        assert!(parse_cut_color("gg0000").is_err());
+        assert!(parse_cut_color("ff0000:much").is_err());
+    }
+
+    #[test]
+    fn exit_codes() {
+        let dir = temp_dir("exit");
+        let input = dir.join("in.png");
+        write_png(&input);
+        let out = dir.join("out");
+        let run_line = |line: &str| run(&args(line));
+        assert_eq!(run_line("-h"), EXIT_SUCCESS);
+        assert_eq!(run_line("--unknown"), EXIT_USAGE_ERROR);
+        assert_eq!(
+            run_line(&format!("--invert {}", input.display())),
+            EXIT_USAGE_ERROR
+        );
+        assert_eq!(run_line(&format!("-o {}", out.display())), EXIT_USAGE_ERROR);
+        assert_eq!(
+            run_line(&format!("--fliph -o {} {}", out.display(), input.display())),
+            EXIT_SUCCESS
+        );
+        assert!(out.join("in.png").is_file());
+        assert_eq!(
+            run_line(&format!(
+                "-o {} {} {}",
+                out.display(),
+                input.display(),
+                dir.join("missing.png").display()
+            )),
+            EXIT_FILE_ERROR
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn same_output_name_fails() {
+        let dir = temp_dir("collision");
+        let (first, second) = (dir.join("a").join("img.png"), dir.join("b").join("img.png"));
+        write_png(&first);
+        write_png(&second);
+        let out = dir.join("out");
+        let line = format!(
+            "--rotate90 -o {} {} {}",
+            out.display(),
+            first.display(),
+            second.display()
+        );
+        assert_eq!(run(&args(&line)), EXIT_FILE_ERROR);
+        // the first file is kept
+        let written = ImageReader::open(out.join("img.png")).map(ImageReader::decode);
+        assert!(matches!(written, Ok(Ok(img)) if (img.width(), img.height()) == (2, 4)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn input_is_not_overwritten() {
+        let dir = temp_dir("overwrite");
+        let input = dir.join("img.png");
+        write_png(&input);
+        // the same directory, written differently
+        let out = dir.join(".");
+        let line = format!("--rotate90 -o {} {}", out.display(), input.display());
+        assert_eq!(run(&args(&line)), EXIT_FILE_ERROR);
+        let kept = ImageReader::open(&input).map(ImageReader::decode);
+        assert!(matches!(kept, Ok(Ok(img)) if (img.width(), img.height()) == (4, 2)));
+        // another format does not replace the input
+        assert_eq!(run(&args(&format!("{line} -f bmp"))), EXIT_SUCCESS);
+        assert_eq!(run(&args(&format!("{line} --overwrite"))), EXIT_SUCCESS);
+        let replaced = ImageReader::open(&input).map(ImageReader::decode);
+        assert!(matches!(replaced, Ok(Ok(img)) if (img.width(), img.height()) == (2, 4)));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,21 +1,28 @@
 //! Document
 
 use bladvak::eframe::egui;
+use bladvak::errors::AppError;
 use bladvak::utils::document::DocumentTrait;
-use image::DynamicImage;
+use image::{ColorType, DynamicImage, GenericImageView, GrayImage};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::edit_mode::SelectionState;
+use crate::floating::FloatingSelection;
+use crate::history::History;
+use crate::layer::{self, Layer, LayerProps};
+use crate::metadata;
+use crate::ops::{self, Operation, Region};
 
 /// Document for one image
 #[derive(serde::Deserialize, serde::Serialize)]
 pub(crate) struct Document {
-    /// Current image
-    #[serde(skip)]
-    pub(crate) img: DynamicImage,
-    /// Save of the image
-    #[serde(skip)]
-    pub(crate) saved_img: DynamicImage,
+    /// Layers, from bottom to top
+    pub(crate) layers: Vec<Layer>,
+    /// Index of the active layer
+    pub(crate) active_layer: usize,
+    /// Saved state of the layers
+    pub(crate) saved_layers: Vec<Layer>,
     /// Image texture
     #[serde(skip)]
     pub(crate) texture: Option<egui::TextureHandle>,
@@ -28,6 +35,12 @@ pub(crate) struct Document {
     pub(crate) selection: SelectionState,
     /// scene rect
     pub(crate) scene_rect: egui::Rect,
+    /// Undo/redo history
+    #[serde(skip)]
+    pub(crate) history: History,
+    /// Pasted pixels not yet committed to the active layer
+    #[serde(skip)]
+    pub(crate) floating: Option<FloatingSelection>,
 }
 
 impl std::fmt::Debug for Document {
@@ -35,6 +48,7 @@ impl std::fmt::Debug for Document {
         f.debug_struct("Document")
             .field("filename", &self.filename)
             .field("selection", &self.selection)
+            .field("layers", &self.layers.len())
             .finish_non_exhaustive()
     }
 }
@@ -42,13 +56,265 @@ impl std::fmt::Debug for Document {
 impl Default for Document {
     fn default() -> Self {
         Self {
-            img: DynamicImage::default(),
-            saved_img: DynamicImage::default(),
+            layers: vec![Layer::new("Background", DynamicImage::default())],
+            active_layer: 0,
+            saved_layers: Vec::new(),
             texture: None,
             exif: None,
             filename: PathBuf::new(),
             selection: SelectionState::default(),
             scene_rect: egui::Rect::NAN,
+            history: History::default(),
+            floating: None,
+        }
+    }
+}
+
+impl Document {
+    /// New document with one layer
+    pub(crate) fn new(filename: PathBuf, img: DynamicImage) -> Self {
+        let layers = vec![Layer::new("Background", img)];
+        Self {
+            saved_layers: layers.clone(),
+            layers,
+            filename,
+            ..Default::default()
+        }
+    }
+
+    /// Image of the active layer
+    pub(crate) fn img(&self) -> &DynamicImage {
+        &self.layers[self.active_layer].img
+    }
+
+    /// Mutable image of the active layer
+    pub(crate) fn img_mut(&mut self) -> &mut DynamicImage {
+        &mut self.layers[self.active_layer].img
+    }
+
+    /// Active layer
+    pub(crate) fn layer(&self) -> &Layer {
+        &self.layers[self.active_layer]
+    }
+
+    /// Size of the canvas, the size of the bottom layer
+    pub(crate) fn size(&self) -> (u32, u32) {
+        self.layers
+            .first()
+            .map_or((0, 0), |layer| layer.img.dimensions())
+    }
+
+    /// Color type of the canvas, the color type of the bottom layer
+    pub(crate) fn color_type(&self) -> ColorType {
+        self.layers
+            .first()
+            .map_or(ColorType::Rgba8, |layer| layer.img.color())
+    }
+
+    /// All the visible layers flattened
+    pub(crate) fn composite(&self) -> DynamicImage {
+        let (width, height) = self.size();
+        layer::composite(&self.layers, width, height, self.color_type())
+    }
+
+    /// Current selection as a region of the canvas
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn selection_region(&self) -> Option<Region> {
+        let canvas = &self.layers.first()?.img;
+        self.selection.rectangle.map(|selection| {
+            Region::new(
+                selection.min.x as u32,
+                selection.min.y as u32,
+                (selection.max.x - selection.min.x) as u32,
+                (selection.max.y - selection.min.y) as u32,
+            )
+            .clamp_to(canvas)
+        })
+    }
+
+    /// Mask of the selection in a region of the canvas, `None` if the selection is a rectangle
+    pub(crate) fn selection_mask(&self, region: Region) -> Option<GrayImage> {
+        self.selection.mask.as_ref().map(|mask| mask.crop(region))
+    }
+
+    /// Replace the image of the active layer
+    pub(crate) fn set_image(&mut self, new_img: DynamicImage) {
+        self.history.record(
+            self.active_layer,
+            &self.layers[self.active_layer].img,
+            &new_img,
+        );
+        *self.img_mut() = new_img;
+        self.texture = None;
+    }
+
+    /// Change the list of layers, keeping it in the history
+    ///
+    /// An empty list is ignored: a document always has a layer
+    pub(crate) fn set_layers(&mut self, layers: Vec<Layer>, active_layer: usize) {
+        if layers.is_empty() {
+            return;
+        }
+        let before = std::mem::replace(&mut self.layers, layers);
+        self.history.record_layers(before, &self.layers);
+        self.active_layer = active_layer.min(self.layers.len().saturating_sub(1));
+        self.texture = None;
+    }
+
+    /// Add a layer above the active one and make it active
+    pub(crate) fn add_layer(&mut self, new_layer: Layer) {
+        let mut layers = self.layers.clone();
+        let idx = (self.active_layer + 1).min(layers.len());
+        layers.insert(idx, new_layer);
+        self.set_layers(layers, idx);
+    }
+
+    /// Change the properties of a layer
+    pub(crate) fn set_layer_props(&mut self, idx: usize, props: LayerProps, merge: bool) {
+        let Some(layer) = self.layers.get_mut(idx) else {
+            return;
+        };
+        let before = std::mem::replace(&mut layer.props, props.clone());
+        self.history.record_props(idx, before, props, merge);
+        self.texture = None;
+    }
+
+    /// Crop all the layers to a region of the canvas
+    ///
+    /// With a selection mask, the region is its bounding box: the pixels outside of the shape are kept
+    pub(crate) fn crop(&mut self, region: Region) {
+        let mut layers = self.layers.clone();
+        for layer in &mut layers {
+            layer.crop_canvas(region);
+        }
+        self.set_layers(layers, self.active_layer);
+    }
+
+    /// Apply `func` on a region of the canvas, or on the whole image of the active layer
+    ///
+    /// With a mask of the region, only the selected pixels are changed
+    /// # Errors
+    /// Fails if the result does not fit in the image
+    pub(crate) fn apply_op<F>(
+        &mut self,
+        canvas_region: Option<Region>,
+        mask: Option<&GrayImage>,
+        func: F,
+    ) -> Result<(), AppError>
+    where
+        F: Fn(&DynamicImage) -> DynamicImage,
+    {
+        let Some(canvas_region) = canvas_region else {
+            let new_img = func(self.img());
+            self.set_image(new_img);
+            return Ok(());
+        };
+        let region = self.layer().to_layer_region(canvas_region);
+        let mask = mask.map(|mask| self.layer().crop_mask(mask, canvas_region, region));
+        let layer_idx = self.active_layer;
+        let img = &mut self.layers[layer_idx].img;
+        let before = ops::crop(img, region);
+        let mut inner = func(&before);
+        // keep the unselected pixels, unless the operation changed the size (e.g. rotation)
+        if let Some(mask) = mask
+            && inner.dimensions() == before.dimensions()
+        {
+            let mut merged = ops::convert(&before, inner.color());
+            ops::merge_masked(&mut merged, &inner, &mask).map_err(|e| {
+                AppError::new_with_source("Cannot apply the selection mask", Arc::new(e))
+            })?;
+            inner = merged;
+        }
+        // the result may be bigger than the region (e.g. rotation)
+        let changed = region
+            .union(Region::new(
+                region.x,
+                region.y,
+                inner.width(),
+                inner.height(),
+            ))
+            .clamp_to(img);
+        let full_before = (inner.color() != img.color()).then(|| {
+            let before = img.clone();
+            *img = ops::convert(img, inner.color());
+            before
+        });
+        let region_before = full_before.is_none().then(|| ops::crop(img, changed));
+        let res = ops::paste(img, &inner, region.x, region.y).map_err(|e| {
+            AppError::new_with_source("Cannot update selected image part", Arc::new(e))
+        });
+        if let Some(before) = full_before {
+            self.history.record(layer_idx, &before, img);
+        } else if let Some(region_before) = region_before {
+            let region_after = ops::crop(img, changed);
+            self.history.record_region(
+                layer_idx,
+                (changed.x, changed.y),
+                region_before,
+                region_after,
+            );
+        }
+        self.texture = None;
+        res
+    }
+
+    /// Apply an operation on a region of the canvas, or on the whole image of the active layer
+    ///
+    /// Without region, the rotations and flips turn the whole canvas: all the layers and the exif orientation
+    /// # Errors
+    /// Fails if the result does not fit in the image, or if the exif cannot be updated
+    pub(crate) fn apply_operation(
+        &mut self,
+        region: Option<Region>,
+        mask: Option<&GrayImage>,
+        operation: &Operation,
+    ) -> Result<(), AppError> {
+        let size = self.size();
+        let oriented = region.is_none().then(|| {
+            self.layers
+                .iter()
+                .map(|layer| layer.oriented(operation, size))
+                .collect::<Option<Vec<_>>>()
+        });
+        let Some(Some(layers)) = oriented else {
+            return self.apply_op(region, mask, |img| operation.apply(img));
+        };
+        self.set_layers(layers, self.active_layer);
+        if let Some(exif) = &self.exif
+            && let Some(new_exif) = metadata::after_operation(exif, operation)?
+        {
+            self.exif = Some(new_exif);
+        }
+        Ok(())
+    }
+
+    /// Start a stroke on the active layer
+    pub(crate) fn begin_stroke(&mut self) {
+        self.history
+            .begin_stroke(self.active_layer, &self.layers[self.active_layer].img);
+    }
+
+    /// End the current stroke
+    pub(crate) fn end_stroke(&mut self) {
+        self.history.end_stroke(&self.layers);
+    }
+
+    /// Undo the last change, the current brush stroke ends there
+    pub(crate) fn undo(&mut self) {
+        self.selection.stroke = None;
+        if self.history.undo(&mut self.layers) {
+            self.active_layer = self.active_layer.min(self.layers.len().saturating_sub(1));
+            self.texture = None;
+        }
+    }
+
+    /// Redo the last undone change, the current brush stroke ends there
+    pub(crate) fn redo(&mut self) {
+        self.selection.stroke = None;
+        if self.history.redo(&mut self.layers) {
+            self.active_layer = self.active_layer.min(self.layers.len().saturating_sub(1));
+            self.texture = None;
         }
     }
 }
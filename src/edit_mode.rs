@@ -2,18 +2,30 @@
 
 use bladvak::eframe::egui::{self, Color32};
 
+use crate::brush::{Brush, Eraser, Stroke};
+use crate::bucket::Bucket;
+use crate::document::Document;
+use crate::magic_wand::MagicWand;
+use crate::perspective::{Perspective, Quad};
+use crate::selection::{Grab, SelectionMask};
+use crate::shapes::ShapeTool;
+use crate::text::{TextBox, TextTool};
+
 /// Drawing mode
-#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
 pub(crate) struct DrawingMode {
     /// Drawing mode
     pub drawing_blend: bool,
     /// Continuous line when drawing when dragged
     pub drawing_continuous_line: bool,
     /// Others settings
-    /// Pen radius
-    pub pen_radius: u32,
+    /// Pen radius, in pixels
+    pub pen_radius: f32,
     /// Pen color
     pub(crate) pen_color: [u8; 4],
+    /// Brush settings
+    #[serde(default)]
+    pub(crate) brush: Brush,
 }
 
 impl Default for DrawingMode {
@@ -21,17 +33,37 @@ impl Default for DrawingMode {
         Self {
             drawing_blend: false,
             drawing_continuous_line: true,
-            pen_radius: 1,
+            pen_radius: 4.0,
             pen_color: [0, 0, 0, 255],
+            brush: Brush::default(),
         }
     }
 }
 
 impl DrawingMode {
     /// Button to draw settings
-    pub(crate) fn show(&mut self, ui: &mut egui::Ui, max_radius: u32) {
-        ui.add(egui::Slider::new(&mut self.pen_radius, 1..=max_radius / 4))
-            .on_hover_text("Pen radius");
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, document: &Document) {
+        self.show_brush(ui, document);
+        self.show_color(ui);
+    }
+
+    /// Button to choose the radius and the brush
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn show_brush(&mut self, ui: &mut egui::Ui, document: &Document) {
+        let (width, height) = document.size();
+        let max_radius = (width.max(height) as f32 / 2.0).max(1.0);
+        ui.add(
+            egui::Slider::new(&mut self.pen_radius, 0.5..=max_radius)
+                .logarithmic(true)
+                .suffix(" px"),
+        )
+        .on_hover_text("Pen radius");
+        self.brush.show(ui, document);
+        ui.checkbox(&mut self.drawing_continuous_line, "Continuous line");
+    }
+
+    /// Button to choose the pen color and the pixel mode
+    pub(crate) fn show_color(&mut self, ui: &mut egui::Ui) {
         let [r, g, b, a] = self.pen_color;
         let mut color = egui::Color32::from_rgba_premultiplied(r, g, b, a);
         egui::color_picker::color_edit_button_srgba(
@@ -53,7 +85,6 @@ impl DrawingMode {
                 self.drawing_blend = true;
             }
         });
-        ui.checkbox(&mut self.drawing_continuous_line, "Continuous line");
     }
 }
 
@@ -66,6 +97,30 @@ pub(crate) struct Mode {
     pub(crate) drawing: DrawingMode,
     /// color selection
     pub(crate) color_selection: (u32, u32, Color32),
+    /// Shape of the new selections
+    #[serde(default)]
+    pub(crate) selection_shape: SelectionShape,
+    /// Aspect ratio of the new selections
+    #[serde(default)]
+    pub(crate) selection_ratio: AspectRatio,
+    /// Magic wand settings
+    #[serde(default)]
+    pub(crate) magic_wand: MagicWand,
+    /// Perspective correction settings
+    #[serde(default)]
+    pub(crate) perspective: Perspective,
+    /// Shape tool settings
+    #[serde(default)]
+    pub(crate) shape: ShapeTool,
+    /// Text tool settings
+    #[serde(default)]
+    pub(crate) text: TextTool,
+    /// Paint bucket settings
+    #[serde(default)]
+    pub(crate) bucket: Bucket,
+    /// Eraser settings
+    #[serde(default)]
+    pub(crate) eraser: Eraser,
 }
 
 impl Default for Mode {
@@ -74,6 +129,131 @@ impl Default for Mode {
             current: EditMode::Cursor,
             drawing: DrawingMode::default(),
             color_selection: (0, 0, Color32::ORANGE),
+            selection_shape: SelectionShape::default(),
+            selection_ratio: AspectRatio::default(),
+            magic_wand: MagicWand::default(),
+            perspective: Perspective::default(),
+            shape: ShapeTool::default(),
+            text: TextTool::default(),
+            bucket: Bucket::default(),
+            eraser: Eraser::default(),
+        }
+    }
+}
+
+/// Shape of a selection
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum SelectionShape {
+    /// Rectangle, dragged from a corner to the other
+    #[default]
+    Rectangle,
+    /// Ellipse inside the dragged rectangle
+    Ellipse,
+    /// Polygon, one click per vertex
+    Polygon,
+    /// Freehand lasso, dragged
+    Lasso,
+}
+
+impl SelectionShape {
+    /// All the shapes
+    pub(crate) const ALL: [Self; 4] = [Self::Rectangle, Self::Ellipse, Self::Polygon, Self::Lasso];
+}
+
+impl std::fmt::Display for SelectionShape {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rectangle => write!(f, "Rectangle"),
+            Self::Ellipse => write!(f, "Ellipse"),
+            Self::Polygon => write!(f, "Polygon"),
+            Self::Lasso => write!(f, "Lasso"),
+        }
+    }
+}
+
+/// Aspect ratio of a selection
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum AspectRatio {
+    /// Any ratio
+    #[default]
+    Free,
+    /// 1:1
+    Square,
+    /// 4:3
+    Standard,
+    /// 16:9
+    Wide,
+    /// Ratio chosen by the user
+    Custom {
+        /// Width part of the ratio
+        width: u32,
+        /// Height part of the ratio
+        height: u32,
+    },
+}
+
+impl AspectRatio {
+    /// The presets, with a 3:2 custom ratio
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Free,
+        Self::Square,
+        Self::Standard,
+        Self::Wide,
+        Self::Custom {
+            width: 3,
+            height: 2,
+        },
+    ];
+
+    /// Width divided by height, `None` for a free ratio
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn value(self) -> Option<f32> {
+        match self {
+            Self::Free => None,
+            Self::Square => Some(1.0),
+            Self::Standard => Some(4.0 / 3.0),
+            Self::Wide => Some(16.0 / 9.0),
+            Self::Custom { width, height } => {
+                (width > 0 && height > 0).then(|| width as f32 / height as f32)
+            }
+        }
+    }
+
+    /// Ratio selector, with the parts of a custom ratio
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Ratio:");
+            egui::ComboBox::from_id_salt("selection_ratio")
+                .selected_text(self.to_string())
+                .show_ui(ui, |ui| {
+                    for preset in Self::ALL {
+                        let selected =
+                            std::mem::discriminant(self) == std::mem::discriminant(&preset);
+                        if ui.selectable_label(selected, preset.to_string()).clicked() && !selected
+                        {
+                            *self = preset;
+                        }
+                    }
+                });
+            if let Self::Custom { width, height } = self {
+                ui.add(egui::DragValue::new(width).range(1..=100));
+                ui.label(":");
+                ui.add(egui::DragValue::new(height).range(1..=100));
+            }
+        })
+        .response
+        .on_hover_text("Shift keeps the ratio while dragging, the arrow keys move the selection");
+    }
+}
+
+impl std::fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Free => write!(f, "Free"),
+            Self::Square => write!(f, "1:1"),
+            Self::Standard => write!(f, "4:3"),
+            Self::Wide => write!(f, "16:9"),
+            Self::Custom { .. } => write!(f, "Custom"),
         }
     }
 }
@@ -89,6 +269,37 @@ pub(crate) enum EditMode {
     Drawing,
     /// Color selection
     ColorSelection,
+    /// Select the pixels of a similar color
+    MagicWand,
+    /// Draw a line that should be horizontal to rotate the image
+    Straighten,
+    /// Drag four corners to correct the perspective
+    Perspective,
+    /// Draw lines, rectangles, ellipses, polygons and arrows
+    Shape,
+    /// Place text boxes
+    Text,
+    /// Fill the area around the clicked pixel
+    Bucket,
+    /// Erase with the brush
+    Eraser,
+}
+
+impl EditMode {
+    /// All the modes, in the order of the menu
+    pub(crate) const ALL: [Self; 11] = [
+        Self::Cursor,
+        Self::Selection,
+        Self::Drawing,
+        Self::Eraser,
+        Self::Bucket,
+        Self::ColorSelection,
+        Self::MagicWand,
+        Self::Straighten,
+        Self::Perspective,
+        Self::Shape,
+        Self::Text,
+    ];
 }
 
 impl std::fmt::Display for EditMode {
@@ -98,6 +309,13 @@ impl std::fmt::Display for EditMode {
             EditMode::Selection => write!(f, "Selection"),
             EditMode::Drawing => write!(f, "Drawing"),
             EditMode::ColorSelection => write!(f, "Color selection"),
+            EditMode::MagicWand => write!(f, "Magic wand"),
+            EditMode::Straighten => write!(f, "Straighten"),
+            EditMode::Perspective => write!(f, "Perspective"),
+            EditMode::Shape => write!(f, "Shapes"),
+            EditMode::Text => write!(f, "Text"),
+            EditMode::Bucket => write!(f, "Paint bucket"),
+            EditMode::Eraser => write!(f, "Eraser"),
         }
     }
 }
@@ -105,17 +323,45 @@ impl std::fmt::Display for EditMode {
 /// Cursor state
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
 pub struct SelectionState {
-    /// Selection rectangle
+    /// Selection rectangle, the bounding box of the mask if any
     #[serde(skip)]
     pub rectangle: Option<egui::Rect>,
 
+    /// Selected pixels, when the selection is not the whole rectangle
+    #[serde(skip)]
+    pub(crate) mask: Option<SelectionMask>,
+
+    /// Vertices of the polygon or lasso being drawn
+    #[serde(skip)]
+    pub(crate) path: Vec<egui::Pos2>,
+
+    /// Handle of the selection being dragged
+    #[serde(skip)]
+    pub(crate) grab: Option<Grab>,
+
+    /// Line drawn to straighten the image
+    #[serde(skip)]
+    pub(crate) line: Option<[egui::Pos2; 2]>,
+
+    /// Corners of the perspective correction
+    #[serde(skip)]
+    pub(crate) quad: Option<Quad>,
+
+    /// Points of the shape being drawn
+    #[serde(skip)]
+    pub(crate) shape: Vec<egui::Pos2>,
+
+    /// Text box being edited
+    #[serde(skip)]
+    pub(crate) text: Option<TextBox>,
+
     /// Start selection position
     #[serde(skip)]
     pub start_selection: egui::Pos2,
 
-    /// Start selection position
+    /// Brush stroke being painted
     #[serde(skip)]
-    pub last_drawing_point: Option<egui::Pos2>,
+    pub(crate) stroke: Option<Stroke>,
 
     /// Selection as windows
     pub cursor_op_as_window: bool,
@@ -128,9 +374,16 @@ impl Default for SelectionState {
     fn default() -> Self {
         Self {
             rectangle: None,
+            mask: None,
+            path: Vec::new(),
+            grab: None,
+            line: None,
+            quad: None,
+            shape: Vec::new(),
+            text: None,
             cursor_op_as_window: false,
             start_selection: egui::Pos2::ZERO,
-            last_drawing_point: None,
+            stroke: None,
             remove_selection_after_op: false,
         }
     }
@@ -0,0 +1,156 @@
+//! Exif editor of the image infos panel
+
+use bladvak::eframe::egui::{self, Color32};
+use bladvak::egui_extras::{Column, TableBuilder};
+use exif::{Exif, Tag};
+
+use crate::document::Document;
+use crate::metadata::{self, ADDABLE_TAGS, TagType};
+
+/// State of the exif editor
+#[derive(Debug, Default)]
+pub(crate) struct ExifEditor {
+    /// Tag being edited, with its value as text
+    editing: Option<(Tag, String)>,
+    /// Index in [`ADDABLE_TAGS`] of the tag to add
+    new_tag: usize,
+    /// Value of the tag to add
+    new_value: String,
+    /// Error of the last change
+    error: Option<String>,
+}
+
+/// Table of the exif fields, with edit and delete buttons
+fn exif_table(
+    ui: &mut egui::Ui,
+    exif: &Exif,
+    editor: &mut ExifEditor,
+) -> Option<Result<Option<Exif>, String>> {
+    let mut update = None;
+    TableBuilder::new(ui)
+        .max_scroll_height(200.0)
+        .striped(true)
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::remainder())
+        .column(Column::auto())
+        .header(20.0, |mut header| {
+            header.col(|ui| {
+                ui.label("Exif tag");
+            });
+            header.col(|ui| {
+                ui.label("IFD idx");
+            });
+            header.col(|ui| {
+                ui.label("exif value");
+            });
+            header.col(|_ui| {});
+        })
+        .body(|mut body| {
+            for field in exif.fields() {
+                let tag_type = TagType::of(field);
+                body.row(30.0, |mut row| {
+                    row.col(|ui| {
+                        ui.label(format!("{}", field.tag));
+                    });
+                    row.col(|ui| {
+                        ui.label(format!("{}", field.ifd_num));
+                    });
+                    let editing = match (&mut editor.editing, &tag_type) {
+                        (Some((tag, text)), Some(tag_type)) if *tag == field.tag => {
+                            Some((tag_type, text))
+                        }
+                        _ => None,
+                    };
+                    let is_editing = editing.is_some();
+                    row.col(|ui| {
+                        if let Some((tag_type, text)) = editing {
+                            ui.text_edit_singleline(text)
+                                .on_hover_text(tag_type.kind.hint());
+                        } else {
+                            ui.label(format!("{}", field.display_value().with_unit(exif)));
+                        }
+                    });
+                    row.col(|ui| {
+                        let Some(tag_type) = &tag_type else {
+                            return;
+                        };
+                        if is_editing {
+                            if ui.small_button("✔").on_hover_text("Apply").clicked()
+                                && let Some((_, text)) = &editor.editing
+                            {
+                                update = Some(tag_type.parse(text).and_then(|new_field| {
+                                    metadata::with_field(Some(exif), new_field).map(Some)
+                                }));
+                            }
+                            if ui.small_button("✖").on_hover_text("Cancel").clicked() {
+                                editor.editing = None;
+                            }
+                        } else {
+                            if ui.small_button("✏").on_hover_text("Edit").clicked() {
+                                editor.editing =
+                                    Some((field.tag, metadata::value_text(&field.value)));
+                            }
+                            if ui.small_button("🗑").on_hover_text("Delete").clicked() {
+                                update = Some(metadata::without_fields(exif, |other| {
+                                    other.tag == field.tag
+                                }));
+                            }
+                        }
+                    });
+                });
+            }
+        });
+    update
+}
+
+/// Exif editor: edit, add and delete fields
+pub(crate) fn exif_ui(ui: &mut egui::Ui, document: &mut Document, editor: &mut ExifEditor) {
+    let mut update = None;
+    match &document.exif {
+        Some(exif) => {
+            update = exif_table(ui, exif, editor);
+            if exif.fields().any(metadata::is_gps)
+                && ui
+                    .button("Remove GPS")
+                    .on_hover_text("Remove the location of the image")
+                    .clicked()
+            {
+                update = Some(metadata::without_fields(exif, metadata::is_gps));
+            }
+        }
+        None => {
+            ui.label("No exif detected");
+        }
+    }
+    ui.separator();
+    ui.horizontal(|ui| {
+        let selected = ADDABLE_TAGS.get(editor.new_tag).unwrap_or(&ADDABLE_TAGS[0]);
+        egui::ComboBox::from_id_salt("exif_new_tag")
+            .selected_text(format!("{}", selected.tag))
+            .show_ui(ui, |ui| {
+                for (idx, tag_type) in ADDABLE_TAGS.iter().enumerate() {
+                    ui.selectable_value(&mut editor.new_tag, idx, format!("{}", tag_type.tag));
+                }
+            });
+        ui.add(egui::TextEdit::singleline(&mut editor.new_value).hint_text(selected.kind.hint()));
+        if ui.button("Add").clicked() {
+            update = Some(selected.parse(&editor.new_value).and_then(|new_field| {
+                metadata::with_field(document.exif.as_ref(), new_field).map(Some)
+            }));
+        }
+    });
+    match update {
+        Some(Ok(exif)) => {
+            document.exif = exif;
+            editor.editing = None;
+            editor.new_value.clear();
+            editor.error = None;
+        }
+        Some(Err(e)) => editor.error = Some(e),
+        None => {}
+    }
+    if let Some(error) = &editor.error {
+        ui.colored_label(Color32::RED, error);
+    }
+}
@@ -0,0 +1,625 @@
+//! Export dialog: encoder options and estimated size
+
+use bladvak::eframe::egui::{self, Color32, Id, Modal};
+use bladvak::errors::ErrorManager;
+use image::codecs::{
+    avif::AvifEncoder,
+    gif::GifEncoder,
+    jpeg::JpegEncoder,
+    png::{self, PngEncoder},
+    webp::WebPEncoder,
+};
+use image::{
+    ColorType, DynamicImage, GenericImageView, ImageEncoder, ImageError, ImageFormat, ImageResult,
+};
+use std::io::Cursor;
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::formats::SaveFormat;
+use crate::metadata;
+use crate::recording::MacroStep;
+
+/// PNG compression level
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum PngCompression {
+    /// No compression
+    Uncompressed,
+    /// Fast, minimal compression
+    #[default]
+    Fast,
+    /// Default compression level
+    Default,
+    /// High compression level
+    Best,
+}
+
+impl PngCompression {
+    /// All compression levels
+    const ALL: [Self; 4] = [Self::Uncompressed, Self::Fast, Self::Default, Self::Best];
+}
+
+impl From<PngCompression> for png::CompressionType {
+    fn from(value: PngCompression) -> Self {
+        match value {
+            PngCompression::Uncompressed => Self::Uncompressed,
+            PngCompression::Fast => Self::Fast,
+            PngCompression::Default => Self::Default,
+            PngCompression::Best => Self::Best,
+        }
+    }
+}
+
+/// PNG filter
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum PngFilter {
+    /// No filter
+    NoFilter,
+    /// Difference with the previous pixel
+    Sub,
+    /// Difference with the pixel above
+    Up,
+    /// Difference with the average of the previous and above pixels
+    Avg,
+    /// Paeth predictor
+    Paeth,
+    /// Best filter for each line
+    #[default]
+    Adaptive,
+}
+
+impl PngFilter {
+    /// All filters
+    const ALL: [Self; 6] = [
+        Self::NoFilter,
+        Self::Sub,
+        Self::Up,
+        Self::Avg,
+        Self::Paeth,
+        Self::Adaptive,
+    ];
+}
+
+impl From<PngFilter> for png::FilterType {
+    fn from(value: PngFilter) -> Self {
+        match value {
+            PngFilter::NoFilter => Self::NoFilter,
+            PngFilter::Sub => Self::Sub,
+            PngFilter::Up => Self::Up,
+            PngFilter::Avg => Self::Avg,
+            PngFilter::Paeth => Self::Paeth,
+            PngFilter::Adaptive => Self::Adaptive,
+        }
+    }
+}
+
+/// Encoder options
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+#[serde(default)]
+pub(crate) struct ExportOptions {
+    /// JPEG quality, from 1 to 100
+    pub(crate) jpeg_quality: u8,
+    /// PNG compression level
+    pub(crate) png_compression: PngCompression,
+    /// PNG filter
+    pub(crate) png_filter: PngFilter,
+    /// GIF palette speed, from 1 (best palette) to 30 (fastest)
+    pub(crate) gif_speed: i32,
+    /// AVIF quality, from 1 to 100
+    pub(crate) avif_quality: u8,
+    /// AVIF speed, from 1 (best compression) to 10 (fastest)
+    pub(crate) avif_speed: u8,
+    /// Do not write the exif (camera, GPS, copyright...)
+    pub(crate) strip_exif: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            jpeg_quality: 75,
+            png_compression: PngCompression::default(),
+            png_filter: PngFilter::default(),
+            gif_speed: 10,
+            avif_quality: 80,
+            avif_speed: 4,
+            strip_exif: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// Encode an image with the options, writing the exif if the format can store it
+    /// # Errors
+    /// Fails if the encoder fails
+    pub(crate) fn encode(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        exif: Option<&exif::Exif>,
+    ) -> Result<Vec<u8>, String> {
+        let exif = exif.filter(|_| !self.strip_exif);
+        if format == ImageFormat::Tiff
+            && let Some(exif) = exif
+        {
+            // the tiff encoder cannot write exif
+            return metadata::write_tiff(img, exif);
+        }
+        let (width, height) = img.dimensions();
+        let exif = exif
+            .map(|exif| metadata::for_image(exif, width, height))
+            .transpose()?;
+        self.encode_with_exif(img, format, exif)
+            .map_err(|e| format!("Cannot write image: {e}"))
+    }
+
+    /// Encode an image with the options and the raw exif
+    fn encode_with_exif(
+        &self,
+        img: &DynamicImage,
+        format: ImageFormat,
+        exif: Option<Vec<u8>>,
+    ) -> ImageResult<Vec<u8>> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut cursor = Cursor::new(&mut bytes);
+        match format {
+            ImageFormat::Jpeg => write_with_exif(
+                img,
+                JpegEncoder::new_with_quality(&mut cursor, self.jpeg_quality.clamp(1, 100)),
+                exif,
+            )?,
+            ImageFormat::Png => write_with_exif(
+                img,
+                PngEncoder::new_with_quality(
+                    &mut cursor,
+                    self.png_compression.into(),
+                    self.png_filter.into(),
+                ),
+                exif,
+            )?,
+            ImageFormat::WebP => {
+                write_with_exif(img, WebPEncoder::new_lossless(&mut cursor), exif)?;
+            }
+            ImageFormat::Gif => img.write_with_encoder(GifEncoder::new_with_speed(
+                &mut cursor,
+                self.gif_speed.clamp(1, 30),
+            ))?,
+            ImageFormat::Avif => write_with_exif(
+                img,
+                AvifEncoder::new_with_speed_quality(
+                    &mut cursor,
+                    self.avif_speed.clamp(1, 10),
+                    self.avif_quality.clamp(1, 100),
+                ),
+                exif,
+            )?,
+            _ => img.write_to(&mut cursor, format)?,
+        }
+        Ok(bytes)
+    }
+
+    /// Options ui of a format
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, format: ImageFormat) {
+        match format {
+            ImageFormat::Jpeg => {
+                ui.add(egui::Slider::new(&mut self.jpeg_quality, 1..=100).text("Quality"));
+            }
+            ImageFormat::Png => {
+                egui::ComboBox::from_label("Compression")
+                    .selected_text(format!("{:?}", self.png_compression))
+                    .show_ui(ui, |ui| {
+                        for compression in PngCompression::ALL {
+                            ui.selectable_value(
+                                &mut self.png_compression,
+                                compression,
+                                format!("{compression:?}"),
+                            );
+                        }
+                    });
+                egui::ComboBox::from_label("Filter")
+                    .selected_text(format!("{:?}", self.png_filter))
+                    .show_ui(ui, |ui| {
+                        for filter in PngFilter::ALL {
+                            ui.selectable_value(
+                                &mut self.png_filter,
+                                filter,
+                                format!("{filter:?}"),
+                            );
+                        }
+                    });
+            }
+            ImageFormat::Gif => {
+                ui.add(egui::Slider::new(&mut self.gif_speed, 1..=30).text("Palette speed"))
+                    .on_hover_text("1 gives the best palette, 30 is the fastest");
+            }
+            ImageFormat::WebP => {
+                ui.label("WebP is saved lossless, lossy encoding is not available");
+            }
+            ImageFormat::Avif => {
+                ui.add(egui::Slider::new(&mut self.avif_quality, 1..=100).text("Quality"));
+                ui.add(egui::Slider::new(&mut self.avif_speed, 1..=10).text("Speed"))
+                    .on_hover_text("1 gives the best compression, 10 is the fastest");
+            }
+            _ => {
+                ui.label("No option for this format");
+            }
+        }
+    }
+}
+
+/// Write an image with an encoder, and the raw exif if any
+fn write_with_exif<E: ImageEncoder>(
+    img: &DynamicImage,
+    mut encoder: E,
+    exif: Option<Vec<u8>>,
+) -> ImageResult<()> {
+    if let Some(exif) = exif {
+        encoder
+            .set_exif_metadata(exif)
+            .map_err(ImageError::Unsupported)?;
+    }
+    img.write_with_encoder(encoder)
+}
+
+/// Image encoded with some options
+struct Encoded {
+    /// Options used
+    options: ExportOptions,
+    /// Conversion used
+    convert: Option<ColorType>,
+    /// Encoded image or error
+    bytes: Result<Vec<u8>, String>,
+}
+
+impl Encoded {
+    /// Check if the image was encoded with these options
+    fn is_for(&self, options: &ExportOptions, convert: Option<ColorType>) -> bool {
+        self.options == *options && self.convert == convert
+    }
+}
+
+/// State of the export dialog
+pub(crate) struct ExportDialog {
+    /// Format to save
+    pub(crate) format: ImageFormat,
+    /// Color type to convert to, if the format cannot store the one of the image
+    pub(crate) convert: Option<ColorType>,
+    /// Last encoded image, with the options used to encode it
+    encoded: Option<Encoded>,
+}
+
+impl std::fmt::Debug for ExportDialog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExportDialog")
+            .field("format", &self.format)
+            .field("convert", &self.convert)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Human readable size
+#[allow(clippy::cast_precision_loss)]
+fn display_size(size: usize) -> String {
+    match size {
+        0..1024 => format!("{size} B"),
+        1024..1_048_576 => format!("{:.1} KiB", size as f64 / 1024.0),
+        _ => format!("{:.1} MiB", size as f64 / 1_048_576.0),
+    }
+}
+
+/// Warnings about what the format cannot store, with the conversion to use
+fn limits_ui(
+    ui: &mut egui::Ui,
+    save_format: &SaveFormat,
+    document: &Document,
+    convert: &mut Option<ColorType>,
+) {
+    if let Some(target) = convert {
+        ui.colored_label(
+            Color32::ORANGE,
+            format!(
+                "{} cannot store {:?} images",
+                save_format.name,
+                document.color_type()
+            ),
+        );
+        ui.horizontal(|ui| {
+            ui.label("Convert to");
+            egui::ComboBox::from_id_salt("export_conversion_box")
+                .selected_text(format!("{target:?}"))
+                .show_ui(ui, |ui| {
+                    for candidate in save_format.color_types {
+                        ui.selectable_value(target, *candidate, format!("{candidate:?}"));
+                    }
+                });
+        });
+        ui.label("The document is not modified");
+        ui.separator();
+    }
+    if let Some(max_size) = save_format.max_size
+        && !save_format.fits(document.size())
+    {
+        let (width, height) = document.size();
+        ui.colored_label(
+            Color32::ORANGE,
+            format!(
+                "{} images are at most {max_size}x{max_size} pixels, the image is {width}x{height}",
+                save_format.name
+            ),
+        );
+        ui.separator();
+    }
+}
+
+impl TarsierApp {
+    /// Open the export dialog for a format
+    pub(crate) fn save_as(&mut self, save_format: &SaveFormat) {
+        let Some(document) = self.documents.get_current_doc() else {
+            return;
+        };
+        let color_type = document.color_type();
+        self.export = Some(ExportDialog {
+            format: save_format.format,
+            convert: (!save_format.supports(color_type))
+                .then(|| save_format.closest_color_type(color_type)),
+            encoded: None,
+        });
+    }
+
+    /// Show the export dialog
+    pub(crate) fn show_export_modal(
+        &mut self,
+        ui: &mut egui::Ui,
+        error_manager: &mut ErrorManager,
+    ) {
+        let Some(export) = &mut self.export else {
+            return;
+        };
+        let (Some(save_format), Some(document)) = (
+            SaveFormat::get(export.format),
+            self.documents.get_current_doc(),
+        ) else {
+            self.export = None;
+            return;
+        };
+        let options = &mut self.settings.export;
+        let mut save = false;
+        let modal = Modal::new(Id::new("Modal export")).show(ui.ctx(), |ui| {
+            ui.heading(format!("Export as {}", save_format.name));
+            limits_ui(ui, save_format, document, &mut export.convert);
+            options.show(ui, export.format);
+            if document.exif.is_some() {
+                ui.separator();
+                if metadata::EXIF_FORMATS.contains(&export.format) {
+                    ui.checkbox(&mut options.strip_exif, "Strip metadata (EXIF, GPS)");
+                } else {
+                    ui.label(format!("{} cannot store the exif", save_format.name));
+                }
+            }
+            ui.separator();
+            // encode again when the options changed, but not while dragging a slider
+            let is_outdated = export
+                .encoded
+                .as_ref()
+                .is_none_or(|encoded| !encoded.is_for(options, export.convert));
+            if is_outdated && !ui.input(|i| i.pointer.any_down()) {
+                export.encoded = Some(Encoded {
+                    options: options.clone(),
+                    convert: export.convert,
+                    bytes: document.encode(export.format, export.convert, options),
+                });
+            }
+            match export.encoded.as_ref().map(|encoded| &encoded.bytes) {
+                Some(Ok(bytes)) => {
+                    ui.label(format!("Estimated size: {}", display_size(bytes.len())));
+                }
+                Some(Err(e)) => {
+                    ui.colored_label(Color32::RED, e);
+                }
+                None => {}
+            }
+            egui::Sides::new().show(
+                ui,
+                |modal_ui| {
+                    if modal_ui.button("Cancel").clicked() {
+                        modal_ui.close();
+                    }
+                },
+                |modal_ui| {
+                    if modal_ui.button("Save").clicked() {
+                        save = true;
+                        modal_ui.close();
+                    }
+                },
+            );
+        });
+        if !(save || modal.should_close()) {
+            return;
+        }
+        let Some(export) = self.export.take() else {
+            return;
+        };
+        if !save {
+            return;
+        }
+        let bytes = match export.encoded {
+            Some(encoded) if encoded.is_for(&self.settings.export, export.convert) => encoded.bytes,
+            _ => document.encode(export.format, export.convert, &self.settings.export),
+        };
+        match bytes {
+            Ok(bytes) => self.save_encoded(save_format, &bytes, error_manager),
+            Err(e) => error_manager.add_error(e),
+        }
+    }
+
+    /// Choose a path and save an encoded image
+    fn save_encoded(
+        &mut self,
+        save_format: &SaveFormat,
+        bytes: &[u8],
+        error_manager: &mut ErrorManager,
+    ) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        let current_extension = document.filename.extension().and_then(|e| e.to_str());
+        let current_save_path = if current_extension
+            .is_some_and(|ext| save_format.format.extensions_str().contains(&ext))
+        {
+            document.filename.clone()
+        } else {
+            document.filename.with_extension(save_format.extension)
+        };
+        match bladvak::utils::get_save_path(Some(&current_save_path)) {
+            Ok(Some(path_to_save)) => {
+                document.filename.clone_from(&path_to_save);
+                if let Err(err) = bladvak::utils::save_file(bytes, &path_to_save) {
+                    error_manager.add_error(err);
+                } else {
+                    self.macros.record(MacroStep::Save(save_format.format));
+                }
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error_manager.add_error(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ops::{Operation, Region};
+    use exif::{Exif, Field, In, Tag, Value};
+
+    /// Formats which can store the exif
+    const EXIF_FORMATS: [ImageFormat; 4] = [
+        ImageFormat::Jpeg,
+        ImageFormat::Png,
+        ImageFormat::WebP,
+        ImageFormat::Tiff,
+    ];
+
+    /// Exif of a 100x100 flipped photo
+    fn photo_exif() -> Exif {
+        let fields = [
+            (Tag::Artist, Value::Ascii(vec![b"tarsier".to_vec()])),
+            (Tag::Orientation, Value::Short(vec![2])),
+            (Tag::PixelXDimension, Value::Long(vec![100])),
+            (Tag::PixelYDimension, Value::Long(vec![100])),
+        ];
+        let mut exif = None;
+        for (tag, value) in fields {
+            let new_field = Field {
+                tag,
+                ifd_num: In::PRIMARY,
+                value,
+            };
+            let Ok(new_exif) = metadata::with_field(exif.as_ref(), new_field) else {
+                panic!("the exif can be written");
+            };
+            exif = Some(new_exif);
+        }
+        let Some(exif) = exif else {
+            panic!("the exif has fields");
+        };
+        exif
+    }
+
+    /// 4x3 document with the exif of a photo
+    fn photo() -> Document {
+        Document {
+            exif: Some(photo_exif()),
+            ..Document::new("photo.jpg".into(), DynamicImage::new_rgb8(4, 3))
+        }
+    }
+
+    /// Exif read back from an encoded document
+    fn read_exif(
+        document: &Document,
+        format: ImageFormat,
+        options: &ExportOptions,
+    ) -> Option<Exif> {
+        let Ok(bytes) = document.encode(format, None, options) else {
+            panic!("the document can be encoded as {format:?}");
+        };
+        exif::Reader::new()
+            .read_from_container(&mut Cursor::new(&bytes))
+            .ok()
+    }
+
+    /// Text of a field of the primary image
+    fn text(exif: &Exif, tag: Tag) -> Option<String> {
+        exif.get_field(tag, In::PRIMARY)
+            .map(|field| metadata::value_text(&field.value))
+    }
+
+    #[test]
+    fn exif_is_embedded() {
+        let document = photo();
+        for format in EXIF_FORMATS {
+            let Some(exif) = read_exif(&document, format, &ExportOptions::default()) else {
+                panic!("{format:?} keeps the exif");
+            };
+            assert_eq!(text(&exif, Tag::Artist).as_deref(), Some("tarsier"));
+            assert_eq!(text(&exif, Tag::Orientation).as_deref(), Some("2"));
+            assert_eq!(text(&exif, Tag::PixelXDimension).as_deref(), Some("4"));
+            assert_eq!(text(&exif, Tag::PixelYDimension).as_deref(), Some("3"));
+        }
+    }
+
+    #[test]
+    fn exif_follows_crop_and_rotate() {
+        let mut document = photo();
+        document.crop(Region::new(1, 0, 2, 3));
+        assert!(
+            document
+                .apply_operation(None, None, &Operation::Rotate90)
+                .is_ok()
+        );
+        let Ok(Some(rotated)) = metadata::after_operation(&photo_exif(), &Operation::Rotate90)
+        else {
+            panic!("a rotation changes a flipped orientation");
+        };
+        let orientation = metadata::orientation(&rotated).to_string();
+        assert_ne!(orientation, "2");
+        for format in EXIF_FORMATS {
+            let Some(exif) = read_exif(&document, format, &ExportOptions::default()) else {
+                panic!("{format:?} keeps the exif");
+            };
+            assert_eq!(text(&exif, Tag::PixelXDimension).as_deref(), Some("3"));
+            assert_eq!(text(&exif, Tag::PixelYDimension).as_deref(), Some("2"));
+            assert_eq!(
+                text(&exif, Tag::Orientation).as_deref(),
+                Some(orientation.as_str())
+            );
+        }
+    }
+
+    #[test]
+    fn strip_exif_removes_everything() {
+        let document = photo();
+        let options = ExportOptions {
+            strip_exif: true,
+            ..Default::default()
+        };
+        for format in [ImageFormat::Jpeg, ImageFormat::Png, ImageFormat::WebP] {
+            assert!(
+                read_exif(&document, format, &options).is_none(),
+                "{format:?}"
+            );
+        }
+        // a TIFF file is itself an exif structure: only the image structure is left
+        let Some(exif) = read_exif(&document, ImageFormat::Tiff, &options) else {
+            panic!("a TIFF file has an IFD");
+        };
+        for tag in [
+            Tag::Artist,
+            Tag::Orientation,
+            Tag::PixelXDimension,
+            Tag::PixelYDimension,
+        ] {
+            assert!(text(&exif, tag).is_none(), "{tag}");
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Floating selection: pasted pixels that can be moved and scaled before they are committed
+
+use bladvak::eframe::egui::{self, Color32, Pos2, Rect, TextureHandle, TextureOptions, Vec2};
+use bladvak::errors::AppError;
+use image::{DynamicImage, GenericImageView, imageops::FilterType};
+
+use crate::document::Document;
+use crate::layer::{self, Layer};
+use crate::ops::{self, Region};
+
+/// Pixels floating above the active layer, at the selection rectangle
+#[derive(Clone)]
+pub(crate) struct FloatingSelection {
+    /// Pixels, at their original size
+    pub(crate) img: DynamicImage,
+    /// Texture of the pixels
+    texture: Option<TextureHandle>,
+}
+
+impl std::fmt::Debug for FloatingSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FloatingSelection")
+            .field("size", &self.img.dimensions())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FloatingSelection {
+    /// New floating selection
+    pub(crate) fn new(img: DynamicImage) -> Self {
+        Self { img, texture: None }
+    }
+
+    /// Paint the pixels at `rect` on the screen
+    pub(crate) fn paint(&mut self, ui: &egui::Ui, rect: Rect) {
+        let texture = self.texture.get_or_insert_with(|| {
+            let rgba = self.img.to_rgba8();
+            let (width, height) = rgba.dimensions();
+            let image = egui::ColorImage::from_rgba_unmultiplied(
+                [width as usize, height as usize],
+                rgba.as_flat_samples().as_slice(),
+            );
+            ui.ctx()
+                .load_texture("floating", image, TextureOptions::LINEAR)
+        });
+        ui.painter().image(
+            texture.id(),
+            rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+    }
+}
+
+/// Image with the pixels of a region cleared: transparent, or white without alpha
+fn cleared(img: &DynamicImage) -> DynamicImage {
+    let mut new_img = DynamicImage::new(img.width(), img.height(), img.color());
+    if !img.color().has_alpha() {
+        new_img.invert();
+    }
+    new_img
+}
+
+impl Document {
+    /// Pixels of the active layer in the selection, the unselected pixels are transparent
+    pub(crate) fn copy_selection(&self) -> Option<DynamicImage> {
+        let (width, height) = self.size();
+        let canvas_region = self
+            .selection_region()
+            .unwrap_or(Region::new(0, 0, width, height));
+        let layer = self.layer();
+        let region = layer.to_layer_region(canvas_region);
+        if region.is_empty() {
+            return None;
+        }
+        let pixels = ops::crop(&layer.img, region);
+        let Some(mask) = self.selection_mask(canvas_region) else {
+            return Some(pixels);
+        };
+        let mask = layer.crop_mask(&mask, canvas_region, region);
+        let (width, height) = pixels.dimensions();
+        let mut copied = DynamicImage::new(width, height, ops::with_alpha(pixels.color()));
+        ops::merge_masked(&mut copied, &pixels, &mask).ok()?;
+        Some(copied)
+    }
+
+    /// Copy the pixels of the selection and clear them in the active layer
+    /// # Errors
+    /// Fails if the pixels cannot be cleared
+    pub(crate) fn cut_selection(&mut self) -> Result<Option<DynamicImage>, AppError> {
+        let Some(copied) = self.copy_selection() else {
+            return Ok(None);
+        };
+        let region = self.selection_region();
+        let mask = region.and_then(|region| self.selection_mask(region));
+        self.apply_op(region, mask.as_ref(), cleared)?;
+        Ok(Some(copied))
+    }
+
+    /// Paste pixels as a floating selection, at the selection or centered, and scaled to fit in the canvas
+    /// # Errors
+    /// Fails if the previous floating selection cannot be committed
+    pub(crate) fn paste_floating(&mut self, img: DynamicImage) -> Result<(), AppError> {
+        self.commit_floating()?;
+        let (width, height) = self.size();
+        #[allow(clippy::cast_precision_loss)]
+        let (canvas, size) = (
+            Vec2::new(width as f32, height as f32),
+            Vec2::new(img.width() as f32, img.height() as f32),
+        );
+        let size = (size * (canvas / size).min_elem().min(1.0))
+            .round()
+            .max(Vec2::splat(1.0));
+        let min = self.selection.rectangle.map_or_else(
+            || ((canvas - size) / 2.0).round().to_pos2(),
+            |rect| rect.min.min((canvas - size).to_pos2()),
+        );
+        self.selection.clear();
+        self.selection.rectangle = Some(Rect::from_min_size(min, size));
+        self.floating = Some(FloatingSelection::new(img));
+        Ok(())
+    }
+
+    /// Blend the floating selection into the active layer
+    /// # Errors
+    /// Fails if the pixels cannot be copied in the layer
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn commit_floating(&mut self) -> Result<(), AppError> {
+        let Some(floating) = self.floating.take() else {
+            return Ok(());
+        };
+        let Some(rect) = self.selection.rectangle.take() else {
+            return Ok(());
+        };
+        let region = Region::new(
+            rect.min.x.max(0.0) as u32,
+            rect.min.y.max(0.0) as u32,
+            rect.width() as u32,
+            rect.height() as u32,
+        );
+        if region.is_empty() {
+            return Ok(());
+        }
+        let pixels = if floating.img.dimensions() == (region.width, region.height) {
+            floating.img
+        } else {
+            floating
+                .img
+                .resize_exact(region.width, region.height, FilterType::Triangle)
+        };
+        let pasted = Layer::new("Floating", pixels);
+        self.apply_op(Some(region), None, |before| {
+            let base = Layer::new("Base", before.clone());
+            let (width, height) = before.dimensions();
+            layer::composite(&[base, pasted.clone()], width, height, before.color())
+        })
+    }
+
+    /// Drop the floating selection
+    pub(crate) fn cancel_floating(&mut self) {
+        if self.floating.take().is_some() {
+            self.selection.clear();
+        }
+    }
+}
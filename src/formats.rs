@@ -0,0 +1,262 @@
+//! Image formats that can be saved
+
+use image::{ColorType, ImageFormat};
+
+/// 8-bit color types
+const COLOR_TYPES_8: &[ColorType] = &[
+    ColorType::L8,
+    ColorType::La8,
+    ColorType::Rgb8,
+    ColorType::Rgba8,
+];
+
+/// 8-bit and 16-bit color types
+const COLOR_TYPES_8_16: &[ColorType] = &[
+    ColorType::L8,
+    ColorType::La8,
+    ColorType::Rgb8,
+    ColorType::Rgba8,
+    ColorType::L16,
+    ColorType::La16,
+    ColorType::Rgb16,
+    ColorType::Rgba16,
+];
+
+/// Format that can be saved
+#[derive(Debug)]
+pub(crate) struct SaveFormat {
+    /// Format
+    pub(crate) format: ImageFormat,
+    /// Name shown in the menu
+    pub(crate) name: &'static str,
+    /// Extension of the saved file
+    pub(crate) extension: &'static str,
+    /// Color types the encoder can store without conversion
+    pub(crate) color_types: &'static [ColorType],
+    /// Largest width and height the encoder can store, if limited
+    pub(crate) max_size: Option<u32>,
+}
+
+/// Every format that can be saved
+pub(crate) const SAVE_FORMATS: &[SaveFormat] = &[
+    SaveFormat {
+        format: ImageFormat::Png,
+        name: "PNG",
+        extension: "png",
+        color_types: COLOR_TYPES_8_16,
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Jpeg,
+        name: "JPEG",
+        extension: "jpg",
+        color_types: &[ColorType::L8, ColorType::Rgb8],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::WebP,
+        name: "WebP",
+        extension: "webp",
+        color_types: COLOR_TYPES_8,
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Gif,
+        name: "GIF",
+        extension: "gif",
+        color_types: &[ColorType::Rgb8, ColorType::Rgba8],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Bmp,
+        name: "BMP",
+        extension: "bmp",
+        color_types: COLOR_TYPES_8,
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Tiff,
+        name: "TIFF",
+        extension: "tiff",
+        color_types: &[
+            ColorType::L8,
+            ColorType::Rgb8,
+            ColorType::Rgba8,
+            ColorType::L16,
+            ColorType::Rgb16,
+            ColorType::Rgba16,
+            ColorType::Rgb32F,
+            ColorType::Rgba32F,
+        ],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Qoi,
+        name: "QOI",
+        extension: "qoi",
+        color_types: &[ColorType::Rgb8, ColorType::Rgba8],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Ico,
+        name: "ICO",
+        extension: "ico",
+        color_types: COLOR_TYPES_8_16,
+        max_size: Some(256),
+    },
+    SaveFormat {
+        format: ImageFormat::Tga,
+        name: "TGA",
+        extension: "tga",
+        color_types: COLOR_TYPES_8,
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Pnm,
+        name: "PNM",
+        extension: "pnm",
+        color_types: &[
+            ColorType::L8,
+            ColorType::La8,
+            ColorType::Rgb8,
+            ColorType::Rgba8,
+            ColorType::L16,
+            ColorType::Rgb16,
+            ColorType::Rgba16,
+        ],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Avif,
+        name: "AVIF",
+        extension: "avif",
+        color_types: COLOR_TYPES_8_16,
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::OpenExr,
+        name: "OpenEXR",
+        extension: "exr",
+        color_types: &[ColorType::Rgb32F, ColorType::Rgba32F],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Hdr,
+        name: "HDR",
+        extension: "hdr",
+        color_types: &[ColorType::Rgb32F],
+        max_size: None,
+    },
+    SaveFormat {
+        format: ImageFormat::Farbfeld,
+        name: "Farbfeld",
+        extension: "ff",
+        color_types: &[ColorType::Rgba16],
+        max_size: None,
+    },
+];
+
+impl SaveFormat {
+    /// Find the entry of a format
+    pub(crate) fn get(format: ImageFormat) -> Option<&'static Self> {
+        SAVE_FORMATS.iter().find(|save| save.format == format)
+    }
+
+    /// Check if the format can store a color type
+    pub(crate) fn supports(&self, color_type: ColorType) -> bool {
+        self.color_types.contains(&color_type)
+    }
+
+    /// Check if the format can store an image of this size
+    pub(crate) fn fits(&self, (width, height): (u32, u32)) -> bool {
+        self.max_size
+            .is_none_or(|max_size| width <= max_size && height <= max_size)
+    }
+
+    /// Supported color type closest to `color_type`: keep alpha first, then color, then depth
+    pub(crate) fn closest_color_type(&self, color_type: ColorType) -> ColorType {
+        let depth = |c: ColorType| c.bytes_per_pixel() / c.channel_count();
+        self.color_types
+            .iter()
+            .copied()
+            .min_by_key(|candidate| {
+                (
+                    candidate.has_alpha() != color_type.has_alpha(),
+                    candidate.has_color() != color_type.has_color(),
+                    depth(*candidate).abs_diff(depth(color_type)),
+                )
+            })
+            .unwrap_or(ColorType::Rgba8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::ExportOptions;
+    use crate::ops;
+    use image::DynamicImage;
+
+    /// Every color type of the images
+    const ALL_COLOR_TYPES: [ColorType; 10] = [
+        ColorType::L8,
+        ColorType::La8,
+        ColorType::Rgb8,
+        ColorType::Rgba8,
+        ColorType::L16,
+        ColorType::La16,
+        ColorType::Rgb16,
+        ColorType::Rgba16,
+        ColorType::Rgb32F,
+        ColorType::Rgba32F,
+    ];
+
+    #[test]
+    fn listed_color_types_can_be_encoded() {
+        let img = DynamicImage::new_rgba8(5, 3);
+        let options = ExportOptions::default();
+        let mut failures = Vec::new();
+        for save_format in SAVE_FORMATS {
+            for color_type in save_format.color_types {
+                let converted = ops::convert(&img, *color_type);
+                if let Err(e) = options.encode(&converted, save_format.format, None) {
+                    failures.push(format!("{} {color_type:?}: {e}", save_format.name));
+                }
+            }
+        }
+        assert!(failures.is_empty(), "{failures:#?}");
+    }
+
+    #[test]
+    fn ico_size_limit() {
+        let Some(ico) = SaveFormat::get(ImageFormat::Ico) else {
+            panic!("ICO can be saved");
+        };
+        let options = ExportOptions::default();
+        for size in [256, 257] {
+            let img = DynamicImage::new_rgba8(size, 1);
+            assert_eq!(ico.fits((size, 1)), size == 256);
+            assert_eq!(
+                options.encode(&img, ImageFormat::Ico, None).is_ok(),
+                size == 256
+            );
+        }
+    }
+
+    #[test]
+    fn closest_color_type_is_listed() {
+        for save_format in SAVE_FORMATS {
+            for color_type in ALL_COLOR_TYPES {
+                let closest = save_format.closest_color_type(color_type);
+                assert!(
+                    save_format.supports(closest),
+                    "{} {color_type:?} -> {closest:?}",
+                    save_format.name
+                );
+                if save_format.supports(color_type) {
+                    assert_eq!(closest, color_type);
+                }
+            }
+        }
+    }
+}
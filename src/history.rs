@@ -0,0 +1,721 @@
+//! Undo/redo history
+
+use image::{DynamicImage, GenericImageView};
+use std::collections::VecDeque;
+
+use crate::layer::{Layer, LayerProps};
+use crate::ops;
+
+/// Memory budget of the history of one document (in bytes)
+const HISTORY_BUDGET: usize = 256 * 1024 * 1024;
+
+/// One change of the layers
+#[derive(Debug, Clone)]
+enum Change {
+    /// The whole image of a layer was replaced (size or color type changed)
+    Full {
+        /// Index of the layer
+        layer: usize,
+        /// Image before the change
+        before: DynamicImage,
+        /// Image after the change
+        after: DynamicImage,
+    },
+    /// Only a region of the image of a layer changed
+    Region {
+        /// Index of the layer
+        layer: usize,
+        /// Left of the region
+        x: u32,
+        /// Top of the region
+        y: u32,
+        /// Region before the change
+        before: DynamicImage,
+        /// Region after the change
+        after: DynamicImage,
+    },
+    /// The properties of a layer changed (name, visibility, opacity, ...)
+    Properties {
+        /// Index of the layer
+        layer: usize,
+        /// Properties before the change
+        before: LayerProps,
+        /// Properties after the change
+        after: LayerProps,
+    },
+    /// The list of layers changed (added, removed, moved, merged)
+    Layers {
+        /// Layers before the change
+        before: Vec<Layer>,
+        /// Layers after the change
+        after: Vec<Layer>,
+    },
+}
+
+impl Change {
+    /// Compute the change between two images, `None` if they are identical
+    fn between(layer: usize, before: &DynamicImage, after: &DynamicImage) -> Option<Self> {
+        if before.dimensions() != after.dimensions() || before.color() != after.color() {
+            return Some(Self::Full {
+                layer,
+                before: before.clone(),
+                after: after.clone(),
+            });
+        }
+        let (x, y, width, height) = diff_bounds(before, after)?;
+        Some(Self::Region {
+            layer,
+            x,
+            y,
+            before: before.crop_imm(x, y, width, height),
+            after: after.crop_imm(x, y, width, height),
+        })
+    }
+
+    /// Memory used by the change
+    fn size(&self) -> usize {
+        match self {
+            Self::Full { before, after, .. } | Self::Region { before, after, .. } => {
+                before.as_bytes().len() + after.as_bytes().len()
+            }
+            Self::Layers { before, after } => before
+                .iter()
+                .chain(after)
+                .map(|layer| layer.img.as_bytes().len())
+                .sum(),
+            Self::Properties { .. } => std::mem::size_of::<Self>(),
+        }
+    }
+
+    /// Revert the change on the layers
+    fn undo(&self, layers: &mut Vec<Layer>) {
+        match self {
+            Self::Full { layer, before, .. } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    layer.img.clone_from(before);
+                }
+            }
+            Self::Region {
+                layer,
+                x,
+                y,
+                before,
+                ..
+            } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    // the region was taken from this image, so it always fits
+                    let _ = ops::paste(&mut layer.img, before, *x, *y);
+                }
+            }
+            Self::Properties { layer, before, .. } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    layer.props = before.clone();
+                }
+            }
+            Self::Layers { before, .. } => layers.clone_from(before),
+        }
+    }
+
+    /// Apply again the change on the layers
+    fn redo(&self, layers: &mut Vec<Layer>) {
+        match self {
+            Self::Full { layer, after, .. } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    layer.img.clone_from(after);
+                }
+            }
+            Self::Region {
+                layer, x, y, after, ..
+            } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    let _ = ops::paste(&mut layer.img, after, *x, *y);
+                }
+            }
+            Self::Properties { layer, after, .. } => {
+                if let Some(layer) = layers.get_mut(*layer) {
+                    layer.props = after.clone();
+                }
+            }
+            Self::Layers { after, .. } => layers.clone_from(after),
+        }
+    }
+}
+
+/// Change with its images replaced by indices, to store it in a project file
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub(crate) enum StoredChange {
+    /// See [`Change::Full`]
+    Full {
+        /// Index of the layer
+        layer: usize,
+        /// Image before the change
+        before: usize,
+        /// Image after the change
+        after: usize,
+    },
+    /// See [`Change::Region`]
+    Region {
+        /// Index of the layer
+        layer: usize,
+        /// Left of the region
+        x: u32,
+        /// Top of the region
+        y: u32,
+        /// Region before the change
+        before: usize,
+        /// Region after the change
+        after: usize,
+    },
+    /// See [`Change::Properties`]
+    Properties {
+        /// Index of the layer
+        layer: usize,
+        /// Properties before the change
+        before: LayerProps,
+        /// Properties after the change
+        after: LayerProps,
+    },
+    /// See [`Change::Layers`]
+    Layers {
+        /// Layers before the change, with their image index
+        before: Vec<(Layer, usize)>,
+        /// Layers after the change, with their image index
+        after: Vec<(Layer, usize)>,
+    },
+}
+
+/// History with its images stored apart
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub(crate) struct StoredHistory {
+    /// Changes that can be undone, oldest first
+    undo: Vec<StoredChange>,
+    /// Changes that can be redone, last undone at the end
+    redo: Vec<StoredChange>,
+}
+
+impl Change {
+    /// Store the change, `add_image` stores an image and returns its index
+    fn store<F>(&self, add_image: &mut F) -> Result<StoredChange, String>
+    where
+        F: FnMut(&DynamicImage) -> Result<usize, String>,
+    {
+        let mut store_layers = |layers: &[Layer]| {
+            layers
+                .iter()
+                .map(|layer| Ok((layer.clone_props(), add_image(&layer.img)?)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(match self {
+            Self::Full {
+                layer,
+                before,
+                after,
+            } => StoredChange::Full {
+                layer: *layer,
+                before: add_image(before)?,
+                after: add_image(after)?,
+            },
+            Self::Region {
+                layer,
+                x,
+                y,
+                before,
+                after,
+            } => StoredChange::Region {
+                layer: *layer,
+                x: *x,
+                y: *y,
+                before: add_image(before)?,
+                after: add_image(after)?,
+            },
+            Self::Properties {
+                layer,
+                before,
+                after,
+            } => StoredChange::Properties {
+                layer: *layer,
+                before: before.clone(),
+                after: after.clone(),
+            },
+            Self::Layers { before, after } => StoredChange::Layers {
+                before: store_layers(before)?,
+                after: store_layers(after)?,
+            },
+        })
+    }
+
+    /// Restore a stored change, `take_image` returns the image of an index
+    fn restore<F>(stored: StoredChange, take_image: &mut F) -> Result<Self, String>
+    where
+        F: FnMut(usize) -> Result<DynamicImage, String>,
+    {
+        let mut restore_layers = |layers: Vec<(Layer, usize)>| {
+            layers
+                .into_iter()
+                .map(|(mut layer, idx)| {
+                    layer.img = take_image(idx)?;
+                    Ok(layer)
+                })
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(match stored {
+            StoredChange::Full {
+                layer,
+                before,
+                after,
+            } => Self::Full {
+                layer,
+                before: take_image(before)?,
+                after: take_image(after)?,
+            },
+            StoredChange::Region {
+                layer,
+                x,
+                y,
+                before,
+                after,
+            } => Self::Region {
+                layer,
+                x,
+                y,
+                before: take_image(before)?,
+                after: take_image(after)?,
+            },
+            StoredChange::Properties {
+                layer,
+                before,
+                after,
+            } => Self::Properties {
+                layer,
+                before,
+                after,
+            },
+            StoredChange::Layers { before, after } => {
+                // a document always has a layer
+                if before.is_empty() || after.is_empty() {
+                    return Err("Empty list of layers in history".to_string());
+                }
+                Self::Layers {
+                    before: restore_layers(before)?,
+                    after: restore_layers(after)?,
+                }
+            }
+        })
+    }
+}
+
+/// Undo/redo history of a document
+#[derive(Debug)]
+pub(crate) struct History {
+    /// Changes that can be undone, oldest first
+    undo: VecDeque<Change>,
+    /// Changes that can be redone, last undone at the end
+    redo: Vec<Change>,
+    /// Memory used by the changes to undo and to redo
+    size: usize,
+    /// Maximum memory used by the changes (in bytes)
+    budget: usize,
+    /// Layer index and image at the start of the current stroke
+    stroke_start: Option<(usize, DynamicImage)>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+            size: 0,
+            budget: HISTORY_BUDGET,
+            stroke_start: None,
+        }
+    }
+}
+
+impl History {
+    /// Record the change between two states of the image of a layer
+    pub(crate) fn record(&mut self, layer: usize, before: &DynamicImage, after: &DynamicImage) {
+        if let Some(change) = Change::between(layer, before, after) {
+            self.push(change);
+        }
+    }
+
+    /// Record the change of a region of the image of a layer
+    pub(crate) fn record_region(
+        &mut self,
+        layer: usize,
+        (x, y): (u32, u32),
+        before: DynamicImage,
+        after: DynamicImage,
+    ) {
+        if before.as_bytes() != after.as_bytes() {
+            self.push(Change::Region {
+                layer,
+                x,
+                y,
+                before,
+                after,
+            });
+        }
+    }
+
+    /// Record a change of the list of layers
+    pub(crate) fn record_layers(&mut self, before: Vec<Layer>, after: &[Layer]) {
+        self.push(Change::Layers {
+            before,
+            after: after.to_vec(),
+        });
+    }
+
+    /// Record a change of the properties of a layer
+    ///
+    /// With `merge`, the change is merged in the last one if it is on the same layer (e.g. slider drag)
+    pub(crate) fn record_props(
+        &mut self,
+        layer: usize,
+        before: LayerProps,
+        after: LayerProps,
+        merge: bool,
+    ) {
+        if merge
+            && self.redo.is_empty()
+            && let Some(Change::Properties {
+                layer: last_layer,
+                after: last_after,
+                ..
+            }) = self.undo.back_mut()
+            && *last_layer == layer
+        {
+            *last_after = after;
+            return;
+        }
+        if before != after {
+            self.push(Change::Properties {
+                layer,
+                before,
+                after,
+            });
+        }
+    }
+
+    /// Start a stroke: every change until `end_stroke` is one history entry
+    pub(crate) fn begin_stroke(&mut self, layer: usize, img: &DynamicImage) {
+        if self.stroke_start.is_none() {
+            self.stroke_start = Some((layer, img.clone()));
+        }
+    }
+
+    /// End the current stroke and record it
+    pub(crate) fn end_stroke(&mut self, layers: &[Layer]) {
+        if let Some((layer, start)) = self.stroke_start.take()
+            && let Some(current) = layers.get(layer)
+        {
+            self.record(layer, &start, &current.img);
+        }
+    }
+
+    /// Check if there is a change to undo
+    pub(crate) fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    /// Check if there is a change to redo
+    pub(crate) fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Undo the last change, return true if the layers changed
+    pub(crate) fn undo(&mut self, layers: &mut Vec<Layer>) -> bool {
+        self.end_stroke(layers);
+        let Some(change) = self.undo.pop_back() else {
+            return false;
+        };
+        change.undo(layers);
+        self.redo.push(change);
+        true
+    }
+
+    /// Redo the last undone change, return true if the layers changed
+    pub(crate) fn redo(&mut self, layers: &mut Vec<Layer>) -> bool {
+        let Some(change) = self.redo.pop() else {
+            return false;
+        };
+        change.redo(layers);
+        self.undo.push_back(change);
+        true
+    }
+
+    /// Store the history, `add_image` stores an image and returns its index
+    /// # Errors
+    /// Fails if an image cannot be stored
+    pub(crate) fn store<F>(&self, mut add_image: F) -> Result<StoredHistory, String>
+    where
+        F: FnMut(&DynamicImage) -> Result<usize, String>,
+    {
+        Ok(StoredHistory {
+            undo: self
+                .undo
+                .iter()
+                .map(|change| change.store(&mut add_image))
+                .collect::<Result<_, _>>()?,
+            redo: self
+                .redo
+                .iter()
+                .map(|change| change.store(&mut add_image))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Restore a stored history, `take_image` returns the image of an index
+    /// # Errors
+    /// Fails if an image is missing, or if a change leaves no layer
+    pub(crate) fn restore<F>(stored: StoredHistory, mut take_image: F) -> Result<Self, String>
+    where
+        F: FnMut(usize) -> Result<DynamicImage, String>,
+    {
+        let undo = stored
+            .undo
+            .into_iter()
+            .map(|change| Change::restore(change, &mut take_image))
+            .collect::<Result<VecDeque<_>, _>>()?;
+        let redo = stored
+            .redo
+            .into_iter()
+            .map(|change| Change::restore(change, &mut take_image))
+            .collect::<Result<Vec<_>, _>>()?;
+        let size = undo.iter().chain(&redo).map(Change::size).sum();
+        Ok(Self {
+            undo,
+            redo,
+            size,
+            ..Self::default()
+        })
+    }
+
+    /// Add a change and drop the oldest ones if over budget
+    fn push(&mut self, change: Change) {
+        for undone in self.redo.drain(..) {
+            self.size -= undone.size();
+        }
+        self.size += change.size();
+        self.undo.push_back(change);
+        while self.size > self.budget && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.size -= oldest.size();
+            }
+        }
+    }
+}
+
+/// Bounds `(x, y, width, height)` of the pixels that differ between two images of the same size and color
+fn diff_bounds(before: &DynamicImage, after: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    let bpp = usize::from(before.color().bytes_per_pixel());
+    let width = before.width() as usize;
+    let row_len = width * bpp;
+    if row_len == 0 {
+        return None;
+    }
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    let rows = before
+        .as_bytes()
+        .chunks_exact(row_len)
+        .zip(after.as_bytes().chunks_exact(row_len));
+    for (y, (row_before, row_after)) in rows.enumerate() {
+        if row_before == row_after {
+            continue;
+        }
+        let first = row_before
+            .chunks_exact(bpp)
+            .zip(row_after.chunks_exact(bpp))
+            .position(|(a, b)| a != b)
+            .unwrap_or(0);
+        let last = width
+            - 1
+            - row_before
+                .chunks_exact(bpp)
+                .rev()
+                .zip(row_after.chunks_exact(bpp).rev())
+                .position(|(a, b)| a != b)
+                .unwrap_or(0);
+        bounds = Some(match bounds {
+            Some((min_x, min_y, max_x, _)) => (min_x.min(first), min_y, max_x.max(last), y),
+            None => (first, y, last, y),
+        });
+    }
+    let (min_x, min_y, max_x, max_y) = bounds?;
+    let to_u32 = |v: usize| u32::try_from(v).unwrap_or(u32::MAX);
+    Some((
+        to_u32(min_x),
+        to_u32(min_y),
+        to_u32(max_x - min_x + 1),
+        to_u32(max_y - min_y + 1),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImage, Rgb, RgbImage, Rgba};
+
+    /// 8x8 black rgb8 image with some white pixels
+    fn image_with(pixels: &[(u32, u32)]) -> DynamicImage {
+        let mut img = RgbImage::new(8, 8);
+        for &(x, y) in pixels {
+            img.put_pixel(x, y, Rgb([255, 255, 255]));
+        }
+        DynamicImage::ImageRgb8(img)
+    }
+
+    /// Memory used by the changes of the history
+    fn changes_size(history: &History) -> usize {
+        history
+            .undo
+            .iter()
+            .chain(&history.redo)
+            .map(Change::size)
+            .sum()
+    }
+
+    #[test]
+    fn diff_bounds_of_changed_pixels() {
+        let before = image_with(&[]);
+        assert_eq!(diff_bounds(&before, &before), None);
+        assert_eq!(
+            diff_bounds(&before, &image_with(&[(3, 5)])),
+            Some((3, 5, 1, 1))
+        );
+        assert_eq!(
+            diff_bounds(&before, &image_with(&[(6, 1), (2, 4), (0, 7)])),
+            Some((0, 1, 7, 7))
+        );
+        let empty = DynamicImage::new_rgb8(0, 4);
+        assert_eq!(diff_bounds(&empty, &empty), None);
+    }
+
+    #[test]
+    fn region_undo_redo() {
+        let before = image_with(&[]);
+        let after = image_with(&[(1, 1), (2, 3)]);
+        let mut layers = vec![Layer::new("a", after.clone())];
+        let mut history = History::default();
+        history.record(0, &before, &after);
+        assert!(matches!(
+            history.undo.back(),
+            Some(Change::Region { x: 1, y: 1, .. })
+        ));
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers[0].img, before);
+        assert!(!history.can_undo());
+        assert!(history.redo(&mut layers));
+        assert_eq!(layers[0].img, after);
+        assert!(!history.redo(&mut layers));
+        // identical images are not recorded
+        history.record(0, &after, &after);
+        assert_eq!(history.undo.len(), 1);
+    }
+
+    #[test]
+    fn full_undo_redo() {
+        let before = image_with(&[(0, 0)]);
+        let after = DynamicImage::new_luma8(4, 2);
+        let mut layers = vec![Layer::new("a", after.clone())];
+        let mut history = History::default();
+        history.record(0, &before, &after);
+        assert!(matches!(history.undo.back(), Some(Change::Full { .. })));
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers[0].img, before);
+        assert!(history.redo(&mut layers));
+        assert_eq!(layers[0].img, after);
+    }
+
+    #[test]
+    fn stroke_is_one_change() {
+        let start = image_with(&[]);
+        let mut layers = vec![Layer::new("a", start.clone())];
+        let mut history = History::default();
+        history.begin_stroke(0, &layers[0].img);
+        for x in 0..4 {
+            layers[0].img.put_pixel(x, 2, Rgba([255, 255, 255, 255]));
+            // nested begins keep the start of the stroke
+            history.begin_stroke(0, &layers[0].img);
+        }
+        let end = layers[0].img.clone();
+        history.end_stroke(&layers);
+        assert_eq!(history.undo.len(), 1);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers[0].img, start);
+        assert!(history.redo(&mut layers));
+        assert_eq!(layers[0].img, end);
+    }
+
+    #[test]
+    fn undo_ends_the_current_stroke() {
+        let start = image_with(&[]);
+        let mut layers = vec![Layer::new("a", start.clone())];
+        let mut history = History::default();
+        history.begin_stroke(0, &layers[0].img);
+        layers[0].img = image_with(&[(4, 4)]);
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers[0].img, start);
+        assert!(history.stroke_start.is_none());
+    }
+
+    #[test]
+    fn budget_counts_undo_and_redo() {
+        let black = image_with(&[]);
+        let mut layers = vec![Layer::new("a", black.clone())];
+        let mut history = History::default();
+        let images: Vec<_> = (0..4).map(|y| image_with(&[(0, y), (7, y)])).collect();
+        let change_size = 2 * 8 * 3;
+        history.budget = 3 * change_size;
+        for img in &images {
+            history.record(0, &black, img);
+        }
+        // the oldest change was dropped
+        assert_eq!(history.undo.len(), 3);
+        assert_eq!(history.size, 3 * change_size);
+        assert_eq!(history.size, changes_size(&history));
+        // undone changes still use memory
+        assert!(history.undo(&mut layers));
+        assert!(history.undo(&mut layers));
+        assert_eq!(history.size, changes_size(&history));
+        assert_eq!(history.size, 3 * change_size);
+        // a new change drops the ones to redo
+        history.record(0, &black, &images[0]);
+        assert!(!history.can_redo());
+        assert_eq!(history.size, 2 * change_size);
+        assert_eq!(history.size, changes_size(&history));
+    }
+
+    #[test]
+    fn budget_keeps_the_last_change() {
+        let mut history = History {
+            budget: 1,
+            ..History::default()
+        };
+        history.record(0, &image_with(&[]), &image_with(&[(1, 1)]));
+        history.record(0, &image_with(&[]), &image_with(&[(2, 2)]));
+        assert_eq!(history.undo.len(), 1);
+        assert!(history.can_undo());
+    }
+
+    #[test]
+    fn restore_rejects_empty_layers() {
+        let layer = Layer::new("layer", image_with(&[]));
+        let stored = |before: Vec<(Layer, usize)>| StoredHistory {
+            undo: vec![StoredChange::Layers {
+                before,
+                after: vec![(layer.clone_props(), 0)],
+            }],
+            redo: Vec::new(),
+        };
+        let take_image = |_| Ok(image_with(&[]));
+        assert!(History::restore(stored(Vec::new()), take_image).is_err());
+        let Ok(mut history) = History::restore(stored(vec![(layer.clone_props(), 0)]), take_image)
+        else {
+            panic!("a history with layers can be restored");
+        };
+        let mut layers = vec![layer.clone_props(), layer];
+        assert!(history.undo(&mut layers));
+        assert_eq!(layers.len(), 1);
+    }
+}
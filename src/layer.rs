@@ -0,0 +1,509 @@
+//! Layers of a document
+
+use bladvak::eframe::egui;
+use image::{ColorType, DynamicImage, GenericImageView, GrayImage, ImageBuffer, Rgba};
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::ops::{self, Operation, Region};
+
+/// Blend mode of a layer
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum BlendMode {
+    /// Layer above the ones below
+    #[default]
+    Normal,
+    /// Multiply the colors
+    Multiply,
+    /// Inverse of multiply
+    Screen,
+    /// Multiply or screen depending on the color below
+    Overlay,
+    /// Keep the darkest color
+    Darken,
+    /// Keep the lightest color
+    Lighten,
+    /// Add the colors
+    Add,
+    /// Absolute difference of the colors
+    Difference,
+}
+
+impl BlendMode {
+    /// All blend modes
+    pub(crate) const ALL: [Self; 8] = [
+        Self::Normal,
+        Self::Multiply,
+        Self::Screen,
+        Self::Overlay,
+        Self::Darken,
+        Self::Lighten,
+        Self::Add,
+        Self::Difference,
+    ];
+
+    /// Blend one channel of the layer (`src`) with the one below (`dst`)
+    fn blend(self, dst: f32, src: f32) -> f32 {
+        match self {
+            Self::Normal => src,
+            Self::Multiply => dst * src,
+            Self::Screen => dst + src - dst * src,
+            Self::Overlay => {
+                if dst <= 0.5 {
+                    2.0 * dst * src
+                } else {
+                    1.0 - 2.0 * (1.0 - dst) * (1.0 - src)
+                }
+            }
+            Self::Darken => dst.min(src),
+            Self::Lighten => dst.max(src),
+            Self::Add => (dst + src).min(1.0),
+            Self::Difference => (dst - src).abs(),
+        }
+    }
+}
+
+impl std::fmt::Display for BlendMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// Properties of a layer, without its pixels
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct LayerProps {
+    /// Name of the layer
+    pub(crate) name: String,
+    /// Is the layer visible
+    pub(crate) visible: bool,
+    /// Opacity, from 0 to 1
+    pub(crate) opacity: f32,
+    /// Blend mode
+    pub(crate) blend: BlendMode,
+    /// Position of the layer on the canvas
+    pub(crate) offset: (i32, i32),
+}
+
+/// One layer
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub(crate) struct Layer {
+    /// Properties of the layer
+    #[serde(flatten)]
+    pub(crate) props: LayerProps,
+    /// Pixels of the layer
+    #[serde(skip)]
+    pub(crate) img: DynamicImage,
+}
+
+impl Layer {
+    /// New visible layer
+    pub(crate) fn new(name: impl Into<String>, img: DynamicImage) -> Self {
+        Self {
+            props: LayerProps {
+                name: name.into(),
+                visible: true,
+                opacity: 1.0,
+                blend: BlendMode::Normal,
+                offset: (0, 0),
+            },
+            img,
+        }
+    }
+
+    /// Copy of the layer without its pixels
+    pub(crate) fn clone_props(&self) -> Self {
+        Self {
+            props: self.props.clone(),
+            img: DynamicImage::default(),
+        }
+    }
+
+    /// Merge `top` in this layer, keeping the size and properties of this layer
+    pub(crate) fn merge(&mut self, top: &Self) {
+        let base = Self::new(self.props.name.clone(), std::mem::take(&mut self.img));
+        let mut top = top.clone();
+        top.props.offset = (
+            top.props.offset.0.saturating_sub(self.props.offset.0),
+            top.props.offset.1.saturating_sub(self.props.offset.1),
+        );
+        let (width, height) = base.img.dimensions();
+        let color_type = base.img.color();
+        self.img = composite(&[base, top], width, height, color_type);
+    }
+
+    /// Check if the layer can be shown as is, without blending
+    fn is_plain(&self) -> bool {
+        self.props.visible
+            && self.props.blend == BlendMode::Normal
+            && (self.props.opacity - 1.0).abs() < f32::EPSILON
+            && self.props.offset == (0, 0)
+    }
+
+    /// Convert a canvas position to a position in the layer
+    pub(crate) fn to_layer_pos(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let x = u32::try_from(i64::from(x) - i64::from(self.props.offset.0)).ok()?;
+        let y = u32::try_from(i64::from(y) - i64::from(self.props.offset.1)).ok()?;
+        (x < self.img.width() && y < self.img.height()).then_some((x, y))
+    }
+
+    /// Convert a region of the canvas to a region of the layer
+    pub(crate) fn to_layer_region(&self, region: Region) -> Region {
+        let to_layer = |value: u32, offset: i32| {
+            u32::try_from((i64::from(value) - i64::from(offset)).max(0)).unwrap_or(u32::MAX)
+        };
+        let x = to_layer(region.x, self.props.offset.0);
+        let y = to_layer(region.y, self.props.offset.1);
+        let right = to_layer(region.x.saturating_add(region.width), self.props.offset.0);
+        let bottom = to_layer(region.y.saturating_add(region.height), self.props.offset.1);
+        Region::new(x, y, right - x, bottom - y).clamp_to(&self.img)
+    }
+
+    /// Part of the mask of a region of the canvas covering a region of the layer
+    pub(crate) fn crop_mask(
+        &self,
+        mask: &GrayImage,
+        canvas_region: Region,
+        layer_region: Region,
+    ) -> GrayImage {
+        let to_mask = |layer: u32, offset: i32, canvas: u32| {
+            u32::try_from(i64::from(layer) + i64::from(offset) - i64::from(canvas))
+                .unwrap_or_default()
+        };
+        image::imageops::crop_imm(
+            mask,
+            to_mask(layer_region.x, self.props.offset.0, canvas_region.x),
+            to_mask(layer_region.y, self.props.offset.1, canvas_region.y),
+            layer_region.width,
+            layer_region.height,
+        )
+        .to_image()
+    }
+
+    /// Layer rotated or flipped with a canvas of size (`width`, `height`),
+    /// `None` if the operation is not a rotation or a flip
+    pub(crate) fn oriented(
+        &self,
+        operation: &Operation,
+        (width, height): (u32, u32),
+    ) -> Option<Self> {
+        let (x, y) = self.props.offset;
+        let (x, y) = (i64::from(x), i64::from(y));
+        let (right, bottom) = (
+            i64::from(width) - x - i64::from(self.img.width()),
+            i64::from(height) - y - i64::from(self.img.height()),
+        );
+        let offset = match operation {
+            Operation::Rotate90 => (bottom, x),
+            Operation::Rotate180 => (right, bottom),
+            Operation::Rotate270 => (y, right),
+            Operation::FlipHorizontal => (right, y),
+            Operation::FlipVertical => (x, bottom),
+            _ => return None,
+        };
+        let to_i32 = |value: i64| i32::try_from(value).unwrap_or(i32::MAX);
+        let mut layer = self.clone_props();
+        layer.img = operation.apply(&self.img);
+        layer.props.offset = (to_i32(offset.0), to_i32(offset.1));
+        Some(layer)
+    }
+
+    /// Crop the part of the layer inside a region of the canvas
+    pub(crate) fn crop_canvas(&mut self, region: Region) {
+        let layer_region = self.to_layer_region(region);
+        self.img = ops::crop(&self.img, layer_region);
+        let to_i32 = |value: i64| i32::try_from(value).unwrap_or(i32::MAX);
+        let (x, y) = self.props.offset;
+        self.props.offset = (
+            to_i32(i64::from(x) + i64::from(layer_region.x) - i64::from(region.x)),
+            to_i32(i64::from(y) + i64::from(layer_region.y) - i64::from(region.y)),
+        );
+    }
+}
+
+/// Flatten the layers, from bottom to top, on a canvas of a given size and color type
+pub(crate) fn composite(
+    layers: &[Layer],
+    width: u32,
+    height: u32,
+    color_type: ColorType,
+) -> DynamicImage {
+    if let [layer] = layers
+        && layer.is_plain()
+        && layer.img.dimensions() == (width, height)
+    {
+        return layer.img.clone();
+    }
+    let mut canvas: ImageBuffer<Rgba<f32>, Vec<f32>> = ImageBuffer::new(width, height);
+    for layer in layers.iter().filter(|layer| layer.props.visible) {
+        let layer_img = layer.img.to_rgba32f();
+        for (x, y, pixel) in canvas.enumerate_pixels_mut() {
+            let Some((lx, ly)) = layer.to_layer_pos(x, y) else {
+                continue;
+            };
+            let src = layer_img.get_pixel(lx, ly).0;
+            let src_alpha = src[3] * layer.props.opacity;
+            if src_alpha <= 0.0 {
+                continue;
+            }
+            let dst = pixel.0;
+            let dst_alpha = dst[3];
+            let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+            for c in 0..3 {
+                let blended = layer.props.blend.blend(dst[c], src[c]);
+                let color = (1.0 - src_alpha) * dst[c] * dst_alpha
+                    + src_alpha * ((1.0 - dst_alpha) * src[c] + dst_alpha * blended);
+                pixel.0[c] = color / out_alpha;
+            }
+            pixel.0[3] = out_alpha;
+        }
+    }
+    ops::convert(&DynamicImage::ImageRgba32F(canvas), color_type)
+}
+
+impl TarsierApp {
+    /// Layers ui
+    pub(crate) fn layers_ui(&mut self, ui: &mut egui::Ui) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            ui.label("No document");
+            return;
+        };
+        layer_props_ui(ui, document);
+        let active = document.active_layer;
+        ui.separator();
+        let count = document.layers.len();
+        let mut layers = None;
+        ui.horizontal_wrapped(|ui| {
+            if ui.button("➕").on_hover_text("New layer").clicked() {
+                let (width, height) = document.size();
+                let new_layer = Layer::new(
+                    format!("Layer {count}"),
+                    DynamicImage::new(width, height, ColorType::Rgba8),
+                );
+                document.add_layer(new_layer);
+            }
+            if ui.button("⎘").on_hover_text("Duplicate layer").clicked() {
+                let mut new_layer = document.layer().clone();
+                new_layer.props.name = format!("{} copy", new_layer.props.name);
+                document.add_layer(new_layer);
+            }
+            if ui
+                .add_enabled(count > 1, egui::Button::new("🗑"))
+                .on_hover_text("Delete layer")
+                .clicked()
+            {
+                let mut new_layers = document.layers.clone();
+                new_layers.remove(active);
+                layers = Some((new_layers, active.saturating_sub(1)));
+            }
+            if ui
+                .add_enabled(active + 1 < count, egui::Button::new("⬆"))
+                .on_hover_text("Move layer up")
+                .clicked()
+            {
+                let mut new_layers = document.layers.clone();
+                new_layers.swap(active, active + 1);
+                layers = Some((new_layers, active + 1));
+            }
+            if ui
+                .add_enabled(active > 0, egui::Button::new("⬇"))
+                .on_hover_text("Move layer down")
+                .clicked()
+            {
+                let mut new_layers = document.layers.clone();
+                new_layers.swap(active, active - 1);
+                layers = Some((new_layers, active - 1));
+            }
+            if ui
+                .add_enabled(active > 0, egui::Button::new("Merge down"))
+                .clicked()
+            {
+                let mut new_layers = document.layers.clone();
+                let top = new_layers.remove(active);
+                new_layers[active - 1].merge(&top);
+                layers = Some((new_layers, active - 1));
+            }
+            if ui
+                .add_enabled(count > 1, egui::Button::new("Flatten"))
+                .clicked()
+            {
+                layers = Some((vec![Layer::new("Background", document.composite())], 0));
+            }
+        });
+        if let Some((new_layers, new_active)) = layers {
+            document.set_layers(new_layers, new_active);
+        }
+        ui.separator();
+        // top layer first
+        for idx in (0..document.layers.len()).rev() {
+            ui.horizontal(|ui| {
+                let mut visible = document.layers[idx].props.visible;
+                if ui.checkbox(&mut visible, "").changed() {
+                    let mut props = document.layers[idx].props.clone();
+                    props.visible = visible;
+                    document.set_layer_props(idx, props, false);
+                }
+                let layer = &document.layers[idx];
+                let text = format!(
+                    "{} ({}x{})",
+                    layer.props.name,
+                    layer.img.width(),
+                    layer.img.height()
+                );
+                if ui
+                    .selectable_label(idx == document.active_layer, text)
+                    .clicked()
+                {
+                    document.active_layer = idx;
+                }
+            });
+        }
+    }
+}
+
+/// Properties of the active layer
+fn layer_props_ui(ui: &mut egui::Ui, document: &mut Document) {
+    let mut props = document.layer().props.clone();
+    let mut merge = false;
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        let response = ui.text_edit_singleline(&mut props.name);
+        // the keystrokes of one edit of the name are one history entry
+        let editing = response.id.with("editing");
+        if response.changed() {
+            merge |= ui.data(|data| data.get_temp(editing).unwrap_or(false));
+            ui.data_mut(|data| data.insert_temp(editing, true));
+        }
+        if !response.has_focus() {
+            ui.data_mut(|data| data.remove::<bool>(editing));
+        }
+    });
+    ui.checkbox(&mut props.visible, "Visible");
+    let response = ui.add(egui::Slider::new(&mut props.opacity, 0.0..=1.0).text("Opacity"));
+    merge |= response.dragged() && !response.drag_started();
+    egui::ComboBox::from_label("Blend mode")
+        .selected_text(props.blend.to_string())
+        .show_ui(ui, |ui| {
+            for mode in BlendMode::ALL {
+                ui.selectable_value(&mut props.blend, mode, mode.to_string());
+            }
+        });
+    ui.horizontal(|ui| {
+        ui.label("Offset");
+        let response_x = ui.add(egui::DragValue::new(&mut props.offset.0));
+        let response_y = ui.add(egui::DragValue::new(&mut props.offset.1));
+        merge |= (response_x.dragged() && !response_x.drag_started())
+            || (response_y.dragged() && !response_y.drag_started());
+    });
+    if props != document.layer().props {
+        document.set_layer_props(document.active_layer, props, merge);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba32FImage;
+
+    /// Layer of one color
+    fn plain(width: u32, height: u32, color: [f32; 4]) -> Layer {
+        Layer::new(
+            "plain",
+            DynamicImage::ImageRgba32F(Rgba32FImage::from_pixel(width, height, Rgba(color))),
+        )
+    }
+
+    /// Pixels of the composite of layers on a canvas
+    fn composite_pixels(layers: &[Layer], width: u32, height: u32) -> Vec<[f32; 4]> {
+        composite(layers, width, height, ColorType::Rgba32F)
+            .to_rgba32f()
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect()
+    }
+
+    /// Check that two colors are equal, with rounding errors
+    fn assert_color(actual: [f32; 4], expected: [f32; 4]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(actual, expected)| (actual - expected).abs() < 1e-6),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn blend_modes() {
+        let bottom = plain(1, 1, [0.25, 0.5, 0.75, 1.0]);
+        let expected = [
+            (BlendMode::Normal, [0.5, 0.25, 1.0]),
+            (BlendMode::Multiply, [0.125, 0.125, 0.75]),
+            (BlendMode::Screen, [0.625, 0.625, 1.0]),
+            (BlendMode::Overlay, [0.25, 0.25, 1.0]),
+            (BlendMode::Darken, [0.25, 0.25, 0.75]),
+            (BlendMode::Lighten, [0.5, 0.5, 1.0]),
+            (BlendMode::Add, [0.75, 0.75, 1.0]),
+            (BlendMode::Difference, [0.25, 0.25, 0.25]),
+        ];
+        assert_eq!(expected.len(), BlendMode::ALL.len());
+        for (blend, [r, g, b]) in expected {
+            let mut top = plain(1, 1, [0.5, 0.25, 1.0, 1.0]);
+            top.props.blend = blend;
+            let pixels = composite_pixels(&[bottom.clone(), top], 1, 1);
+            assert_color(pixels[0], [r, g, b, 1.0]);
+        }
+    }
+
+    #[test]
+    fn opacity_and_visibility() {
+        let bottom = plain(1, 1, [0.25, 0.5, 0.75, 1.0]);
+        let mut top = plain(1, 1, [0.5, 0.25, 1.0, 1.0]);
+        top.props.opacity = 0.5;
+        let pixels = composite_pixels(&[bottom.clone(), top.clone()], 1, 1);
+        assert_color(pixels[0], [0.375, 0.375, 0.875, 1.0]);
+        // on a transparent pixel, the color is kept with less alpha
+        let pixels = composite_pixels(&[plain(1, 1, [0.0; 4]), top.clone()], 1, 1);
+        assert_color(pixels[0], [0.5, 0.25, 1.0, 0.5]);
+        top.props.visible = false;
+        let pixels = composite_pixels(&[bottom.clone(), top], 1, 1);
+        assert_color(pixels[0], [0.25, 0.5, 0.75, 1.0]);
+    }
+
+    #[test]
+    fn offset_layer_partly_off_canvas() {
+        let black = [0.0, 0.0, 0.0, 1.0];
+        let white = [1.0; 4];
+        let mut right = plain(2, 1, white);
+        right.props.offset = (3, 0);
+        let pixels = composite_pixels(&[plain(4, 1, black), right], 4, 1);
+        assert_eq!(pixels, vec![black, black, black, white]);
+        let mut left = plain(2, 2, white);
+        left.props.offset = (-1, 1);
+        let pixels = composite_pixels(&[plain(4, 1, black), left], 3, 2);
+        // the bottom layer is smaller than the canvas
+        assert_eq!(pixels, vec![black, black, black, white, [0.0; 4], [0.0; 4]]);
+    }
+
+    #[test]
+    fn layer_region_with_offset() {
+        let mut layer = Layer::new("layer", DynamicImage::new_rgb8(10, 10));
+        layer.props.offset = (-5, -5);
+        assert_eq!(
+            layer.to_layer_region(Region::new(0, 0, 8, 20)),
+            Region::new(5, 5, 5, 5)
+        );
+        assert_eq!(
+            layer.to_layer_region(Region::new(u32::MAX - 1, 0, 100, 3)),
+            Region::new(10, 5, 0, 3)
+        );
+    }
+
+    #[test]
+    fn empty_layer_stack_is_ignored() {
+        let mut document = Document::new("a.png".into(), DynamicImage::new_rgb8(2, 2));
+        document.set_layers(Vec::new(), 0);
+        assert_eq!(document.layers.len(), 1);
+        assert!(!document.history.can_undo());
+        assert_eq!(document.layer().props.name, "Background");
+    }
+}
@@ -20,13 +20,32 @@
 #![allow(clippy::multiple_crate_versions)]
 
 mod app;
+mod brush;
+mod bucket;
 mod central_panel;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cli;
 mod document;
 mod edit_mode;
+mod exif_editor;
+mod export;
+mod floating;
+mod formats;
+mod history;
+mod layer;
+mod magic_wand;
+mod metadata;
 mod operations;
+pub mod ops;
 mod panels;
+mod perspective;
+mod preview;
+mod project;
+mod recording;
 mod selection;
+mod shapes;
 mod side_panel;
+mod text;
 mod top_panel;
 
 pub use app::TarsierApp;
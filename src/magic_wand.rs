@@ -0,0 +1,452 @@
+//! Magic wand: select the pixels of a similar color
+
+use bladvak::eframe::egui::{self, Modifiers};
+use image::{DynamicImage, GrayImage, Luma, Rgba32FImage};
+
+/// Color space used to compare the colors
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum ColorDistance {
+    /// Euclidean distance of the RGB values
+    #[default]
+    Rgb,
+    /// Distance in the HSV cone, the hue matters less for unsaturated colors
+    Hsv,
+    /// CIE76 distance in the Lab space, close to the perceived difference
+    Lab,
+}
+
+impl ColorDistance {
+    /// All the distances
+    const ALL: [Self; 3] = [Self::Rgb, Self::Hsv, Self::Lab];
+
+    /// Coordinates of a color in the space of the distance, scaled so that the maximum distance is about 1
+    fn coordinates(self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            Self::Rgb => rgb.map(|c| c / 3_f32.sqrt()),
+            Self::Hsv => hsv_cone(rgb),
+            Self::Lab => lab(rgb),
+        }
+    }
+}
+
+/// Position of a color in the HSV cone: the chroma is the radius, the value the height
+fn hsv_cone([red, green, blue]: [f32; 3]) -> [f32; 3] {
+    let max = red.max(green).max(blue);
+    let chroma = max - red.min(green).min(blue);
+    let sector = if chroma <= 0.0 {
+        0.0
+    } else if red >= green && red >= blue {
+        ((green - blue) / chroma).rem_euclid(6.0)
+    } else if green >= blue {
+        (blue - red) / chroma + 2.0
+    } else {
+        (red - green) / chroma + 4.0
+    };
+    let hue = sector * std::f32::consts::FRAC_PI_3;
+    [chroma * hue.cos() / 2.0, chroma * hue.sin() / 2.0, max]
+}
+
+/// CIE Lab coordinates of a sRGB color, with the D65 white point, divided by 100
+fn lab(rgb: [f32; 3]) -> [f32; 3] {
+    let [red, green, blue] = rgb.map(|c| {
+        if c <= 0.040_45 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let f = |t: f32| {
+        if t > 0.008_856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let fx = f((0.412_456_4 * red + 0.357_576_1 * green + 0.180_437_5 * blue) / 0.950_47);
+    let fy = f(0.212_672_9 * red + 0.715_152_2 * green + 0.072_175 * blue);
+    let fz = f((0.019_333_9 * red + 0.119_192 * green + 0.950_304_1 * blue) / 1.088_83);
+    // a difference of 100 is about the difference between black and white
+    [
+        (116.0 * fy - 16.0) / 100.0,
+        5.0 * (fx - fy),
+        2.0 * (fy - fz),
+    ]
+}
+
+impl std::fmt::Display for ColorDistance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Rgb => write!(f, "RGB"),
+            Self::Hsv => write!(f, "HSV"),
+            Self::Lab => write!(f, "Lab"),
+        }
+    }
+}
+
+/// How a new selection is combined with the current one
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum SelectionCombine {
+    /// Replace the current selection
+    #[default]
+    Replace,
+    /// Add to the current selection
+    Add,
+    /// Remove from the current selection
+    Subtract,
+    /// Keep the pixels in both selections
+    Intersect,
+}
+
+impl SelectionCombine {
+    /// All the combinations
+    const ALL: [Self; 4] = [Self::Replace, Self::Add, Self::Subtract, Self::Intersect];
+
+    /// Combination from the keyboard modifiers: shift adds, alt subtracts, both intersect
+    pub(crate) fn from_modifiers(modifiers: Modifiers, default: Self) -> Self {
+        match (modifiers.shift, modifiers.alt) {
+            (true, true) => Self::Intersect,
+            (true, false) => Self::Add,
+            (false, true) => Self::Subtract,
+            (false, false) => default,
+        }
+    }
+
+    /// Combine a new mask into the current one
+    pub(crate) fn apply(self, current: &mut GrayImage, new: &GrayImage) {
+        for (pixel, new_pixel) in current.pixels_mut().zip(new.pixels()) {
+            let (value, new_value) = (pixel[0], new_pixel[0]);
+            pixel[0] = match self {
+                Self::Replace => new_value,
+                Self::Add => value.max(new_value),
+                Self::Subtract => value.min(255 - new_value),
+                Self::Intersect => value.min(new_value),
+            };
+        }
+    }
+}
+
+impl std::fmt::Display for SelectionCombine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Replace => write!(f, "Replace"),
+            Self::Add => write!(f, "Add"),
+            Self::Subtract => write!(f, "Subtract"),
+            Self::Intersect => write!(f, "Intersect"),
+        }
+    }
+}
+
+/// Magic wand settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct MagicWand {
+    /// Maximum color difference, in percent
+    pub(crate) tolerance: f32,
+    /// Only select the pixels connected to the clicked one
+    pub(crate) contiguous: bool,
+    /// Color space of the difference
+    pub(crate) distance: ColorDistance,
+    /// Combination with the current selection
+    pub(crate) combine: SelectionCombine,
+}
+
+impl Default for MagicWand {
+    fn default() -> Self {
+        Self {
+            tolerance: 15.0,
+            contiguous: true,
+            distance: ColorDistance::default(),
+            combine: SelectionCombine::default(),
+        }
+    }
+}
+
+impl MagicWand {
+    /// Settings ui
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.tolerance, 0.0..=100.0).text("Tolerance"));
+        ui.checkbox(&mut self.contiguous, "Contiguous")
+            .on_hover_text("Only select the pixels connected to the clicked one");
+        ui.horizontal(|ui| {
+            ui.label("Distance:");
+            for distance in ColorDistance::ALL {
+                ui.selectable_value(&mut self.distance, distance, distance.to_string());
+            }
+        });
+        ui.horizontal(|ui| {
+            for combine in SelectionCombine::ALL {
+                ui.selectable_value(&mut self.combine, combine, combine.to_string());
+            }
+        });
+        ui.label("Shift: add, Alt: subtract, Shift+Alt: intersect");
+    }
+
+    /// Mask of the pixels similar to the pixel at (`x`, `y`)
+    pub(crate) fn select(self, img: &DynamicImage, x: u32, y: u32) -> GrayImage {
+        let img = img.to_rgba32f();
+        let (width, height) = img.dimensions();
+        let Some(seed) = img.get_pixel_checked(x, y) else {
+            return GrayImage::new(width, height);
+        };
+        let seed = Features::new(self.distance, seed.0);
+        let tolerance = self.tolerance / 100.0;
+        let matches = |x: u32, y: u32| {
+            let pixel = Features::new(self.distance, img.get_pixel(x, y).0);
+            seed.distance(&pixel) <= tolerance
+        };
+        if self.contiguous {
+            flood_fill(width, height, (x, y), false, matches)
+        } else {
+            global_mask(&img, matches)
+        }
+    }
+}
+
+/// Color of a pixel in the space of a distance, with its alpha
+pub(crate) struct Features {
+    /// Coordinates of the color
+    color: [f32; 3],
+    /// Alpha
+    alpha: f32,
+}
+
+impl Features {
+    /// Features of a pixel
+    pub(crate) fn new(distance: ColorDistance, [r, g, b, a]: [f32; 4]) -> Self {
+        Self {
+            color: distance.coordinates([r, g, b].map(|c| c.clamp(0.0, 1.0))),
+            alpha: a,
+        }
+    }
+
+    /// Difference between two pixels, from 0 to about 1
+    pub(crate) fn distance(&self, other: &Self) -> f32 {
+        let color = self
+            .color
+            .iter()
+            .zip(other.color)
+            .map(|(a, b)| (a - b).powi(2))
+            .sum::<f32>()
+            .sqrt();
+        color.max((self.alpha - other.alpha).abs())
+    }
+}
+
+/// Mask of all the pixels matching
+fn global_mask(img: &Rgba32FImage, matches: impl Fn(u32, u32) -> bool) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        Luma([if matches(x, y) { 255 } else { 0 }])
+    })
+}
+
+/// Mask of the pixels matching and connected to `seed`, with a scanline fill
+pub(crate) fn flood_fill(
+    width: u32,
+    height: u32,
+    seed: (u32, u32),
+    eight_connected: bool,
+    matches: impl Fn(u32, u32) -> bool,
+) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+    if seed.0 >= width || seed.1 >= height || !matches(seed.0, seed.1) {
+        return mask;
+    }
+    let is_free = |mask: &GrayImage, x: u32, y: u32| mask.get_pixel(x, y)[0] == 0 && matches(x, y);
+    let mut stack = vec![seed];
+    while let Some((x, y)) = stack.pop() {
+        if mask.get_pixel(x, y)[0] != 0 {
+            continue;
+        }
+        let mut left = x;
+        while left > 0 && is_free(&mask, left - 1, y) {
+            left -= 1;
+        }
+        let mut right = x;
+        while right + 1 < width && is_free(&mask, right + 1, y) {
+            right += 1;
+        }
+        for fill_x in left..=right {
+            mask.put_pixel(fill_x, y, Luma([255]));
+        }
+        // with 8-connectivity, the diagonal neighbours of the span are connected too
+        let (scan_left, scan_right) = if eight_connected {
+            (left.saturating_sub(1), (right + 1).min(width - 1))
+        } else {
+            (left, right)
+        };
+        let rows = [y.checked_sub(1), (y + 1 < height).then_some(y + 1)];
+        for next_y in rows.into_iter().flatten() {
+            let mut in_span = false;
+            for next_x in scan_left..=scan_right {
+                if is_free(&mask, next_x, next_y) {
+                    if !in_span {
+                        stack.push((next_x, next_y));
+                        in_span = true;
+                    }
+                } else {
+                    in_span = false;
+                }
+            }
+        }
+    }
+    mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    /// Mask from rows of '#' (selected) and '.' (not selected)
+    fn mask(rows: &[&str]) -> GrayImage {
+        #[allow(clippy::cast_possible_truncation)]
+        let (width, height) = (rows[0].len() as u32, rows.len() as u32);
+        GrayImage::from_fn(width, height, |x, y| {
+            let selected = rows[y as usize].as_bytes()[x as usize] == b'#';
+            Luma([if selected { 255 } else { 0 }])
+        })
+    }
+
+    /// Flood fill of the '#' of a pattern
+    fn fill(rows: &[&str], seed: (u32, u32), eight_connected: bool) -> GrayImage {
+        let pattern = mask(rows);
+        let (width, height) = pattern.dimensions();
+        flood_fill(width, height, seed, eight_connected, |x, y| {
+            pattern.get_pixel(x, y)[0] == 255
+        })
+    }
+
+    /// Distance between two colors
+    fn distance(distance: ColorDistance, a: [f32; 3], b: [f32; 3]) -> f32 {
+        let features = |[r, g, b]: [f32; 3]| Features::new(distance, [r, g, b, 1.0]);
+        features(a).distance(&features(b))
+    }
+
+    #[test]
+    fn flood_fill_connectivity() {
+        let diagonal = ["#...", ".#..", "..#.", "...#"];
+        assert_eq!(
+            fill(&diagonal, (0, 0), false),
+            mask(&["#...", "....", "....", "...."])
+        );
+        assert_eq!(fill(&diagonal, (0, 0), true), mask(&diagonal));
+        // the fill goes back up around the walls
+        let spiral = ["#####", "....#", "###.#", "#...#", "#####"];
+        assert_eq!(fill(&spiral, (0, 2), false), mask(&spiral));
+        let islands = ["##..", "##..", "...#", "..##"];
+        assert_eq!(
+            fill(&islands, (3, 3), false),
+            mask(&["....", "....", "...#", "..##"])
+        );
+    }
+
+    #[test]
+    fn flood_fill_seeds() {
+        let full = ["###", "###"];
+        for seed in [(0, 0), (2, 0), (0, 1), (2, 1)] {
+            assert_eq!(fill(&full, seed, false), mask(&full));
+        }
+        let empty = mask(&["...", "..."]);
+        assert_eq!(fill(&full, (3, 0), false), empty);
+        assert_eq!(fill(&full, (0, 2), true), empty);
+        assert_eq!(fill(&["#..", "..."], (1, 1), true), empty);
+        assert_eq!(
+            flood_fill(0, 0, (0, 0), true, |_, _| true).dimensions(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn select_tolerance() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| {
+            [Rgb([100, 100, 100]), Rgb([104, 100, 100]), Rgb([0, 0, 0])][x as usize]
+        }));
+        let wand = |tolerance, contiguous| MagicWand {
+            tolerance,
+            contiguous,
+            ..MagicWand::default()
+        };
+        assert_eq!(wand(0.0, true).select(&img, 0, 0), mask(&["#.."]));
+        assert_eq!(wand(5.0, true).select(&img, 0, 0), mask(&["##."]));
+        assert_eq!(wand(100.0, true).select(&img, 0, 0), mask(&["###"]));
+        assert_eq!(wand(100.0, true).select(&img, 3, 0), mask(&["..."]));
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| {
+            [Rgb([9, 9, 9]), Rgb([255, 0, 0]), Rgb([9, 9, 9])][x as usize]
+        }));
+        assert_eq!(wand(0.0, true).select(&img, 0, 0), mask(&["#.."]));
+        assert_eq!(wand(0.0, false).select(&img, 0, 0), mask(&["#.#"]));
+    }
+
+    #[test]
+    fn distances() {
+        let (black, white) = ([0.0; 3], [1.0; 3]);
+        for space in ColorDistance::ALL {
+            assert!(distance(space, [0.2, 0.5, 0.7], [0.2, 0.5, 0.7]).abs() < 1e-6);
+            let extremes = distance(space, black, white);
+            assert!((extremes - 1.0).abs() < 0.01, "{space}: {extremes}");
+        }
+        assert!(
+            (distance(ColorDistance::Rgb, black, [1.0, 0.0, 0.0]) - 1.0 / 3_f32.sqrt()).abs()
+                < 1e-6
+        );
+        // the hue of dark colors matters less in the HSV cone
+        let (dark_red, dark_green) = ([0.1, 0.0, 0.0], [0.0, 0.1, 0.0]);
+        let (red, green) = ([1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        assert!(
+            distance(ColorDistance::Hsv, dark_red, dark_green)
+                < distance(ColorDistance::Hsv, red, green) / 5.0
+        );
+        // in Lab, a green is lighter than a blue of the same RGB value
+        assert!(
+            distance(ColorDistance::Lab, black, [0.0, 0.5, 0.0])
+                > distance(ColorDistance::Lab, black, [0.0, 0.0, 0.5])
+        );
+        // the alpha difference counts
+        let transparent = Features::new(ColorDistance::Rgb, [0.0, 0.0, 0.0, 0.0]);
+        let opaque = Features::new(ColorDistance::Rgb, [0.0, 0.0, 0.0, 1.0]);
+        assert!((transparent.distance(&opaque) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn combine_masks() {
+        let current = mask(&["##.."]);
+        let new = mask(&["#.#."]);
+        let combined = |combine: SelectionCombine| {
+            let mut mask = current.clone();
+            combine.apply(&mut mask, &new);
+            mask
+        };
+        assert_eq!(combined(SelectionCombine::Replace), mask(&["#.#."]));
+        assert_eq!(combined(SelectionCombine::Add), mask(&["###."]));
+        assert_eq!(combined(SelectionCombine::Subtract), mask(&[".#.."]));
+        assert_eq!(combined(SelectionCombine::Intersect), mask(&["#..."]));
+        let mut partial = GrayImage::from_pixel(1, 1, Luma([200]));
+        SelectionCombine::Subtract.apply(&mut partial, &GrayImage::from_pixel(1, 1, Luma([100])));
+        assert_eq!(partial.get_pixel(0, 0)[0], 155);
+    }
+
+    #[test]
+    fn combine_from_modifiers() {
+        let modifiers = |shift, alt| Modifiers {
+            shift,
+            alt,
+            ..Modifiers::NONE
+        };
+        let default = SelectionCombine::Intersect;
+        assert_eq!(
+            SelectionCombine::from_modifiers(modifiers(false, false), SelectionCombine::Add),
+            SelectionCombine::Add
+        );
+        assert_eq!(
+            SelectionCombine::from_modifiers(modifiers(true, false), default),
+            SelectionCombine::Add
+        );
+        assert_eq!(
+            SelectionCombine::from_modifiers(modifiers(false, true), default),
+            SelectionCombine::Subtract
+        );
+        assert_eq!(
+            SelectionCombine::from_modifiers(modifiers(true, true), SelectionCombine::Replace),
+            SelectionCombine::Intersect
+        );
+    }
+}
@@ -5,5 +5,9 @@ use bladvak::app::Bladvak;
 use tarsier::TarsierApp;
 
 fn main() -> bladvak::MainResult {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(exit_code) = tarsier::cli::run_from_args(&std::env::args().collect::<Vec<_>>()) {
+        std::process::exit(exit_code);
+    }
     Bladvak::<TarsierApp>::bladvak_main()
 }
@@ -0,0 +1,1006 @@
+//! Exif metadata: keep it up to date and write it on save
+
+use exif::experimental::Writer;
+use exif::{Context, Exif, Field, In, Tag, Value};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::io::Cursor;
+
+use crate::ops::Operation;
+
+/// Formats where the exif can be written
+pub(crate) const EXIF_FORMATS: &[ImageFormat] = &[
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::WebP,
+    ImageFormat::Tiff,
+    ImageFormat::Avif,
+];
+
+/// `ExtraSamples` TIFF tag, unknown to kamadak-exif
+const EXTRA_SAMPLES: Tag = Tag(Context::Tiff, 0x152);
+
+/// `SampleFormat` TIFF tag, unknown to kamadak-exif
+const SAMPLE_FORMAT: Tag = Tag(Context::Tiff, 0x153);
+
+/// Tags describing the pixels of a TIFF image, they are rebuilt from the saved image
+const IMAGE_STRUCTURE_TAGS: &[Tag] = &[
+    Tag::ImageWidth,
+    Tag::ImageLength,
+    Tag::BitsPerSample,
+    Tag::Compression,
+    Tag::PhotometricInterpretation,
+    Tag::SamplesPerPixel,
+    Tag::RowsPerStrip,
+    Tag::PlanarConfiguration,
+    EXTRA_SAMPLES,
+    SAMPLE_FORMAT,
+];
+
+/// Orientation of the pixels: a horizontal flip (if any) then clockwise quarter turns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Orientation {
+    /// Number of clockwise quarter turns
+    quarter_turns: u8,
+    /// Horizontal flip, applied first
+    flip: bool,
+}
+
+impl Orientation {
+    /// Orientation from the value of the exif Orientation tag
+    fn from_exif(value: u32) -> Self {
+        let (quarter_turns, flip) = match value {
+            2 => (0, true),
+            3 => (2, false),
+            4 => (2, true),
+            5 => (3, true),
+            6 => (1, false),
+            7 => (1, true),
+            8 => (3, false),
+            _ => (0, false),
+        };
+        Self {
+            quarter_turns,
+            flip,
+        }
+    }
+
+    /// Value of the exif Orientation tag
+    fn to_exif(self) -> u16 {
+        match (self.quarter_turns % 4, self.flip) {
+            (0, true) => 2,
+            (2, false) => 3,
+            (2, true) => 4,
+            (3, true) => 5,
+            (1, false) => 6,
+            (1, true) => 7,
+            (3, false) => 8,
+            _ => 1,
+        }
+    }
+
+    /// Orientation change done by an operation on the whole image
+    fn from_operation(operation: &Operation) -> Option<Self> {
+        let (quarter_turns, flip) = match operation {
+            Operation::Rotate90 => (1, false),
+            Operation::Rotate180 => (2, false),
+            Operation::Rotate270 => (3, false),
+            Operation::FlipHorizontal => (0, true),
+            Operation::FlipVertical => (2, true),
+            _ => return None,
+        };
+        Some(Self {
+            quarter_turns,
+            flip,
+        })
+    }
+
+    /// `self` applied after `other`
+    fn after(self, other: Self) -> Self {
+        // a flip reverses the direction of the rotations done before it
+        let other_turns = if self.flip {
+            4 - other.quarter_turns % 4
+        } else {
+            other.quarter_turns
+        };
+        Self {
+            quarter_turns: (self.quarter_turns + other_turns) % 4,
+            flip: self.flip != other.flip,
+        }
+    }
+
+    /// Inverse orientation
+    fn inverse(self) -> Self {
+        if self.flip {
+            self
+        } else {
+            Self {
+                quarter_turns: (4 - self.quarter_turns % 4) % 4,
+                flip: false,
+            }
+        }
+    }
+}
+
+/// Value of the Orientation tag, 1 if missing
+pub(crate) fn orientation(exif: &Exif) -> u32 {
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|field| field.value.get_uint(0))
+        .unwrap_or(1)
+}
+
+/// Turn the image upright with the exif orientation, returns the exif with the orientation reset
+/// # Errors
+/// Fails if the exif cannot be written, the image is not changed
+pub(crate) fn auto_orient(img: &mut DynamicImage, exif: &Exif) -> Result<Option<Exif>, String> {
+    let Some(orientation) = u8::try_from(orientation(exif))
+        .ok()
+        .and_then(image::metadata::Orientation::from_exif)
+        .filter(|orientation| *orientation != image::metadata::Orientation::NoTransforms)
+    else {
+        return Ok(None);
+    };
+    let replace = [field(Tag::Orientation, Value::Short(vec![1]))];
+    let upright = parse(rewrite(
+        exif,
+        &replace,
+        |_| true,
+        exif.little_endian(),
+        None,
+    )?)?;
+    img.apply_orientation(orientation);
+    Ok(Some(upright))
+}
+
+/// Parse exif from its raw data
+fn parse(data: Vec<u8>) -> Result<Exif, String> {
+    exif::Reader::new()
+        .read_raw(data)
+        .map_err(|e| format!("Invalid exif: {e}"))
+}
+
+/// Write exif fields, with the strips of the image if any
+fn write<'a>(
+    fields: impl IntoIterator<Item = &'a Field>,
+    little_endian: bool,
+    strips: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let strips = strips.map(|strip| [strip]);
+    if let Some(strips) = &strips {
+        writer.set_strips(strips, In::PRIMARY);
+    }
+    let mut data = Cursor::new(Vec::new());
+    writer
+        .write(&mut data, little_endian)
+        .map_err(|e| format!("Cannot write exif: {e}"))?;
+    Ok(data.into_inner())
+}
+
+/// Write the fields of the primary image, with some fields replaced
+fn rewrite<F>(
+    exif: &Exif,
+    replace: &[Field],
+    keep: F,
+    little_endian: bool,
+    strips: Option<&[u8]>,
+) -> Result<Vec<u8>, String>
+where
+    F: Fn(&Field) -> bool,
+{
+    // the thumbnail (second IFD) does not match the image anymore
+    let kept = exif.fields().filter(|field| {
+        field.ifd_num == In::PRIMARY
+            && !matches!(field.value, Value::Unknown(..))
+            && !replace.iter().any(|new| new.tag == field.tag)
+            && keep(field)
+    });
+    write(kept.chain(replace), little_endian, strips)
+}
+
+/// Field of the primary image
+fn field(tag: Tag, value: Value) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value,
+    }
+}
+
+/// Dimension fields of an image
+fn dimension_fields(exif: &Exif, width: u32, height: u32) -> Vec<Field> {
+    let mut fields = vec![
+        field(Tag::PixelXDimension, Value::Long(vec![width])),
+        field(Tag::PixelYDimension, Value::Long(vec![height])),
+    ];
+    if exif.get_field(Tag::ImageWidth, In::PRIMARY).is_some() {
+        fields.push(field(Tag::ImageWidth, Value::Long(vec![width])));
+    }
+    if exif.get_field(Tag::ImageLength, In::PRIMARY).is_some() {
+        fields.push(field(Tag::ImageLength, Value::Long(vec![height])));
+    }
+    fields
+}
+
+/// Exif after an operation on the whole image, `None` if the operation does not change it
+/// # Errors
+/// Fails if the exif cannot be written
+pub(crate) fn after_operation(exif: &Exif, operation: &Operation) -> Result<Option<Exif>, String> {
+    let Some(change) = Orientation::from_operation(operation) else {
+        return Ok(None);
+    };
+    // what a viewer shows gets the same change as the pixels
+    let current = Orientation::from_exif(orientation(exif));
+    let new = change.after(current).after(change.inverse());
+    if new == current {
+        return Ok(None);
+    }
+    let replace = [field(Tag::Orientation, Value::Short(vec![new.to_exif()]))];
+    let data = rewrite(exif, &replace, |_| true, exif.little_endian(), None)?;
+    parse(data).map(Some)
+}
+
+/// Raw exif to save with an image, with its dimensions updated
+/// # Errors
+/// Fails if the exif cannot be written
+pub(crate) fn for_image(exif: &Exif, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let replace = dimension_fields(exif, width, height);
+    rewrite(exif, &replace, |_| true, exif.little_endian(), None)
+}
+
+/// Write an uncompressed TIFF file with the exif
+/// # Errors
+/// Fails if the image cannot be stored in a TIFF file
+pub(crate) fn write_tiff(img: &DynamicImage, exif: &Exif) -> Result<Vec<u8>, String> {
+    let (width, height) = img.dimensions();
+    let color_type = img.color();
+    let channels = u16::from(color_type.channel_count());
+    let bits = u16::from(color_type.bytes_per_pixel()) * 8 / channels;
+    let mut replace = dimension_fields(exif, width, height);
+    replace.extend([
+        field(Tag::ImageWidth, Value::Long(vec![width])),
+        field(Tag::ImageLength, Value::Long(vec![height])),
+        field(
+            Tag::BitsPerSample,
+            Value::Short(vec![bits; usize::from(channels)]),
+        ),
+        // no compression
+        field(Tag::Compression, Value::Short(vec![1])),
+        // 2 is RGB, 1 is gray with black as zero
+        field(
+            Tag::PhotometricInterpretation,
+            Value::Short(vec![if color_type.has_color() { 2 } else { 1 }]),
+        ),
+        field(Tag::SamplesPerPixel, Value::Short(vec![channels])),
+        field(Tag::RowsPerStrip, Value::Long(vec![height])),
+        field(Tag::PlanarConfiguration, Value::Short(vec![1])),
+    ]);
+    if color_type.has_alpha() {
+        // unassociated alpha
+        replace.push(field(EXTRA_SAMPLES, Value::Short(vec![2])));
+    }
+    if matches!(
+        img,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    ) {
+        // IEEE floating point
+        replace.push(field(
+            SAMPLE_FORMAT,
+            Value::Short(vec![3; usize::from(channels)]),
+        ));
+    }
+    // the samples are stored in the native byte order
+    rewrite(
+        exif,
+        &replace,
+        |field| !IMAGE_STRUCTURE_TAGS.contains(&field.tag),
+        cfg!(target_endian = "little"),
+        Some(img.as_bytes()),
+    )
+}
+
+/// Tags written by the encoder, or updated on save
+const AUTOMATIC_TAGS: &[Tag] = &[
+    Tag::ExifIFDPointer,
+    Tag::GPSInfoIFDPointer,
+    Tag::InteropIFDPointer,
+    Tag::StripOffsets,
+    Tag::StripByteCounts,
+    Tag::TileOffsets,
+    Tag::TileByteCounts,
+    Tag::JPEGInterchangeFormat,
+    Tag::JPEGInterchangeFormatLength,
+    Tag::ImageWidth,
+    Tag::ImageLength,
+    Tag::PixelXDimension,
+    Tag::PixelYDimension,
+];
+
+/// Type of an exif value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueKind {
+    /// 8-bit unsigned integers
+    Byte,
+    /// ASCII text
+    Ascii,
+    /// 16-bit unsigned integers
+    Short,
+    /// 32-bit unsigned integers
+    Long,
+    /// Unsigned rationals
+    Rational,
+    /// 8-bit signed integers
+    SByte,
+    /// Raw bytes
+    Undefined,
+    /// 16-bit signed integers
+    SShort,
+    /// 32-bit signed integers
+    SLong,
+    /// Signed rationals
+    SRational,
+    /// 32-bit floating point numbers
+    Float,
+    /// 64-bit floating point numbers
+    Double,
+}
+
+impl ValueKind {
+    /// Type of a value, `None` if unknown
+    pub(crate) fn of(value: &Value) -> Option<Self> {
+        Some(match value {
+            Value::Byte(_) => Self::Byte,
+            Value::Ascii(_) => Self::Ascii,
+            Value::Short(_) => Self::Short,
+            Value::Long(_) => Self::Long,
+            Value::Rational(_) => Self::Rational,
+            Value::SByte(_) => Self::SByte,
+            Value::Undefined(..) => Self::Undefined,
+            Value::SShort(_) => Self::SShort,
+            Value::SLong(_) => Self::SLong,
+            Value::SRational(_) => Self::SRational,
+            Value::Float(_) => Self::Float,
+            Value::Double(_) => Self::Double,
+            Value::Unknown(..) => return None,
+        })
+    }
+
+    /// Hint about the expected text
+    pub(crate) fn hint(self) -> &'static str {
+        match self {
+            Self::Ascii => "ASCII text",
+            Self::Undefined => "hexadecimal bytes, e.g. 01 ff",
+            Self::Rational | Self::SRational => "rationals, e.g. 1/250, 2.8",
+            Self::Float | Self::Double => "decimal numbers, separated by commas",
+            _ => "integers, separated by commas",
+        }
+    }
+
+    /// Parse a value of this type from text
+    /// # Errors
+    /// Fails if the text is not a valid value of this type
+    pub(crate) fn parse(self, text: &str) -> Result<Value, String> {
+        let text = text.trim();
+        Ok(match self {
+            Self::Ascii => {
+                if !text.is_ascii() || text.contains('\0') {
+                    return Err("The text must be ASCII, without null character".to_string());
+                }
+                Value::Ascii(vec![text.as_bytes().to_vec()])
+            }
+            Self::Undefined => {
+                let bytes = text
+                    .split_whitespace()
+                    .map(|byte| u8::from_str_radix(byte, 16).ok())
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| format!("Expected {}", self.hint()))?;
+                Value::Undefined(bytes, 0)
+            }
+            Self::Byte => Value::Byte(self.parse_list(text, |item| item.parse().ok())?),
+            Self::Short => Value::Short(self.parse_list(text, |item| item.parse().ok())?),
+            Self::Long => Value::Long(self.parse_list(text, |item| item.parse().ok())?),
+            Self::SByte => Value::SByte(self.parse_list(text, |item| item.parse().ok())?),
+            Self::SShort => Value::SShort(self.parse_list(text, |item| item.parse().ok())?),
+            Self::SLong => Value::SLong(self.parse_list(text, |item| item.parse().ok())?),
+            Self::Float => Value::Float(self.parse_list(text, |item| item.parse().ok())?),
+            Self::Double => Value::Double(self.parse_list(text, |item| item.parse().ok())?),
+            Self::Rational => Value::Rational(self.parse_list(text, |item| {
+                let (num, denom) = parse_rational(item)?;
+                Some(exif::Rational {
+                    num: num.try_into().ok()?,
+                    denom: denom.try_into().ok()?,
+                })
+            })?),
+            Self::SRational => Value::SRational(self.parse_list(text, |item| {
+                let (num, denom) = parse_rational(item)?;
+                Some(exif::SRational {
+                    num: num.try_into().ok()?,
+                    denom: denom.try_into().ok()?,
+                })
+            })?),
+        })
+    }
+
+    /// Parse a list of numbers separated by commas
+    fn parse_list<T>(
+        self,
+        text: &str,
+        parse: impl Fn(&str) -> Option<T>,
+    ) -> Result<Vec<T>, String> {
+        let items = text
+            .split(',')
+            .map(|item| parse(item.trim()))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("Expected {}", self.hint()))?;
+        if items.is_empty() {
+            return Err("The value is empty".to_string());
+        }
+        Ok(items)
+    }
+}
+
+/// Parse a rational: `num/denom`, a decimal number or an integer
+fn parse_rational(text: &str) -> Option<(i64, i64)> {
+    let (num, denom) = if let Some((num, denom)) = text.split_once('/') {
+        (num.trim().parse().ok()?, denom.trim().parse().ok()?)
+    } else if let Some((int, frac)) = text.split_once('.') {
+        if frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        (
+            format!("{int}{frac}").parse().ok()?,
+            10_i64.pow(frac.len().try_into().ok()?),
+        )
+    } else {
+        (text.parse().ok()?, 1)
+    };
+    (denom != 0).then_some((num, denom))
+}
+
+/// Value as editable text, the opposite of [`ValueKind::parse`]
+pub(crate) fn value_text(value: &Value) -> String {
+    /// Join items with commas
+    fn join<T>(items: &[T], to_text: impl Fn(&T) -> String) -> String {
+        items.iter().map(to_text).collect::<Vec<_>>().join(", ")
+    }
+    match value {
+        Value::Byte(items) => join(items, u8::to_string),
+        Value::Ascii(items) => join(items, |item| String::from_utf8_lossy(item).into_owned()),
+        Value::Short(items) => join(items, u16::to_string),
+        Value::Long(items) => join(items, u32::to_string),
+        Value::Rational(items) => join(items, |r| format!("{}/{}", r.num, r.denom)),
+        Value::SByte(items) => join(items, i8::to_string),
+        Value::Undefined(bytes, _) => join(bytes, |byte| format!("{byte:02x}")).replace(',', ""),
+        Value::SShort(items) => join(items, i16::to_string),
+        Value::SLong(items) => join(items, i32::to_string),
+        Value::SRational(items) => join(items, |r| format!("{}/{}", r.num, r.denom)),
+        Value::Float(items) => join(items, f32::to_string),
+        Value::Double(items) => join(items, f64::to_string),
+        Value::Unknown(..) => String::new(),
+    }
+}
+
+/// Number of items in a value
+fn value_count(value: &Value) -> usize {
+    match value {
+        Value::Byte(items) | Value::Undefined(items, _) => items.len(),
+        Value::Ascii(items) => items.len(),
+        Value::Short(items) => items.len(),
+        Value::Long(items) => items.len(),
+        Value::Rational(items) => items.len(),
+        Value::SByte(items) => items.len(),
+        Value::SShort(items) => items.len(),
+        Value::SLong(items) => items.len(),
+        Value::SRational(items) => items.len(),
+        Value::Float(items) => items.len(),
+        Value::Double(items) => items.len(),
+        Value::Unknown(_, count, _) => usize::try_from(*count).unwrap_or(usize::MAX),
+    }
+}
+
+/// Type of a tag that can be added
+#[derive(Debug)]
+pub(crate) struct TagType {
+    /// Tag
+    pub(crate) tag: Tag,
+    /// Type of the value
+    pub(crate) kind: ValueKind,
+    /// Number of items, if fixed
+    count: Option<usize>,
+}
+
+impl TagType {
+    /// Parse a value of this tag
+    /// # Errors
+    /// Fails if the text is not a valid value of this tag
+    pub(crate) fn parse(&self, text: &str) -> Result<Field, String> {
+        let value = self.kind.parse(text)?;
+        if let Some(count) = self.count
+            && value_count(&value) != count
+        {
+            return Err(format!("{} expects {count} value(s)", self.tag));
+        }
+        Ok(field(self.tag, value))
+    }
+
+    /// Type of an existing field, `None` if it cannot be edited
+    pub(crate) fn of(exif_field: &Field) -> Option<Self> {
+        if exif_field.ifd_num != In::PRIMARY || AUTOMATIC_TAGS.contains(&exif_field.tag) {
+            return None;
+        }
+        let kind = ValueKind::of(&exif_field.value)?;
+        // the number of items of text and raw bytes is free
+        let count = (!matches!(kind, ValueKind::Ascii | ValueKind::Undefined))
+            .then(|| value_count(&exif_field.value));
+        Some(Self {
+            tag: exif_field.tag,
+            kind,
+            count: ADDABLE_TAGS
+                .iter()
+                .find(|tag_type| tag_type.tag == exif_field.tag)
+                .map_or(count, |tag_type| tag_type.count),
+        })
+    }
+}
+
+/// Tags that can be added in the editor
+pub(crate) const ADDABLE_TAGS: &[TagType] = &[
+    TagType {
+        tag: Tag::ImageDescription,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Artist,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Copyright,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Make,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Model,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Software,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::DateTime,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::DateTimeOriginal,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::CameraOwnerName,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::LensModel,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::Orientation,
+        kind: ValueKind::Short,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::XResolution,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::YResolution,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::ResolutionUnit,
+        kind: ValueKind::Short,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::ExposureTime,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::FNumber,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::PhotographicSensitivity,
+        kind: ValueKind::Short,
+        count: None,
+    },
+    TagType {
+        tag: Tag::FocalLength,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::GPSLatitudeRef,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::GPSLatitude,
+        kind: ValueKind::Rational,
+        count: Some(3),
+    },
+    TagType {
+        tag: Tag::GPSLongitudeRef,
+        kind: ValueKind::Ascii,
+        count: None,
+    },
+    TagType {
+        tag: Tag::GPSLongitude,
+        kind: ValueKind::Rational,
+        count: Some(3),
+    },
+    TagType {
+        tag: Tag::GPSAltitudeRef,
+        kind: ValueKind::Byte,
+        count: Some(1),
+    },
+    TagType {
+        tag: Tag::GPSAltitude,
+        kind: ValueKind::Rational,
+        count: Some(1),
+    },
+];
+
+/// Exif with a field added or replaced
+/// # Errors
+/// Fails if the exif cannot be written
+pub(crate) fn with_field(exif: Option<&Exif>, new_field: Field) -> Result<Exif, String> {
+    let data = match exif {
+        Some(exif) => rewrite(exif, &[new_field], |_| true, exif.little_endian(), None)?,
+        None => write([&new_field], false, None)?,
+    };
+    parse(data)
+}
+
+/// Exif without the fields matching `remove`, `None` if no field is left
+/// # Errors
+/// Fails if the exif cannot be written
+pub(crate) fn without_fields<F>(exif: &Exif, remove: F) -> Result<Option<Exif>, String>
+where
+    F: Fn(&Field) -> bool,
+{
+    let left = exif.fields().any(|field| {
+        field.ifd_num == In::PRIMARY
+            && !AUTOMATIC_TAGS.contains(&field.tag)
+            && !matches!(field.value, Value::Unknown(..))
+            && !remove(field)
+    });
+    if !left {
+        return Ok(None);
+    }
+    let data = rewrite(
+        exif,
+        &[],
+        |field| !remove(field),
+        exif.little_endian(),
+        None,
+    )?;
+    parse(data).map(Some)
+}
+
+/// Check if a field is part of the GPS data
+pub(crate) fn is_gps(exif_field: &Field) -> bool {
+    exif_field.tag.context() == Context::Gps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+    use crate::layer::Layer;
+    use crate::ops::Region;
+
+    /// Rotations and flips of the whole image
+    const ORIENTATION_OPERATIONS: [Operation; 5] = [
+        Operation::Rotate90,
+        Operation::Rotate180,
+        Operation::Rotate270,
+        Operation::FlipHorizontal,
+        Operation::FlipVertical,
+    ];
+
+    /// Exif with only an Orientation tag
+    fn exif_with_orientation(value: u16) -> Exif {
+        let orientation = field(Tag::Orientation, Value::Short(vec![value]));
+        let Ok(exif) = write([&orientation], false, None).and_then(parse) else {
+            panic!("the exif can be written");
+        };
+        exif
+    }
+
+    /// Image as shown by a viewer following the exif orientation
+    fn shown(img: &DynamicImage, exif: &Exif) -> DynamicImage {
+        let mut img = img.clone();
+        if let Some(orientation) = u8::try_from(orientation(exif))
+            .ok()
+            .and_then(image::metadata::Orientation::from_exif)
+        {
+            img.apply_orientation(orientation);
+        }
+        img
+    }
+
+    /// 3x2 image with a different value per pixel
+    fn asymmetric_image() -> DynamicImage {
+        DynamicImage::ImageLuma8(image::GrayImage::from_fn(3, 2, |x, y| {
+            #[allow(clippy::cast_possible_truncation)]
+            image::Luma([(x + 3 * y) as u8])
+        }))
+    }
+
+    #[test]
+    fn orientation_tag_values() {
+        for value in 1..=8 {
+            assert_eq!(
+                Orientation::from_exif(value).to_exif(),
+                u16::try_from(value).unwrap_or_default()
+            );
+        }
+        assert_eq!(Orientation::from_exif(0).to_exif(), 1);
+        assert_eq!(Orientation::from_exif(9).to_exif(), 1);
+        let identity = Orientation::from_exif(1);
+        for value in 1..=8 {
+            let orientation = Orientation::from_exif(value);
+            assert_eq!(orientation.after(orientation.inverse()), identity);
+            assert_eq!(orientation.inverse().after(orientation), identity);
+            assert_eq!(orientation.after(identity), orientation);
+        }
+    }
+
+    #[test]
+    fn orientation_follows_rotations_and_flips() {
+        let img = asymmetric_image();
+        for value in 1..=8 {
+            let exif = exif_with_orientation(value);
+            for operation in &ORIENTATION_OPERATIONS {
+                let Ok(new_exif) = after_operation(&exif, operation) else {
+                    panic!("the exif can be written");
+                };
+                let new_exif = new_exif.as_ref().unwrap_or(&exif);
+                // the viewer shows the image with the same change as the pixels
+                assert_eq!(
+                    shown(&operation.apply(&img), new_exif),
+                    operation.apply(&shown(&img, &exif)),
+                    "{operation} with orientation {value}"
+                );
+            }
+        }
+        let Ok(None) = after_operation(&exif_with_orientation(6), &Operation::Invert) else {
+            panic!("the orientation is kept");
+        };
+    }
+
+    #[test]
+    fn rotation_of_all_layers_updates_the_orientation() {
+        let mut document = Document::new("a.jpg".into(), DynamicImage::new_rgb8(4, 3));
+        let mut top = Layer::new("top", DynamicImage::new_rgb8(2, 1));
+        top.props.offset = (1, 0);
+        document.add_layer(top);
+        document.exif = Some(exif_with_orientation(2));
+        let Ok(Some(expected)) = after_operation(&exif_with_orientation(2), &Operation::Rotate90)
+        else {
+            panic!("a rotation changes a flipped orientation");
+        };
+        assert!(
+            document
+                .apply_operation(None, None, &Operation::Rotate90)
+                .is_ok()
+        );
+        assert_eq!(
+            document.exif.as_ref().map(orientation),
+            Some(orientation(&expected))
+        );
+        assert_ne!(orientation(&expected), 2);
+        assert_eq!(document.size(), (3, 4));
+        assert_eq!(document.layers[1].img.dimensions(), (1, 2));
+        assert_eq!(document.layers[1].props.offset, (2, 1));
+        // a region is not the whole canvas
+        let region = Region::new(0, 0, 2, 2);
+        assert!(
+            document
+                .apply_operation(Some(region), None, &Operation::FlipHorizontal)
+                .is_ok()
+        );
+        assert_eq!(
+            document.exif.as_ref().map(orientation),
+            Some(orientation(&expected))
+        );
+        // the flip of the black pixels changed nothing, the rotation is undone
+        document.undo();
+        assert_eq!(document.size(), (4, 3));
+        assert_eq!(document.layers[1].props.offset, (1, 0));
+    }
+
+    /// Type of an addable tag
+    fn tag_type(tag: Tag) -> &'static TagType {
+        let Some(tag_type) = ADDABLE_TAGS.iter().find(|tag_type| tag_type.tag == tag) else {
+            panic!("{tag} can be added");
+        };
+        tag_type
+    }
+
+    #[test]
+    fn parse_rationals() {
+        assert_eq!(parse_rational("1/250"), Some((1, 250)));
+        assert_eq!(parse_rational(" 3 / 4 "), Some((3, 4)));
+        assert_eq!(parse_rational("2.8"), Some((28, 10)));
+        assert_eq!(parse_rational("-0.25"), Some((-25, 100)));
+        assert_eq!(parse_rational("7"), Some((7, 1)));
+        assert_eq!(parse_rational("1/0"), None);
+        assert_eq!(parse_rational("1.2.3"), None);
+        assert_eq!(parse_rational("1.1234567890"), None);
+        assert_eq!(parse_rational("f/2"), None);
+        let Ok(Value::Rational(items)) = ValueKind::Rational.parse("1/250, 2.8") else {
+            panic!("valid rationals");
+        };
+        assert_eq!(
+            items.iter().map(|r| (r.num, r.denom)).collect::<Vec<_>>(),
+            [(1, 250), (28, 10)]
+        );
+        assert!(ValueKind::Rational.parse("-1/2").is_err());
+        assert!(ValueKind::Rational.parse("1/0").is_err());
+        let Ok(Value::SRational(items)) = ValueKind::SRational.parse("-1/2") else {
+            panic!("valid signed rational");
+        };
+        assert_eq!((items[0].num, items[0].denom), (-1, 2));
+        assert!(ValueKind::SRational.parse("4294967296/1").is_err());
+    }
+
+    #[test]
+    fn parse_text_and_bytes() {
+        assert!(matches!(
+            ValueKind::Ascii.parse(" Jane Doe "),
+            Ok(Value::Ascii(items)) if items == vec![b"Jane Doe".to_vec()]
+        ));
+        assert!(ValueKind::Ascii.parse("Zoë").is_err());
+        assert!(ValueKind::Ascii.parse("a\0b").is_err());
+        assert!(matches!(
+            ValueKind::Undefined.parse("01 ff 0A"),
+            Ok(Value::Undefined(items, _)) if items == vec![0x01, 0xff, 0x0a]
+        ));
+        assert!(ValueKind::Undefined.parse("1ff").is_err());
+        assert!(ValueKind::Undefined.parse("0x01").is_err());
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert!(matches!(
+            ValueKind::Short.parse("1, 2,3"),
+            Ok(Value::Short(items)) if items == vec![1, 2, 3]
+        ));
+        assert!(ValueKind::Short.parse("65536").is_err());
+        assert!(ValueKind::Short.parse("-1").is_err());
+        assert!(ValueKind::Long.parse("").is_err());
+        assert!(ValueKind::Long.parse("1,,2").is_err());
+        assert!(matches!(
+            ValueKind::SShort.parse("-5"),
+            Ok(Value::SShort(items)) if items == vec![-5]
+        ));
+        assert!(ValueKind::Byte.parse("256").is_err());
+        assert!(matches!(
+            ValueKind::Double.parse("0.5, 2"),
+            Ok(Value::Double(items)) if items == vec![0.5, 2.0]
+        ));
+        assert!(ValueKind::Float.parse("half").is_err());
+    }
+
+    #[test]
+    fn value_text_round_trip() {
+        for (kind, text) in [
+            (ValueKind::Ascii, "Some text"),
+            (ValueKind::Undefined, "30 32 33 31"),
+            (ValueKind::Short, "1, 2"),
+            (ValueKind::SLong, "-7"),
+            (ValueKind::Rational, "1/250"),
+            (ValueKind::SRational, "-1/3, 5/1"),
+            (ValueKind::Double, "2.5"),
+        ] {
+            let Ok(value) = kind.parse(text) else {
+                panic!("'{text}' is a valid {kind:?}");
+            };
+            assert_eq!(value_text(&value), text);
+            assert_eq!(ValueKind::of(&value), Some(kind));
+        }
+    }
+
+    #[test]
+    fn tag_types_check_the_count() {
+        let orientation = tag_type(Tag::Orientation);
+        assert!(orientation.parse("6").is_ok());
+        assert!(orientation.parse("6, 1").is_err());
+        assert!(orientation.parse("1/2").is_err());
+        let latitude = tag_type(Tag::GPSLatitude);
+        assert!(latitude.parse("48/1, 51/1, 2964/100").is_ok());
+        assert!(latitude.parse("48/1, 51/1").is_err());
+        let Ok(artist) = tag_type(Tag::Artist).parse("Jane") else {
+            panic!("valid artist");
+        };
+        assert_eq!((artist.tag, artist.ifd_num), (Tag::Artist, In::PRIMARY));
+        // existing fields keep their type and count
+        let existing = field(Tag::WhiteBalance, Value::Short(vec![0]));
+        let Some(white_balance) = TagType::of(&existing) else {
+            panic!("the white balance can be edited");
+        };
+        assert!(white_balance.parse("1").is_ok());
+        assert!(white_balance.parse("1, 0").is_err());
+        assert!(TagType::of(&field(Tag::PixelXDimension, Value::Long(vec![1]))).is_none());
+    }
+
+    /// 3x2 image of each color type the TIFF writer stores
+    #[allow(clippy::cast_precision_loss)]
+    fn tiff_images() -> Vec<DynamicImage> {
+        let img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(3, 2, |x, y| {
+            #[allow(clippy::cast_possible_truncation)]
+            image::Rgba([(40 * x) as u8, (100 * y) as u8, 7, 200])
+        }));
+        vec![
+            DynamicImage::ImageLuma8(img.to_luma8()),
+            DynamicImage::ImageRgb8(img.to_rgb8()),
+            img.clone(),
+            DynamicImage::ImageLuma16(img.to_luma16()),
+            DynamicImage::ImageRgb16(img.to_rgb16()),
+            DynamicImage::ImageRgba16(img.to_rgba16()),
+            DynamicImage::ImageRgb32F(image::Rgb32FImage::from_fn(3, 2, |x, y| {
+                image::Rgb([x as f32 * 0.5, 1.5, -(y as f32)])
+            })),
+            DynamicImage::ImageRgba32F(img.to_rgba32f()),
+        ]
+    }
+
+    #[test]
+    fn tiff_keeps_pixels_and_exif() {
+        let artist = field(Tag::Artist, Value::Ascii(vec![b"tarsier".to_vec()]));
+        let size = field(Tag::ImageWidth, Value::Long(vec![100]));
+        let Ok(exif) = write([&artist, &size], false, None).and_then(parse) else {
+            panic!("the exif can be written");
+        };
+        for img in tiff_images() {
+            let Ok(bytes) = write_tiff(&img, &exif) else {
+                panic!("{:?} can be written", img.color());
+            };
+            let Ok(decoded) = image::load_from_memory_with_format(&bytes, image::ImageFormat::Tiff)
+            else {
+                panic!("{:?} can be decoded", img.color());
+            };
+            assert_eq!(decoded, img, "{:?}", img.color());
+            let Ok(read) = exif::Reader::new().read_from_container(&mut Cursor::new(&bytes)) else {
+                panic!("the exif of {:?} can be read", img.color());
+            };
+            let text = |tag| {
+                read.get_field(tag, In::PRIMARY)
+                    .map(|field| value_text(&field.value))
+            };
+            assert_eq!(text(Tag::Artist).as_deref(), Some("tarsier"));
+            assert_eq!(text(Tag::ImageWidth).as_deref(), Some("3"));
+            assert_eq!(text(Tag::ImageLength).as_deref(), Some("2"));
+        }
+    }
+}
@@ -6,6 +6,7 @@ use bladvak::{
 };
 
 use crate::TarsierApp;
+use crate::ops::Operation;
 
 impl TarsierApp {
     /// Reset icon
@@ -38,8 +39,9 @@ impl TarsierApp {
                 let Some(document) = self.documents.get_current_doc_mut() else {
                     return;
                 };
-                let new_img = document.saved_img.clone();
-                self.update_image(new_img);
+                let saved_layers = document.saved_layers.clone();
+                document.set_layers(saved_layers, document.active_layer);
+                self.updated_image();
             }
             let ico_image = Image::new(Self::SAVE_STATE_ICON);
             if ui
@@ -54,8 +56,9 @@ impl TarsierApp {
                 let Some(document) = self.documents.get_current_doc_mut() else {
                     return;
                 };
-                document.saved_img = document.img.clone();
+                document.saved_layers = document.layers.clone();
             }
+            self.history_buttons(ui);
         });
         ui.separator();
         ui.horizontal(|ui| {
@@ -69,7 +72,7 @@ impl TarsierApp {
                 .on_hover_text("Rotate 90 degrees clockwise")
                 .clicked()
             {
-                self.apply_op(image::DynamicImage::rotate90, error_manager);
+                self.apply_operation(&Operation::Rotate90, error_manager);
             }
 
             let ico_image = Image::new(Self::ROTATE_CCW_ICON);
@@ -82,7 +85,7 @@ impl TarsierApp {
                 .on_hover_text("Rotate 90 degrees counter-clockwise")
                 .clicked()
             {
-                self.apply_op(image::DynamicImage::rotate270, error_manager);
+                self.apply_operation(&Operation::Rotate270, error_manager);
             }
 
             let ico_image = Image::new(Self::FLIP_H_ICON);
@@ -95,7 +98,7 @@ impl TarsierApp {
                 .on_hover_text("Flip horizontally")
                 .clicked()
             {
-                self.apply_op(image::DynamicImage::fliph, error_manager);
+                self.apply_operation(&Operation::FlipHorizontal, error_manager);
             }
             let ico_image = Image::new(Self::FLIP_V_ICON);
             if ui
@@ -107,8 +110,29 @@ impl TarsierApp {
                 .on_hover_text("Flip vertically")
                 .clicked()
             {
-                self.apply_op(image::DynamicImage::flipv, error_manager);
+                self.apply_operation(&Operation::FlipVertical, error_manager);
             }
         });
     }
+
+    /// Undo and redo buttons
+    fn history_buttons(&mut self, ui: &mut egui::Ui) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        if ui
+            .add_enabled(document.history.can_undo(), egui::Button::new("⟲"))
+            .on_hover_text("Undo (Ctrl+Z)")
+            .clicked()
+        {
+            document.undo();
+        }
+        if ui
+            .add_enabled(document.history.can_redo(), egui::Button::new("⟳"))
+            .on_hover_text("Redo (Ctrl+Shift+Z)")
+            .clicked()
+        {
+            document.redo();
+        }
+    }
 }
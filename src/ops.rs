@@ -0,0 +1,1476 @@
+//! Image operations, independent of the ui
+//!
+//! These are the functions used by the editor, they can be used on any [`DynamicImage`].
+//!
+//! ```
+//! use image::{DynamicImage, GenericImageView};
+//! use tarsier::ops::{Operation, Region, apply_in_region};
+//!
+//! let img = DynamicImage::new_rgba8(64, 64);
+//! let region = Region::new(8, 8, 16, 16);
+//! let result = apply_in_region(&img, region, |part| Operation::Invert.apply(part)).unwrap();
+//! assert_eq!(result.dimensions(), (64, 64));
+//! ```
+
+use image::{
+    ColorType, DynamicImage, GenericImage, GenericImageView, GrayImage, ImageBuffer, ImageError,
+    ImageResult, Pixel, Rgba,
+    error::{ParameterError, ParameterErrorKind},
+    imageops::{self, FilterType},
+};
+use imageproc::definitions::Clamp;
+use imageproc::filter::median_filter as imageproc_median_filter;
+use imageproc::geometric_transformations::{self, Projection};
+
+/// Rectangular region of an image, in pixels
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Region {
+    /// Left of the region
+    pub x: u32,
+    /// Top of the region
+    pub y: u32,
+    /// Width of the region
+    pub width: u32,
+    /// Height of the region
+    pub height: u32,
+}
+
+impl Region {
+    /// New region
+    #[must_use]
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Region covering the whole image
+    #[must_use]
+    pub fn full(img: &DynamicImage) -> Self {
+        Self::new(0, 0, img.width(), img.height())
+    }
+
+    /// Intersection of the region with the image bounds
+    #[must_use]
+    pub fn clamp_to(self, img: &DynamicImage) -> Self {
+        let x = self.x.min(img.width());
+        let y = self.y.min(img.height());
+        Self {
+            x,
+            y,
+            width: self.width.min(img.width() - x),
+            height: self.height.min(img.height() - y),
+        }
+    }
+
+    /// Smallest region containing both regions
+    #[must_use]
+    pub fn union(self, other: Self) -> Self {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = self
+            .x
+            .saturating_add(self.width)
+            .max(other.x.saturating_add(other.width));
+        let bottom = self
+            .y
+            .saturating_add(self.height)
+            .max(other.y.saturating_add(other.height));
+        Self::new(x, y, right - x, bottom - y)
+    }
+
+    /// Intersection of both regions, empty if they do not overlap
+    #[must_use]
+    pub fn intersection(self, other: Self) -> Self {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self
+            .x
+            .saturating_add(self.width)
+            .min(other.x.saturating_add(other.width));
+        let bottom = self
+            .y
+            .saturating_add(self.height)
+            .min(other.y.saturating_add(other.height));
+        Self::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    /// Check if the region is empty
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// Color channel
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Channel {
+    /// Red channel
+    Red,
+    /// Green channel
+    Green,
+    /// Blue channel
+    Blue,
+}
+
+/// Interpolation of the pixels in geometric transformations
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Interpolation {
+    /// Nearest pixel
+    Nearest,
+    /// Bilinear interpolation of the 4 nearest pixels
+    #[default]
+    Bilinear,
+    /// Bicubic interpolation of the 16 nearest pixels
+    Bicubic,
+}
+
+impl Interpolation {
+    /// All the interpolations
+    pub const ALL: [Self; 3] = [Self::Nearest, Self::Bilinear, Self::Bicubic];
+}
+
+impl From<Interpolation> for geometric_transformations::Interpolation {
+    fn from(interpolation: Interpolation) -> Self {
+        match interpolation {
+            Interpolation::Nearest => Self::Nearest,
+            Interpolation::Bilinear => Self::Bilinear,
+            Interpolation::Bicubic => Self::Bicubic,
+        }
+    }
+}
+
+/// Size of the image after a free rotation
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum RotateFit {
+    /// Expand the canvas to keep the whole image
+    #[default]
+    Expand,
+    /// Crop to the largest rectangle inside the rotated image
+    Crop,
+    /// Keep the size of the image
+    Keep,
+}
+
+impl RotateFit {
+    /// All the fits
+    pub const ALL: [Self; 3] = [Self::Expand, Self::Crop, Self::Keep];
+}
+
+/// How an image is resized to a width and a height
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ResizeMode {
+    /// Fit within the size, keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Exactly the size, the aspect ratio may change
+    Exact,
+    /// Fill the size keeping the aspect ratio, and crop the overflow
+    Fill,
+}
+
+impl ResizeMode {
+    /// All the modes
+    pub const ALL: [Self; 3] = [Self::Fit, Self::Exact, Self::Fill];
+}
+
+/// Position of the image in a resized canvas
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Anchor {
+    /// Top left corner
+    TopLeft,
+    /// Top edge
+    Top,
+    /// Top right corner
+    TopRight,
+    /// Left edge
+    Left,
+    /// Center
+    #[default]
+    Center,
+    /// Right edge
+    Right,
+    /// Bottom left corner
+    BottomLeft,
+    /// Bottom edge
+    Bottom,
+    /// Bottom right corner
+    BottomRight,
+}
+
+impl Anchor {
+    /// All the anchors, row by row
+    pub const ALL: [Self; 9] = [
+        Self::TopLeft,
+        Self::Top,
+        Self::TopRight,
+        Self::Left,
+        Self::Center,
+        Self::Right,
+        Self::BottomLeft,
+        Self::Bottom,
+        Self::BottomRight,
+    ];
+
+    /// Column and row in the grid of anchors, from 0 to 2
+    #[must_use]
+    pub fn grid_position(self) -> (u8, u8) {
+        match self {
+            Self::TopLeft => (0, 0),
+            Self::Top => (1, 0),
+            Self::TopRight => (2, 0),
+            Self::Left => (0, 1),
+            Self::Center => (1, 1),
+            Self::Right => (2, 1),
+            Self::BottomLeft => (0, 2),
+            Self::Bottom => (1, 2),
+            Self::BottomRight => (2, 2),
+        }
+    }
+}
+
+/// Operation with its parameters
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub enum Operation {
+    /// Resize to a width and a height
+    Resize {
+        /// new width
+        width: u32,
+        /// new height
+        height: u32,
+        /// filter
+        filter: FilterType,
+        /// how the size is reached, fit for the older operations
+        #[serde(default)]
+        mode: ResizeMode,
+    },
+    /// Scale by a percentage
+    Scale {
+        /// scale, in percent
+        percent: f32,
+        /// filter
+        filter: FilterType,
+    },
+    /// Resize so that the longest edge has a size, keeping the aspect ratio
+    LongEdge {
+        /// size of the longest edge
+        size: u32,
+        /// filter
+        filter: FilterType,
+    },
+    /// Convert to another color type
+    Convert(ColorType),
+    /// Grayscale, keeping the color type
+    Grayscale,
+    /// Invert colors
+    Invert,
+    /// Gaussian blur
+    Blur(f32),
+    /// Hue rotation in degrees
+    HueRotate(i32),
+    /// Brighten
+    Brighten(i32),
+    /// Contrast
+    Contrast(f32),
+    /// Median filter
+    MedianFilter(u32),
+    /// Make transparent the pixels near a color
+    CutColor {
+        /// color to cut
+        color: [u8; 3],
+        /// tolerance per channel
+        tolerance: i16,
+    },
+    /// Sobel outline
+    SobelOutline,
+    /// Edge detection
+    EdgeDetection,
+    /// Keep only one channel
+    Channel(Channel),
+    /// Rotate 90 degrees clockwise
+    Rotate90,
+    /// Rotate 180 degrees
+    Rotate180,
+    /// Rotate 90 degrees counter-clockwise
+    Rotate270,
+    /// Flip horizontally
+    FlipHorizontal,
+    /// Flip vertically
+    FlipVertical,
+    /// Rotate clockwise by any angle
+    Rotate {
+        /// angle in degrees
+        degrees: f32,
+        /// interpolation of the pixels
+        interpolation: Interpolation,
+        /// size of the result
+        fit: RotateFit,
+        /// color of the uncovered pixels
+        fill: [u8; 4],
+    },
+    /// Warp a quadrilateral to a rectangle
+    Perspective {
+        /// corners of the quadrilateral: top left, top right, bottom right and bottom left
+        corners: [[f32; 2]; 4],
+        /// width of the result
+        width: u32,
+        /// height of the result
+        height: u32,
+        /// interpolation of the pixels
+        interpolation: Interpolation,
+    },
+    /// Change the canvas size without scaling the pixels
+    CanvasSize {
+        /// new width
+        width: u32,
+        /// new height
+        height: u32,
+        /// position of the image in the new canvas
+        anchor: Anchor,
+        /// color of the added pixels
+        fill: [u8; 4],
+    },
+    /// Remove the borders of the color of the top left pixel
+    Trim {
+        /// maximum difference per channel, in percent
+        tolerance: f32,
+    },
+}
+
+impl Operation {
+    /// Apply the operation on an image
+    #[must_use]
+    pub fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            Self::Resize {
+                width,
+                height,
+                filter,
+                mode,
+            } => resize(img, (*width, *height), *mode, *filter),
+            Self::Scale { filter, .. } | Self::LongEdge { filter, .. } => {
+                let (width, height) = self
+                    .resized_dimensions(img.dimensions())
+                    .unwrap_or(img.dimensions());
+                img.resize_exact(width, height, *filter)
+            }
+            Self::Convert(color_type) => convert(img, *color_type),
+            Self::Grayscale => grayscale(img),
+            Self::Invert => {
+                let mut copied_img = img.clone();
+                copied_img.invert();
+                copied_img
+            }
+            Self::Blur(sigma) => img.blur(*sigma),
+            Self::HueRotate(value) => img.huerotate(*value),
+            Self::Brighten(value) => img.brighten(*value),
+            Self::Contrast(value) => img.adjust_contrast(*value),
+            Self::MedianFilter(radius) => median_filter(img, *radius),
+            Self::CutColor { color, tolerance } => cut_color(img, *color, *tolerance),
+            Self::SobelOutline => sobel_outline(img),
+            Self::EdgeDetection => img.filter3x3(&[
+                0.0, -1.0, 0.0, //
+                -1.0, 4.0, -1.0, //
+                0.0, -1.0, 0.0, //
+            ]),
+            Self::Channel(channel) => keep_channel(img, *channel),
+            Self::Rotate90 => img.rotate90(),
+            Self::Rotate180 => img.rotate180(),
+            Self::Rotate270 => img.rotate270(),
+            Self::FlipHorizontal => img.fliph(),
+            Self::FlipVertical => img.flipv(),
+            Self::Rotate {
+                degrees,
+                interpolation,
+                fit,
+                fill,
+            } => rotate(img, *degrees, *interpolation, *fit, *fill),
+            Self::Perspective {
+                corners,
+                width,
+                height,
+                interpolation,
+            } => perspective(img, *corners, (*width, *height), *interpolation),
+            Self::CanvasSize {
+                width,
+                height,
+                anchor,
+                fill,
+            } => canvas_size(img, (*width, *height), *anchor, *fill),
+            Self::Trim { tolerance } => trim(img, *tolerance),
+        }
+    }
+
+    /// Same operation for an image scaled by `scale`, e.g. a preview on a downscaled copy
+    ///
+    /// The parameters in pixels are scaled, the others are kept
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn scaled(&self, scale: f32) -> Self {
+        let pixels = |value: u32| ((value as f32 * scale).round() as u32).max(1);
+        let mut scaled = self.clone();
+        match &mut scaled {
+            Self::Blur(sigma) => *sigma *= scale,
+            Self::MedianFilter(radius) => *radius = (*radius as f32 * scale).round() as u32,
+            Self::Resize { width, height, .. } | Self::CanvasSize { width, height, .. } => {
+                (*width, *height) = (pixels(*width), pixels(*height));
+            }
+            Self::Perspective {
+                corners,
+                width,
+                height,
+                ..
+            } => {
+                *corners = corners.map(|corner| corner.map(|value| value * scale));
+                (*width, *height) = (pixels(*width), pixels(*height));
+            }
+            Self::LongEdge { size, .. } => *size = pixels(*size),
+            _ => {}
+        }
+        scaled
+    }
+
+    /// Size of an image of `size` after a resize operation, `None` for the other operations
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn resized_dimensions(&self, (width, height): (u32, u32)) -> Option<(u32, u32)> {
+        match self {
+            Self::Resize {
+                width: new_width,
+                height: new_height,
+                mode: ResizeMode::Fit,
+                ..
+            } => Some(fit_dimensions((width, height), (*new_width, *new_height))),
+            Self::Resize {
+                width: new_width,
+                height: new_height,
+                ..
+            } => Some((*new_width, *new_height)),
+            Self::Scale { percent, .. } => {
+                let scale = |value: u32| ((value as f32 * percent / 100.0).round() as u32).max(1);
+                Some((scale(width), scale(height)))
+            }
+            Self::LongEdge { size, .. } => Some(fit_dimensions((width, height), (*size, *size))),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Resize {
+                width,
+                height,
+                filter,
+                mode,
+            } => write!(f, "Resize {width}x{height} ({mode:?}, {filter:?})"),
+            Self::Scale { percent, filter } => write!(f, "Scale {percent}% ({filter:?})"),
+            Self::LongEdge { size, filter } => write!(f, "Long edge {size} ({filter:?})"),
+            Self::Convert(color_type) => write!(f, "Convert to {color_type:?}"),
+            Self::Grayscale => write!(f, "Grayscale"),
+            Self::Invert => write!(f, "Invert"),
+            Self::Blur(sigma) => write!(f, "Blur {sigma}"),
+            Self::HueRotate(value) => write!(f, "Hue rotate {value}"),
+            Self::Brighten(value) => write!(f, "Brighten {value}"),
+            Self::Contrast(value) => write!(f, "Contrast {value}"),
+            Self::MedianFilter(radius) => write!(f, "Median filter {radius}"),
+            Self::CutColor { color, tolerance } => {
+                write!(f, "Cut color {color:?} ({tolerance})")
+            }
+            Self::SobelOutline => write!(f, "Sobel outline"),
+            Self::EdgeDetection => write!(f, "Edge detection"),
+            Self::Channel(channel) => write!(f, "{channel:?} channel"),
+            Self::Rotate90 => write!(f, "Rotate 90"),
+            Self::Rotate180 => write!(f, "Rotate 180"),
+            Self::Rotate270 => write!(f, "Rotate 270"),
+            Self::FlipHorizontal => write!(f, "Flip horizontally"),
+            Self::FlipVertical => write!(f, "Flip vertically"),
+            Self::Rotate { degrees, fit, .. } => write!(f, "Rotate {degrees}° ({fit:?})"),
+            Self::Perspective { width, height, .. } => write!(f, "Perspective {width}x{height}"),
+            Self::CanvasSize {
+                width,
+                height,
+                anchor,
+                ..
+            } => write!(f, "Canvas size {width}x{height} ({anchor:?})"),
+            Self::Trim { tolerance } => write!(f, "Trim ({tolerance}%)"),
+        }
+    }
+}
+
+/// Convert an image to a color type
+#[must_use]
+pub fn convert(img: &DynamicImage, color_type: ColorType) -> DynamicImage {
+    match color_type {
+        ColorType::L8 => img.to_luma8().into(),
+        ColorType::L16 => img.to_luma16().into(),
+        ColorType::La8 => img.to_luma_alpha8().into(),
+        ColorType::La16 => img.to_luma_alpha16().into(),
+        ColorType::Rgb8 => img.to_rgb8().into(),
+        ColorType::Rgb16 => img.to_rgb16().into(),
+        ColorType::Rgb32F => img.to_rgb32f().into(),
+        ColorType::Rgba16 => img.to_rgba16().into(),
+        ColorType::Rgba32F => img.to_rgba32f().into(),
+        ColorType::Rgba8 | _ => img.to_rgba8().into(),
+    }
+}
+
+/// Same color type with an alpha channel
+#[must_use]
+pub fn with_alpha(color_type: ColorType) -> ColorType {
+    match color_type {
+        ColorType::L8 => ColorType::La8,
+        ColorType::L16 => ColorType::La16,
+        ColorType::Rgb8 => ColorType::Rgba8,
+        ColorType::Rgb16 => ColorType::Rgba16,
+        ColorType::Rgb32F => ColorType::Rgba32F,
+        other => other,
+    }
+}
+
+/// Grayscale, keeping the color type of the image
+#[must_use]
+pub fn grayscale(img: &DynamicImage) -> DynamicImage {
+    let color = img.color();
+    let inner = img.grayscale();
+    match color {
+        ColorType::L8 => inner.to_luma8().into(),
+        ColorType::L16 => inner.to_luma16().into(),
+        ColorType::La8 => inner.to_luma_alpha8().into(),
+        ColorType::La16 => inner.to_luma_alpha16().into(),
+        ColorType::Rgb8 => inner.to_rgb8().into(),
+        ColorType::Rgb16 => inner.to_rgb16().into(),
+        ColorType::Rgba16 => inner.to_rgba16().into(),
+        ColorType::Rgba8 | _ => inner.to_rgba8().into(),
+    }
+}
+
+/// Median filter on the luma of the image
+#[must_use]
+pub fn median_filter(img: &DynamicImage, radius: u32) -> DynamicImage {
+    let inner = img.to_luma8();
+    let inner = imageproc_median_filter(&inner, radius, radius);
+    DynamicImage::ImageLuma8(inner)
+}
+
+/// Make transparent the pixels near `target`
+#[must_use]
+pub fn cut_color(img: &DynamicImage, target: [u8; 3], tolerance: i16) -> DynamicImage {
+    let mut res = img.to_rgba8();
+    for (x, y, pixel) in img.pixels() {
+        let [r, g, b, a] = pixel.0;
+        let diff_r = (i16::from(r) - i16::from(target[0])).abs();
+        let diff_g = (i16::from(g) - i16::from(target[1])).abs();
+        let diff_b = (i16::from(b) - i16::from(target[2])).abs();
+        let matches = diff_r <= tolerance && diff_b <= tolerance && diff_g <= tolerance;
+
+        let color = if matches {
+            Rgba([r, g, b, 0]) // transparent
+        } else {
+            Rgba([r, g, b, a])
+        };
+        res.put_pixel(x, y, color);
+    }
+    DynamicImage::ImageRgba8(res)
+}
+
+/// Keep only one channel of the image
+#[must_use]
+pub fn keep_channel(img: &DynamicImage, channel: Channel) -> DynamicImage {
+    let removed = match channel {
+        Channel::Red => [1, 2],
+        Channel::Green => [0, 2],
+        Channel::Blue => [0, 1],
+    };
+    let mut c = img.clone();
+    for x in 0..c.width() {
+        for y in 0..c.height() {
+            let mut px = c.get_pixel(x, y);
+            for idx in removed {
+                px.channels_mut()[idx] = 0;
+            }
+            c.put_pixel(x, y, px);
+        }
+    }
+    c
+}
+
+/// Sobel outline
+#[must_use]
+#[allow(clippy::similar_names)]
+pub fn sobel_outline(img: &DynamicImage) -> DynamicImage {
+    let mut img = img.clone();
+    let sobel_x = img.filter3x3(&[
+        -1.0, 0.0, 1.0, //
+        -2.0, 0.0, 2.0, //
+        -1.0, 0.0, 1.0, //
+    ]);
+    let sobel_x2 = img.filter3x3(&[
+        1.0, 0.0, -1.0, //
+        2.0, 0.0, -2.0, //
+        1.0, 0.0, -1.0, //
+    ]);
+    let sobel_y = img.filter3x3(&[
+        -1.0, -2.0, -1.0, //
+        0.0, 0.0, 0.0, //
+        1.0, 2.0, 1.0, //
+    ]);
+    let sobel_y2 = img.filter3x3(&[
+        1.0, 2.0, 1.0, //
+        0.0, 0.0, 0.0, //
+        -1.0, -2.0, -1.0, //
+    ]);
+    for y in 0..img.height() {
+        for x in 0..img.width() {
+            let mut pixel = sobel_x.get_pixel(x, y);
+            let pixel_y = sobel_y.get_pixel(x, y);
+            pixel.blend(&pixel_y);
+            let pixel_x2 = sobel_x2.get_pixel(x, y);
+            pixel.blend(&pixel_x2);
+            let pixel_y2 = sobel_y2.get_pixel(x, y);
+            pixel.blend(&pixel_y2);
+            img.put_pixel(x, y, pixel);
+        }
+    }
+    img
+}
+
+/// Size of the largest rectangle, centered and aligned on the axes, inside a rotated rectangle
+fn inscribed_size(width: f32, height: f32, radians: f32) -> (f32, f32) {
+    let (sin, cos) = (radians.sin().abs(), radians.cos().abs());
+    let (long, short) = (width.max(height), width.min(height));
+    if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+        // the rectangle touches the two long sides of the rotated one
+        let half = short / 2.0;
+        if width >= height {
+            (half / sin, half / cos)
+        } else {
+            (half / cos, half / sin)
+        }
+    } else {
+        let cos_2a = cos * cos - sin * sin;
+        (
+            (width * cos - height * sin) / cos_2a,
+            (height * cos - width * sin) / cos_2a,
+        )
+    }
+}
+
+/// Rotate clockwise by any angle, the uncovered pixels get the `fill` color
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+#[allow(clippy::cast_precision_loss)]
+pub fn rotate(
+    img: &DynamicImage,
+    degrees: f32,
+    interpolation: Interpolation,
+    fit: RotateFit,
+    fill: [u8; 4],
+) -> DynamicImage {
+    let radians = degrees.to_radians();
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let (sin, cos) = (radians.sin().abs(), radians.cos().abs());
+    let (new_width, new_height) = match fit {
+        // round to avoid an extra row for an angle of 90 degrees
+        RotateFit::Expand => (
+            (width * cos + height * sin - 1e-3).ceil(),
+            (width * sin + height * cos - 1e-3).ceil(),
+        ),
+        RotateFit::Crop => {
+            let (inner_width, inner_height) = inscribed_size(width, height, radians);
+            (inner_width.floor(), inner_height.floor())
+        }
+        RotateFit::Keep => (width, height),
+    };
+    // the pixels are sampled at their centers, the center of the image is at (width - 1) / 2
+    let projection = Projection::translate((new_width - 1.0) / 2.0, (new_height - 1.0) / 2.0)
+        * Projection::rotate(radians)
+        * Projection::translate((1.0 - width) / 2.0, (1.0 - height) / 2.0);
+    warp(
+        img,
+        projection,
+        interpolation,
+        fill,
+        (new_width.max(1.0) as u32, new_height.max(1.0) as u32),
+    )
+}
+
+/// Warp the quadrilateral of `corners` (top left, top right, bottom right, bottom left) to an image of `size`
+///
+/// The image is unchanged if the corners cannot be mapped to a rectangle
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn perspective(
+    img: &DynamicImage,
+    corners: [[f32; 2]; 4],
+    (width, height): (u32, u32),
+    interpolation: Interpolation,
+) -> DynamicImage {
+    // the pixels are sampled at their centers, half a pixel from their top left corner
+    let (left, top) = (-0.5, -0.5);
+    let (right, bottom) = (width as f32 - 0.5, height as f32 - 0.5);
+    let target = [(left, top), (right, top), (right, bottom), (left, bottom)];
+    let source = corners.map(|[x, y]| (x - 0.5, y - 0.5));
+    match Projection::from_control_points(source, target) {
+        Some(projection) if width > 0 && height > 0 => {
+            warp(img, projection, interpolation, [0; 4], (width, height))
+        }
+        _ => img.clone(),
+    }
+}
+
+/// Transform an image with a projection, in an image of `size`
+///
+/// The pixels outside of the source image get the `fill` color, converted to the color type of the image
+#[must_use]
+pub fn warp(
+    img: &DynamicImage,
+    projection: Projection,
+    interpolation: Interpolation,
+    fill: [u8; 4],
+    (width, height): (u32, u32),
+) -> DynamicImage {
+    let fill_img = convert(
+        &DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba(fill))),
+        img.color(),
+    );
+    let interpolation = interpolation.into();
+    let size = (width, height);
+    match (img, &fill_img) {
+        (DynamicImage::ImageLuma8(i), DynamicImage::ImageLuma8(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageLumaA8(i), DynamicImage::ImageLumaA8(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageRgb8(i), DynamicImage::ImageRgb8(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageLuma16(i), DynamicImage::ImageLuma16(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageLumaA16(i), DynamicImage::ImageLumaA16(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageRgb16(i), DynamicImage::ImageRgb16(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageRgba16(i), DynamicImage::ImageRgba16(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageRgb32F(i), DynamicImage::ImageRgb32F(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        (DynamicImage::ImageRgba32F(i), DynamicImage::ImageRgba32F(f)) => {
+            warp_buffer(i, projection, interpolation, *f.get_pixel(0, 0), size).into()
+        }
+        _ => warp_buffer(&img.to_rgba8(), projection, interpolation, Rgba(fill), size).into(),
+    }
+}
+
+/// Transform an image buffer with a projection
+fn warp_buffer<P>(
+    img: &ImageBuffer<P, Vec<P::Subpixel>>,
+    projection: Projection,
+    interpolation: geometric_transformations::Interpolation,
+    fill: P,
+    (width, height): (u32, u32),
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + Send + Sync,
+    P::Subpixel: Send + Sync + Into<f32> + Clamp<f32>,
+{
+    let mut out = ImageBuffer::new(width, height);
+    geometric_transformations::warp_into(
+        img,
+        projection,
+        interpolation,
+        geometric_transformations::Border::Constant(fill),
+        &mut out,
+    );
+    out
+}
+
+/// Largest size with the aspect ratio of `size` that fits within `bounds`, at least 1x1
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+pub fn fit_dimensions(
+    (width, height): (u32, u32),
+    (max_width, max_height): (u32, u32),
+) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (max_width, max_height);
+    }
+    let scale =
+        (f64::from(max_width) / f64::from(width)).min(f64::from(max_height) / f64::from(height));
+    let fit =
+        |value: u32, max: u32| ((f64::from(value) * scale).round() as u32).clamp(1, max.max(1));
+    (fit(width, max_width), fit(height, max_height))
+}
+
+/// Resize an image to `size` with a mode
+#[must_use]
+pub fn resize(
+    img: &DynamicImage,
+    (width, height): (u32, u32),
+    mode: ResizeMode,
+    filter: FilterType,
+) -> DynamicImage {
+    match mode {
+        ResizeMode::Fit => {
+            let (width, height) = fit_dimensions(img.dimensions(), (width, height));
+            img.resize_exact(width, height, filter)
+        }
+        ResizeMode::Exact => img.resize_exact(width, height, filter),
+        ResizeMode::Fill => img.resize_to_fill(width, height, filter),
+    }
+}
+
+/// Change the size of the canvas, the image is placed at `anchor` and the new pixels get the `fill` color
+///
+/// The color type is kept, with an alpha channel added if the fill is not opaque
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+pub fn canvas_size(
+    img: &DynamicImage,
+    (width, height): (u32, u32),
+    anchor: Anchor,
+    fill: [u8; 4],
+) -> DynamicImage {
+    let color_type = if fill[3] < u8::MAX {
+        with_alpha(img.color())
+    } else {
+        img.color()
+    };
+    let mut canvas = convert(
+        &DynamicImage::ImageRgba8(ImageBuffer::from_pixel(width, height, Rgba(fill))),
+        color_type,
+    );
+    let (column, row) = anchor.grid_position();
+    // offset of the image in the canvas, negative when the canvas is smaller
+    let offset = |old: u32, new: u32, position: u8| {
+        (i64::from(new) - i64::from(old)) * i64::from(position) / 2
+    };
+    let (offset_x, offset_y) = (
+        offset(img.width(), width, column),
+        offset(img.height(), height, row),
+    );
+    let (src_x, src_y) = ((-offset_x).max(0) as u32, (-offset_y).max(0) as u32);
+    let (dst_x, dst_y) = (offset_x.max(0) as u32, offset_y.max(0) as u32);
+    let visible = Region::new(
+        src_x,
+        src_y,
+        (img.width() - src_x).min(width - dst_x),
+        (img.height() - src_y).min(height - dst_y),
+    );
+    if !visible.is_empty() {
+        // the visible part always fits in the canvas
+        let _ = paste(&mut canvas, &crop(img, visible), dst_x, dst_y);
+    }
+    canvas
+}
+
+/// Remove the borders of the color of the top left pixel, within a `tolerance` per channel in percent
+///
+/// The image is unchanged if it is uniform
+#[must_use]
+pub fn trim(img: &DynamicImage, tolerance: f32) -> DynamicImage {
+    let pixels = img.to_rgba32f();
+    let Some(reference) = pixels.get_pixel_checked(0, 0).copied() else {
+        return img.clone();
+    };
+    let tolerance = tolerance / 100.0;
+    let mut bounds: Option<(u32, u32, u32, u32)> = None;
+    for (x, y, pixel) in pixels.enumerate_pixels() {
+        let differs = pixel
+            .0
+            .iter()
+            .zip(reference.0)
+            .any(|(value, reference)| (value - reference).abs() > tolerance);
+        if differs {
+            bounds = Some(bounds.map_or((x, y, x, y), |(left, top, right, bottom)| {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }));
+        }
+    }
+    match bounds {
+        Some((left, top, right, bottom)) => crop(
+            img,
+            Region::new(left, top, right - left + 1, bottom - top + 1),
+        ),
+        None => img.clone(),
+    }
+}
+
+/// Crop a region of the image, clamped to the image bounds
+#[must_use]
+pub fn crop(img: &DynamicImage, region: Region) -> DynamicImage {
+    let region = region.clamp_to(img);
+    img.crop_imm(region.x, region.y, region.width, region.height)
+}
+
+/// Copy `patch` into `img` at (`x`, `y`)
+///
+/// The pixels are copied without loss when both images have the same color type,
+/// otherwise `patch` is first converted to the color type of `img`.
+///
+/// # Errors
+/// Fails if the patch does not fit in the image
+pub fn paste(img: &mut DynamicImage, patch: &DynamicImage, x: u32, y: u32) -> ImageResult<()> {
+    if u64::from(x) + u64::from(patch.width()) > u64::from(img.width())
+        || u64::from(y) + u64::from(patch.height()) > u64::from(img.height())
+    {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    }
+    let converted;
+    let patch = if patch.color() == img.color() {
+        patch
+    } else {
+        converted = convert(patch, img.color());
+        &converted
+    };
+    let (x, y) = (i64::from(x), i64::from(y));
+    match (img, patch) {
+        (DynamicImage::ImageLuma8(d), DynamicImage::ImageLuma8(s)) => imageops::replace(d, s, x, y),
+        (DynamicImage::ImageLumaA8(d), DynamicImage::ImageLumaA8(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (DynamicImage::ImageRgb8(d), DynamicImage::ImageRgb8(s)) => imageops::replace(d, s, x, y),
+        (DynamicImage::ImageRgba8(d), DynamicImage::ImageRgba8(s)) => imageops::replace(d, s, x, y),
+        (DynamicImage::ImageLuma16(d), DynamicImage::ImageLuma16(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (DynamicImage::ImageLumaA16(d), DynamicImage::ImageLumaA16(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (DynamicImage::ImageRgb16(d), DynamicImage::ImageRgb16(s)) => imageops::replace(d, s, x, y),
+        (DynamicImage::ImageRgba16(d), DynamicImage::ImageRgba16(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (DynamicImage::ImageRgb32F(d), DynamicImage::ImageRgb32F(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (DynamicImage::ImageRgba32F(d), DynamicImage::ImageRgba32F(s)) => {
+            imageops::replace(d, s, x, y);
+        }
+        (img, patch) => {
+            #[allow(clippy::cast_possible_truncation)]
+            #[allow(clippy::cast_sign_loss)]
+            return img.copy_from(patch, x as u32, y as u32);
+        }
+    }
+    Ok(())
+}
+
+/// Copy the pixels of `src` selected by `mask` into `img`
+///
+/// A pixel is selected when its value in the mask is at least 128.
+/// `src` is first converted to the color type of `img` if needed.
+///
+/// # Errors
+/// Fails if the images and the mask do not have the same size
+pub fn merge_masked(
+    img: &mut DynamicImage,
+    src: &DynamicImage,
+    mask: &GrayImage,
+) -> ImageResult<()> {
+    if img.dimensions() != src.dimensions() || img.dimensions() != mask.dimensions() {
+        return Err(ImageError::Parameter(ParameterError::from_kind(
+            ParameterErrorKind::DimensionMismatch,
+        )));
+    }
+    let converted;
+    let src = if src.color() == img.color() {
+        src
+    } else {
+        converted = convert(src, img.color());
+        &converted
+    };
+    match (img, src) {
+        (DynamicImage::ImageLuma8(d), DynamicImage::ImageLuma8(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageLumaA8(d), DynamicImage::ImageLumaA8(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageRgb8(d), DynamicImage::ImageRgb8(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageRgba8(d), DynamicImage::ImageRgba8(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageLuma16(d), DynamicImage::ImageLuma16(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageLumaA16(d), DynamicImage::ImageLumaA16(s)) => {
+            merge_buffers(d, s, mask);
+        }
+        (DynamicImage::ImageRgb16(d), DynamicImage::ImageRgb16(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageRgba16(d), DynamicImage::ImageRgba16(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageRgb32F(d), DynamicImage::ImageRgb32F(s)) => merge_buffers(d, s, mask),
+        (DynamicImage::ImageRgba32F(d), DynamicImage::ImageRgba32F(s)) => {
+            merge_buffers(d, s, mask);
+        }
+        (img, src) => {
+            for (x, y, selected) in mask.enumerate_pixels() {
+                if selected[0] >= 128 {
+                    img.put_pixel(x, y, src.get_pixel(x, y));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy the pixels of `src` selected by `mask` into `img`, both of the same type
+fn merge_buffers<P: Pixel>(
+    img: &mut ImageBuffer<P, Vec<P::Subpixel>>,
+    src: &ImageBuffer<P, Vec<P::Subpixel>>,
+    mask: &GrayImage,
+) {
+    for ((pixel, src_pixel), selected) in img.pixels_mut().zip(src.pixels()).zip(mask.pixels()) {
+        if selected[0] >= 128 {
+            *pixel = *src_pixel;
+        }
+    }
+}
+
+/// Apply `func` only on a region of the image
+///
+/// The region is cropped, given to `func` and the result is copied back at the same position.
+/// If `func` changes the color type, the whole image is converted to the new color type.
+///
+/// # Errors
+/// Fails if the result of `func` does not fit in the image
+pub fn apply_in_region<F>(img: &DynamicImage, region: Region, func: F) -> ImageResult<DynamicImage>
+where
+    F: FnOnce(&DynamicImage) -> DynamicImage,
+{
+    let region = region.clamp_to(img);
+    let result = func(&crop(img, region));
+    let mut new_img = if result.color() == img.color() {
+        img.clone()
+    } else {
+        convert(img, result.color())
+    };
+    paste(&mut new_img, &result, region.x, region.y)?;
+    Ok(new_img)
+}
+
+/// display a `FilterType`
+pub(crate) fn display_filter_type(filter_type: &FilterType) -> &str {
+    match filter_type {
+        FilterType::Nearest => "Nearest",
+        FilterType::Triangle => "Triangle",
+        FilterType::CatmullRom => "CatmullRom",
+        FilterType::Gaussian => "Gaussian",
+        FilterType::Lanczos3 => "Lanczos3",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Luma, Rgb};
+
+    /// 4x4 rgba8 image with a different color per pixel
+    fn sample_rgba8() -> DynamicImage {
+        DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 4, |x, y| {
+            #[allow(clippy::cast_possible_truncation)]
+            let v = (x * 60 + y * 10) as u8;
+            Rgba([v, 255 - v, v / 2, 255])
+        }))
+    }
+
+    #[test]
+    fn region_clamp_and_union() {
+        let img = DynamicImage::new_rgb8(10, 10);
+        assert_eq!(
+            Region::new(8, 8, 5, 5).clamp_to(&img),
+            Region::new(8, 8, 2, 2)
+        );
+        assert_eq!(
+            Region::new(20, 0, 5, 5).clamp_to(&img),
+            Region::new(10, 0, 0, 5)
+        );
+        assert_eq!(
+            Region::new(1, 2, 2, 2).union(Region::new(4, 0, 1, 1)),
+            Region::new(1, 0, 4, 4)
+        );
+        assert_eq!(
+            Region::new(1, 2, 4, 4).intersection(Region::new(3, 0, 5, 3)),
+            Region::new(3, 2, 2, 1)
+        );
+        assert!(
+            Region::new(0, 0, 2, 2)
+                .intersection(Region::new(5, 5, 1, 1))
+                .is_empty()
+        );
+        assert!(Region::new(1, 1, 0, 3).is_empty());
+        assert_eq!(Region::full(&img), Region::new(0, 0, 10, 10));
+    }
+
+    #[test]
+    fn region_near_max() {
+        let far = Region::new(u32::MAX - 10, 0, 100, 5);
+        assert_eq!(
+            far.union(Region::new(0, 0, 5, 5)),
+            Region::new(0, 0, u32::MAX, 5)
+        );
+        assert_eq!(
+            far.intersection(Region::new(u32::MAX - 20, 0, 15, 5)),
+            Region::new(u32::MAX - 10, 0, 5, 5)
+        );
+        assert_eq!(
+            far.intersection(Region::new(u32::MAX - 5, 1, 100, 100)),
+            Region::new(u32::MAX - 5, 1, 5, 4)
+        );
+    }
+
+    #[test]
+    fn convert_changes_color_type() {
+        let img = sample_rgba8();
+        for color_type in [ColorType::L8, ColorType::La16, ColorType::Rgb32F] {
+            let res = convert(&img, color_type);
+            assert_eq!(res.color(), color_type);
+            assert_eq!(res.dimensions(), img.dimensions());
+        }
+    }
+
+    #[test]
+    fn with_alpha_keeps_depth() {
+        assert_eq!(with_alpha(ColorType::L16), ColorType::La16);
+        assert_eq!(with_alpha(ColorType::Rgb32F), ColorType::Rgba32F);
+        assert_eq!(with_alpha(ColorType::Rgba8), ColorType::Rgba8);
+    }
+
+    #[test]
+    fn grayscale_keeps_color_type() {
+        let img = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(2, 2, Rgb([1000, 20000, 300])));
+        let res = grayscale(&img);
+        assert_eq!(res.color(), ColorType::Rgb16);
+        let [r, g, b] = res
+            .as_rgb16()
+            .map(|buf| buf.get_pixel(0, 0).0)
+            .unwrap_or_default();
+        assert_eq!(r, g);
+        assert_eq!(g, b);
+        assert_ne!(r, 0);
+    }
+
+    #[test]
+    fn keep_channel_zeroes_other_channels() {
+        let img = sample_rgba8();
+        let res = keep_channel(&img, Channel::Green);
+        for (x, y, pixel) in res.pixels() {
+            let original = img.get_pixel(x, y);
+            assert_eq!(pixel.0, [0, original[1], 0, original[3]]);
+        }
+    }
+
+    #[test]
+    fn cut_color_makes_close_pixels_transparent() {
+        let mut img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(2, 1, Rgb([100, 100, 100])));
+        img.put_pixel(1, 0, Rgba([200, 10, 10, 255]));
+        let res = cut_color(&img, [105, 95, 100], 5);
+        assert_eq!(res.color(), ColorType::Rgba8);
+        assert_eq!(res.get_pixel(0, 0).0, [100, 100, 100, 0]);
+        assert_eq!(res.get_pixel(1, 0).0, [200, 10, 10, 255]);
+        let res = cut_color(&img, [105, 95, 100], 4);
+        assert_eq!(res.get_pixel(0, 0).0, [100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn median_filter_removes_noise() {
+        let mut img = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(5, 5, Luma([10])));
+        img.put_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let res = median_filter(&img, 1);
+        assert_eq!(res.color(), ColorType::L8);
+        assert_eq!(res.get_pixel(2, 2).0, [10, 10, 10, 255]);
+    }
+
+    #[test]
+    fn sobel_outline_of_uniform_image_is_black() {
+        let img = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, Rgba([80, 80, 80, 255])));
+        let res = sobel_outline(&img);
+        assert_eq!(res.color(), ColorType::Rgba8);
+        for (_, _, pixel) in res.pixels() {
+            assert_eq!(&pixel.0[..3], &[0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn operations_geometry() {
+        let img = DynamicImage::new_rgb8(100, 50);
+        let resize = |mode| Operation::Resize {
+            width: 50,
+            height: 50,
+            filter: FilterType::Nearest,
+            mode,
+        };
+        for (mode, size) in [
+            (ResizeMode::Fit, (50, 25)),
+            (ResizeMode::Exact, (50, 50)),
+            (ResizeMode::Fill, (50, 50)),
+        ] {
+            assert_eq!(resize(mode).apply(&img).dimensions(), size);
+            assert_eq!(resize(mode).resized_dimensions((100, 50)), Some(size));
+        }
+        let scale = Operation::Scale {
+            percent: 33.0,
+            filter: FilterType::Triangle,
+        };
+        assert_eq!(scale.apply(&img).dimensions(), (33, 17));
+        let long_edge = Operation::LongEdge {
+            size: 40,
+            filter: FilterType::Triangle,
+        };
+        assert_eq!(long_edge.apply(&img.rotate90()).dimensions(), (20, 40));
+        assert_eq!(Operation::Invert.resized_dimensions((100, 50)), None);
+        assert_eq!(
+            resize(ResizeMode::Exact)
+                .scaled(0.5)
+                .apply(&img)
+                .dimensions(),
+            (25, 25)
+        );
+        assert_eq!(Operation::Blur(4.0).scaled(0.25), Operation::Blur(1.0));
+        assert_eq!(Operation::Rotate90.apply(&img).dimensions(), (50, 100));
+        assert_eq!(Operation::Rotate180.apply(&img).dimensions(), (100, 50));
+        let img = sample_rgba8();
+        assert_eq!(
+            Operation::FlipHorizontal.apply(&img).get_pixel(0, 0),
+            img.get_pixel(3, 0)
+        );
+        assert_eq!(
+            Operation::FlipVertical.apply(&img).get_pixel(0, 0),
+            img.get_pixel(0, 3)
+        );
+    }
+
+    #[test]
+    fn invert_keeps_alpha() {
+        let img = sample_rgba8();
+        let res = Operation::Invert.apply(&img);
+        assert_eq!(res.color(), ColorType::Rgba8);
+        for (x, y, pixel) in res.pixels() {
+            let [r, g, b, a] = img.get_pixel(x, y).0;
+            assert_eq!(pixel.0, [255 - r, 255 - g, 255 - b, a]);
+        }
+    }
+
+    #[test]
+    fn blur_spreads_a_pixel() {
+        let mut img = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(5, 5, Luma([0])));
+        img.put_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let res = Operation::Blur(1.0).apply(&img);
+        assert_eq!(res.color(), ColorType::L8);
+        let value = |x, y| res.get_pixel(x, y)[0];
+        assert!(value(2, 2) < 255);
+        assert!(value(2, 2) > value(1, 2) && value(1, 2) > value(0, 2));
+        assert!(value(0, 0) > 0);
+        // symmetric around the pixel
+        assert_eq!(value(1, 2), value(3, 2));
+        assert_eq!(value(2, 1), value(2, 3));
+        assert_eq!(value(1, 2), value(2, 1));
+    }
+
+    #[test]
+    fn color_adjustments() {
+        let img = DynamicImage::ImageRgb8(ImageBuffer::from_fn(3, 1, |x, _| {
+            [Rgb([255, 0, 0]), Rgb([128, 128, 128]), Rgb([200, 60, 10])][x as usize]
+        }));
+        let pixels = |operation: Operation| {
+            let res = operation.apply(&img);
+            assert_eq!(res.color(), ColorType::Rgb8);
+            res.to_rgb8()
+                .pixels()
+                .map(|pixel| pixel.0)
+                .collect::<Vec<_>>()
+        };
+        let rotated = pixels(Operation::HueRotate(180));
+        assert_eq!(rotated[0], [0, 108, 108]);
+        assert!(rotated[1].iter().all(|c| c.abs_diff(128) <= 1));
+        assert_eq!(
+            pixels(Operation::HueRotate(0)),
+            [[255, 0, 0], [128, 128, 128], [200, 60, 10]]
+        );
+        assert_eq!(
+            pixels(Operation::Brighten(-30)),
+            [[225, 0, 0], [98, 98, 98], [170, 30, 0]]
+        );
+        assert_eq!(pixels(Operation::Brighten(100))[2], [255, 160, 110]);
+        assert_eq!(
+            pixels(Operation::Contrast(50.0)),
+            [[255, 0, 0], [128, 128, 128], [255, 0, 0]]
+        );
+        assert_eq!(pixels(Operation::Contrast(-50.0))[0], [159, 95, 95]);
+    }
+
+    #[test]
+    fn edge_detection_keeps_the_edges() {
+        let mut img = DynamicImage::ImageLuma8(ImageBuffer::from_pixel(5, 5, Luma([0])));
+        img.put_pixel(2, 2, Rgba([255, 255, 255, 255]));
+        let res = Operation::EdgeDetection.apply(&img);
+        assert_eq!(res.color(), ColorType::L8);
+        for (x, y, pixel) in res.pixels() {
+            let expected = if (x, y) == (2, 2) { 255 } else { 0 };
+            assert_eq!(pixel[0], expected);
+        }
+        let uniform = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(3, 3, Rgb([90, 90, 90])));
+        let res = Operation::EdgeDetection.apply(&uniform);
+        assert_eq!(res.get_pixel(1, 1), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn rotations_and_flips_move_the_pixels() {
+        let img = DynamicImage::ImageRgba8(ImageBuffer::from_fn(4, 2, |x, y| {
+            #[allow(clippy::cast_possible_truncation)]
+            let v = (x + 10 * y) as u8;
+            Rgba([v, 0, 0, 255])
+        }));
+        let (width, height) = img.dimensions();
+        let rotated = Operation::Rotate90.apply(&img);
+        assert_eq!(rotated.dimensions(), (height, width));
+        for (x, y, pixel) in rotated.pixels() {
+            assert_eq!(pixel, img.get_pixel(y, height - 1 - x));
+        }
+        let rotated = Operation::Rotate270.apply(&img);
+        assert_eq!(rotated.dimensions(), (height, width));
+        for (x, y, pixel) in rotated.pixels() {
+            assert_eq!(pixel, img.get_pixel(width - 1 - y, x));
+        }
+        let flipped = Operation::FlipHorizontal.apply(&img);
+        for (x, y, pixel) in flipped.pixels() {
+            assert_eq!(pixel, img.get_pixel(width - 1 - x, y));
+        }
+        let flipped = Operation::FlipVertical.apply(&img);
+        for (x, y, pixel) in flipped.pixels() {
+            assert_eq!(pixel, img.get_pixel(x, height - 1 - y));
+        }
+        let back = Operation::Rotate270.apply(&Operation::Rotate90.apply(&img));
+        assert_eq!(back, img);
+    }
+
+    #[test]
+    fn rotate_fits_and_fills() {
+        let img = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(100, 50, Rgb([9, 9, 9])));
+        let rotate = |degrees, fit| rotate(&img, degrees, Interpolation::Bilinear, fit, [255; 4]);
+        let expanded = rotate(90.0, RotateFit::Expand);
+        assert_eq!(expanded.dimensions(), (50, 100));
+        assert_eq!(expanded.color(), ColorType::Rgb16);
+        let expanded = rotate(45.0, RotateFit::Expand);
+        assert_eq!(expanded.dimensions(), (107, 107));
+        assert_eq!(expanded.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        let kept = rotate(-2.3, RotateFit::Keep);
+        assert_eq!(kept.dimensions(), (100, 50));
+        assert_eq!(kept.get_pixel(50, 25), Rgba([0, 0, 0, 255]));
+        let cropped = rotate(10.0, RotateFit::Crop);
+        assert!(cropped.width() < 100 && cropped.height() < 50);
+        assert!(cropped.pixels().all(|(_, _, pixel)| pixel[0] < 128));
+    }
+
+    #[test]
+    fn perspective_maps_corners_to_rectangle() {
+        let img = DynamicImage::ImageLuma8(ImageBuffer::from_fn(20, 20, |x, y| {
+            Luma([if (5..15).contains(&x) && (5..15).contains(&y) {
+                255
+            } else {
+                0
+            }])
+        }));
+        let corners = [[5.0, 5.0], [15.0, 5.0], [15.0, 15.0], [5.0, 15.0]];
+        let res = perspective(&img, corners, (30, 10), Interpolation::Nearest);
+        assert_eq!(res.dimensions(), (30, 10));
+        assert_eq!(res.color(), ColorType::L8);
+        assert!(res.pixels().all(|(_, _, pixel)| pixel[0] == 255));
+        let flat = [[0.0, 0.0], [1.0, 0.0], [2.0, 0.0], [3.0, 0.0]];
+        assert_eq!(perspective(&img, flat, (5, 5), Interpolation::Nearest), img);
+    }
+
+    #[test]
+    fn canvas_size_places_and_fills() {
+        let img = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(4, 2, Rgb([1000, 2000, 3000])));
+        let bigger = canvas_size(&img, (8, 6), Anchor::BottomRight, [255, 0, 0, 255]);
+        assert_eq!(bigger.dimensions(), (8, 6));
+        assert_eq!(bigger.color(), ColorType::Rgb16);
+        assert_eq!(bigger.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(bigger.to_rgb16().get_pixel(7, 5), &Rgb([1000, 2000, 3000]));
+        assert_eq!(bigger.get_pixel(3, 3), Rgba([255, 0, 0, 255]));
+        let transparent = canvas_size(&img, (6, 2), Anchor::Center, [0; 4]);
+        assert_eq!(transparent.color(), ColorType::Rgba16);
+        assert_eq!(transparent.get_pixel(0, 0)[3], 0);
+        assert_eq!(transparent.get_pixel(1, 0)[3], 255);
+        let smaller = canvas_size(&img, (2, 1), Anchor::Center, [0; 4]);
+        assert_eq!(smaller.dimensions(), (2, 1));
+        assert!(smaller.pixels().all(|(_, _, pixel)| pixel[3] == 255));
+    }
+
+    #[test]
+    fn trim_removes_uniform_borders() {
+        let img = DynamicImage::ImageLuma8(ImageBuffer::from_fn(10, 8, |x, y| {
+            Luma([match (x, y) {
+                (3, 2) => 0,
+                (6, 5) => 250,
+                _ => 255,
+            }])
+        }));
+        let trimmed = trim(&img, 0.0);
+        assert_eq!(trimmed.dimensions(), (4, 4));
+        assert_eq!(trimmed.color(), ColorType::L8);
+        assert_eq!(trim(&img, 5.0).dimensions(), (1, 1));
+        assert_eq!(trim(&img, 100.0), img);
+    }
+
+    #[test]
+    fn paste_is_lossless_and_checks_bounds() {
+        let mut img = DynamicImage::new_rgba16(4, 4);
+        let patch =
+            DynamicImage::ImageRgba16(ImageBuffer::from_pixel(2, 2, Rgba([1, 2, 3, 65535])));
+        assert!(paste(&mut img, &patch, 2, 2).is_ok());
+        let buffer = img.as_rgba16().cloned().unwrap_or_default();
+        assert_eq!(buffer.get_pixel(3, 3).0, [1, 2, 3, 65535]);
+        assert_eq!(buffer.get_pixel(1, 1).0, [0, 0, 0, 0]);
+        assert!(paste(&mut img, &patch, 3, 0).is_err());
+    }
+
+    #[test]
+    fn apply_in_region_only_changes_the_region() {
+        let img = sample_rgba8();
+        let region = Region::new(1, 1, 2, 2);
+        let res =
+            apply_in_region(&img, region, |part| Operation::Invert.apply(part)).unwrap_or_default();
+        for (x, y, pixel) in res.pixels() {
+            let mut expected = img.get_pixel(x, y);
+            if (1..3).contains(&x) && (1..3).contains(&y) {
+                expected.invert();
+            }
+            assert_eq!(pixel, expected);
+        }
+    }
+
+    #[test]
+    fn merge_masked_only_copies_selected_pixels() {
+        let mut img = DynamicImage::new_rgb16(2, 2);
+        let src = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(2, 2, Rgb([7, 8, 9])));
+        let mask = GrayImage::from_fn(2, 2, |x, y| image::Luma([if x == y { 255 } else { 0 }]));
+        assert!(merge_masked(&mut img, &src, &mask).is_ok());
+        let buffer = img.as_rgb16().cloned().unwrap_or_default();
+        assert_eq!(buffer.get_pixel(0, 0).0, [7, 8, 9]);
+        assert_eq!(buffer.get_pixel(1, 1).0, [7, 8, 9]);
+        assert_eq!(buffer.get_pixel(1, 0).0, [0, 0, 0]);
+        assert!(merge_masked(&mut img, &src, &GrayImage::new(1, 2)).is_err());
+    }
+
+    #[test]
+    fn apply_in_region_converts_the_image() {
+        let img = sample_rgba8();
+        let res = apply_in_region(&img, Region::new(0, 0, 2, 2), |part| median_filter(part, 1))
+            .unwrap_or_default();
+        assert_eq!(res.color(), ColorType::L8);
+        assert_eq!(res.dimensions(), img.dimensions());
+    }
+}
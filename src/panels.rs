@@ -76,6 +76,8 @@ impl BladvakPanel for ImageInfo {
         });
         app.grid.show_settings(ui);
         ui.separator();
+        ui.checkbox(&mut app.settings.raw_orientation, "Keep raw orientation")
+            .on_hover_text("Do not turn the opened images upright with the exif Orientation tag");
         if ui.button("Default image").clicked() {
             let (img, cursor) = TarsierApp::load_default_image();
             app.new_file(PathBuf::from("tarsier.png"), img, Some(cursor));
@@ -92,6 +94,80 @@ impl BladvakPanel for ImageInfo {
     }
 }
 
+/// Panel for layers
+#[derive(Debug)]
+pub(crate) struct LayersPanel;
+
+impl BladvakPanel for LayersPanel {
+    type App = TarsierApp;
+
+    fn name(&self) -> &'static str {
+        "Layers"
+    }
+
+    fn has_settings(&self) -> bool {
+        false
+    }
+
+    fn ui_settings(
+        &self,
+        _app: &mut Self::App,
+        _ui: &mut egui::Ui,
+        _error_manager: &mut bladvak::ErrorManager,
+    ) {
+    }
+
+    fn has_ui(&self) -> bool {
+        true
+    }
+
+    fn ui(
+        &self,
+        app: &mut Self::App,
+        ui: &mut egui::Ui,
+        _error_manager: &mut bladvak::ErrorManager,
+    ) {
+        app.layers_ui(ui);
+    }
+}
+
+/// Panel for macros
+#[derive(Debug)]
+pub(crate) struct MacrosPanel;
+
+impl BladvakPanel for MacrosPanel {
+    type App = TarsierApp;
+
+    fn name(&self) -> &'static str {
+        "Macros"
+    }
+
+    fn has_settings(&self) -> bool {
+        false
+    }
+
+    fn ui_settings(
+        &self,
+        _app: &mut Self::App,
+        _ui: &mut egui::Ui,
+        _error_manager: &mut bladvak::ErrorManager,
+    ) {
+    }
+
+    fn has_ui(&self) -> bool {
+        true
+    }
+
+    fn ui(
+        &self,
+        app: &mut Self::App,
+        ui: &mut egui::Ui,
+        error_manager: &mut bladvak::ErrorManager,
+    ) {
+        app.macros_ui(ui, error_manager);
+    }
+}
+
 /// Panel for cursor operations
 #[derive(Debug)]
 pub(crate) struct CursorInfo;
@@ -129,8 +205,8 @@ impl BladvakPanel for CursorInfo {
         &self,
         app: &mut Self::App,
         ui: &mut egui::Ui,
-        _error_manager: &mut bladvak::ErrorManager,
+        error_manager: &mut bladvak::ErrorManager,
     ) {
-        app.cursor_ui(ui);
+        app.cursor_ui(ui, error_manager);
     }
 }
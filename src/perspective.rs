@@ -0,0 +1,182 @@
+//! Perspective correction: warp a quadrilateral of the canvas to a rectangle
+
+use bladvak::eframe::egui::{self, Color32, Pos2, Rect, Stroke, Vec2};
+use bladvak::errors::ErrorManager;
+
+use crate::TarsierApp;
+use crate::ops::{Interpolation, Operation};
+use crate::selection;
+
+/// Perspective correction settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct Perspective {
+    /// Compute the output size from the corners
+    pub(crate) auto_size: bool,
+    /// Output width
+    pub(crate) width: u32,
+    /// Output height
+    pub(crate) height: u32,
+    /// Interpolation of the pixels
+    pub(crate) interpolation: Interpolation,
+}
+
+impl Default for Perspective {
+    fn default() -> Self {
+        Self {
+            auto_size: true,
+            width: 800,
+            height: 600,
+            interpolation: Interpolation::Bicubic,
+        }
+    }
+}
+
+/// Corners dragged on the canvas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Quad {
+    /// Top left, top right, bottom right and bottom left corners, in canvas coordinates
+    pub(crate) corners: [Pos2; 4],
+    /// Index of the dragged corner
+    grabbed: Option<usize>,
+}
+
+impl Quad {
+    /// Corners of a rectangle of the canvas
+    pub(crate) fn new(rect: Rect) -> Self {
+        Self {
+            corners: [
+                rect.left_top(),
+                rect.right_top(),
+                rect.right_bottom(),
+                rect.left_bottom(),
+            ],
+            grabbed: None,
+        }
+    }
+
+    /// Size of the rectangle, the longest opposite sides
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn size(&self) -> (u32, u32) {
+        let [top_left, top_right, bottom_right, bottom_left] = self.corners;
+        let width = top_left
+            .distance(top_right)
+            .max(bottom_left.distance(bottom_right));
+        let height = top_left
+            .distance(bottom_left)
+            .max(top_right.distance(bottom_right));
+        (width.round() as u32, height.round() as u32)
+    }
+
+    /// Grab the corner near a position
+    pub(crate) fn grab(&mut self, pos: Pos2, radius: f32) {
+        self.grabbed = self
+            .corners
+            .iter()
+            .enumerate()
+            .map(|(idx, corner)| (idx, corner.distance(pos)))
+            .filter(|(_, distance)| *distance <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(idx, _)| idx);
+    }
+
+    /// Move the grabbed corner
+    pub(crate) fn drag(&mut self, pos: Pos2) {
+        if let Some(idx) = self.grabbed {
+            self.corners[idx] = pos;
+        }
+    }
+
+    /// Release the grabbed corner
+    pub(crate) fn release(&mut self) {
+        self.grabbed = None;
+    }
+
+    /// Paint the quadrilateral and its corners, at `image_rect` on the screen
+    pub(crate) fn paint(&self, ui: &egui::Ui, image_rect: Rect, color: Color32) {
+        let to_screen = |pos: Pos2| pos + image_rect.min.to_vec2();
+        let radius = selection::handle_radius(ui);
+        let stroke = Stroke::new(radius / 4.0, color);
+        let painter = ui.painter();
+        for idx in 0..4 {
+            let (start, end) = (self.corners[idx], self.corners[(idx + 1) % 4]);
+            painter.line_segment([to_screen(start), to_screen(end)], stroke);
+        }
+        for corner in self.corners {
+            painter.rect(
+                Rect::from_center_size(to_screen(corner), Vec2::splat(radius)),
+                0.0,
+                Color32::WHITE,
+                Stroke::new(radius / 8.0, Color32::BLACK),
+                egui::StrokeKind::Middle,
+            );
+        }
+    }
+}
+
+impl TarsierApp {
+    /// Perspective correction ui
+    pub(crate) fn perspective_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        ui.label("Drag the corners on the canvas");
+        let settings = &mut self.mode.perspective;
+        let Some(quad) = document.selection.quad else {
+            return;
+        };
+        if settings.auto_size {
+            (settings.width, settings.height) = quad.size();
+        }
+        ui.checkbox(&mut settings.auto_size, "Size from the corners");
+        ui.add_enabled_ui(!settings.auto_size, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Size");
+                ui.add(egui::DragValue::new(&mut settings.width).range(1..=16384));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut settings.height).range(1..=16384));
+            });
+        });
+        ui.horizontal(|ui| {
+            for interpolation in Interpolation::ALL {
+                ui.selectable_value(
+                    &mut settings.interpolation,
+                    interpolation,
+                    format!("{interpolation:?}"),
+                );
+            }
+        });
+        let mut apply = false;
+        ui.horizontal(|ui| {
+            if ui.button("Reset").clicked() {
+                document.selection.quad = None;
+            }
+            apply = ui.button("Apply").clicked();
+        });
+        if !apply {
+            return;
+        }
+        // the corners are relative to the part of the active layer the operation gets
+        let layer = document.layer();
+        let (x, y) = document.selection_region().map_or((0, 0), |region| {
+            let layer_region = layer.to_layer_region(region);
+            (layer_region.x, layer_region.y)
+        });
+        #[allow(clippy::cast_precision_loss)]
+        let origin = Vec2::new(
+            (i64::from(x) + i64::from(layer.props.offset.0)) as f32,
+            (i64::from(y) + i64::from(layer.props.offset.1)) as f32,
+        );
+        let operation = Operation::Perspective {
+            corners: quad.corners.map(|corner| {
+                let corner = corner - origin;
+                [corner.x, corner.y]
+            }),
+            width: settings.width,
+            height: settings.height,
+            interpolation: settings.interpolation,
+        };
+        document.selection.quad = None;
+        self.apply_operation(&operation, error_manager);
+    }
+}
@@ -0,0 +1,253 @@
+//! Live preview of an operation, computed on a downscaled copy of the document
+
+use bladvak::eframe::egui::{self, Color32, Pos2, Rect, TextureHandle, TextureId, TextureOptions};
+use bladvak::errors::ErrorManager;
+use image::{GenericImageView, imageops::FilterType};
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::edit_mode::SelectionState;
+use crate::ops::Operation;
+use crate::selection::SelectionMask;
+
+/// Longest edge of the downscaled copy used for the preview
+const PROXY_SIZE: u32 = 1024;
+
+/// Downscaled copy of a document
+struct Proxy {
+    /// Texture of the document when the copy was made, it changes with the document
+    source: TextureId,
+    /// Downscaled document
+    document: Document,
+    /// Scale of the copy
+    scale: f32,
+}
+
+/// Preview of the pending operation
+#[derive(Default)]
+pub(crate) struct Preview {
+    /// Preview the operations instead of applying them
+    pub(crate) enabled: bool,
+    /// Operation waiting to be applied
+    pub(crate) operation: Option<Operation>,
+    /// Position of the split between before (left) and after (right), from 0 to 1
+    split: f32,
+    /// Downscaled copy of the document
+    proxy: Option<Proxy>,
+    /// Result of the operation on the copy, with the operation
+    texture: Option<(Operation, TextureHandle)>,
+}
+
+impl std::fmt::Debug for Preview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Preview")
+            .field("enabled", &self.enabled)
+            .field("operation", &self.operation)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Preview {
+    /// Show `operation` in the preview
+    pub(crate) fn set(&mut self, operation: Operation) {
+        if self.operation.is_none() {
+            self.split = 0.5;
+        }
+        self.operation = Some(operation);
+    }
+
+    /// Drop the pending operation
+    pub(crate) fn cancel(&mut self) {
+        self.operation = None;
+        self.texture = None;
+    }
+
+    /// Compute the preview of the pending operation if the operation or the document changed
+    fn update(&mut self, ctx: &egui::Context, document: &Document) {
+        let (Some(operation), Some(source)) = (&self.operation, &document.texture) else {
+            return;
+        };
+        if self
+            .proxy
+            .as_ref()
+            .is_none_or(|proxy| proxy.source != source.id())
+        {
+            let (proxy, scale) = document.proxy(PROXY_SIZE);
+            self.proxy = Some(Proxy {
+                source: source.id(),
+                document: proxy,
+                scale,
+            });
+            self.texture = None;
+        }
+        if self
+            .texture
+            .as_ref()
+            .is_some_and(|(shown, _)| shown == operation)
+        {
+            return;
+        }
+        let Some(proxy) = &self.proxy else {
+            return;
+        };
+        let mut after = proxy.document.clone_layers();
+        let region = after.selection_region();
+        let mask = region.and_then(|region| after.selection_mask(region));
+        if after
+            .apply_operation(region, mask.as_ref(), &operation.scaled(proxy.scale))
+            .is_err()
+        {
+            self.texture = None;
+            return;
+        }
+        let rgba = after.composite().to_rgba8();
+        let (width, height) = rgba.dimensions();
+        let image = egui::ColorImage::from_rgba_unmultiplied(
+            [width as usize, height as usize],
+            rgba.as_flat_samples().as_slice(),
+        );
+        let texture = ctx.load_texture("preview", image, TextureOptions::LINEAR);
+        self.texture = Some((operation.clone(), texture));
+    }
+
+    /// Paint the result right of the split, over the image at `image_rect` on the screen
+    pub(crate) fn paint(
+        &mut self,
+        ui: &egui::Ui,
+        document: &Document,
+        image_rect: Rect,
+        color: Color32,
+    ) {
+        self.update(ui.ctx(), document);
+        let (Some((_, texture)), Some(proxy)) = (&self.texture, &self.proxy) else {
+            return;
+        };
+        let after_rect = Rect::from_min_size(image_rect.min, texture.size_vec2() / proxy.scale);
+        let split_x = egui::lerp(image_rect.x_range(), self.split);
+        let mut clip = after_rect.union(image_rect);
+        clip.min.x = split_x;
+        let painter = ui.painter().with_clip_rect(clip);
+        // hide the pixels before the operation when the result is smaller
+        painter.rect_filled(image_rect, 0.0, ui.visuals().extreme_bg_color);
+        painter.image(
+            texture.id(),
+            after_rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        ui.painter().vline(
+            split_x,
+            clip.y_range(),
+            egui::Stroke::new(image_rect.width() / 400.0, color),
+        );
+    }
+}
+
+impl Document {
+    /// Copy of the document with its longest edge at most `max_size`, with the scale of the copy
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_precision_loss)]
+    fn proxy(&self, max_size: u32) -> (Self, f32) {
+        let (width, height) = self.size();
+        let scale = (max_size as f32 / width.max(height).max(1) as f32).min(1.0);
+        let pixels = |value: u32| ((value as f32 * scale).round() as u32).max(1);
+        let scale_image = |img: &image::DynamicImage| {
+            let (width, height) = img.dimensions();
+            img.resize_exact(pixels(width), pixels(height), FilterType::Triangle)
+        };
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| {
+                let mut proxy = layer.clone_props();
+                proxy.img = scale_image(&layer.img);
+                proxy.props.offset = (
+                    (layer.props.offset.0 as f32 * scale).round() as i32,
+                    (layer.props.offset.1 as f32 * scale).round() as i32,
+                );
+                proxy
+            })
+            .collect();
+        let selection = SelectionState {
+            rectangle: self
+                .selection
+                .rectangle
+                .map(|rect| Rect::from_min_max(rect.min * scale, rect.max * scale)),
+            mask: self.selection.mask.as_ref().and_then(|mask| {
+                let img = image::DynamicImage::ImageLuma8(mask.img.clone());
+                SelectionMask::new(scale_image(&img).to_luma8())
+            }),
+            ..Default::default()
+        };
+        let proxy = Self {
+            layers,
+            active_layer: self.active_layer,
+            selection,
+            ..Default::default()
+        };
+        (proxy, scale)
+    }
+
+    /// Copy of the layers and selection, without the history
+    fn clone_layers(&self) -> Self {
+        Self {
+            layers: self.layers.clone(),
+            active_layer: self.active_layer,
+            selection: SelectionState {
+                rectangle: self.selection.rectangle,
+                mask: self.selection.mask.clone(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl TarsierApp {
+    /// Preview toggle, and the pending operation with its split, apply and cancel buttons
+    pub(crate) fn preview_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        if ui
+            .checkbox(&mut self.preview.enabled, "Live preview")
+            .on_hover_text("Preview the operations before applying them")
+            .changed()
+            && !self.preview.enabled
+        {
+            self.preview.cancel();
+        }
+        let Some(operation) = self.preview.operation.clone() else {
+            return;
+        };
+        ui.label(format!("Preview: {operation}"));
+        ui.add(
+            egui::Slider::new(&mut self.preview.split, 0.0..=1.0)
+                .show_value(false)
+                .text("Before / after"),
+        );
+        ui.horizontal(|ui| {
+            if ui.button("Apply").clicked() {
+                self.preview.cancel();
+                self.apply_operation(&operation, error_manager);
+            }
+            if ui.button("Cancel").clicked() {
+                self.preview.cancel();
+            }
+        });
+    }
+
+    /// Apply an operation, or preview it when the preview is enabled
+    pub(crate) fn run_operation(&mut self, operation: Operation, error_manager: &mut ErrorManager) {
+        if self.preview.enabled {
+            self.preview.set(operation);
+        } else {
+            self.apply_operation(&operation, error_manager);
+        }
+    }
+
+    /// Update the previewed operation when its settings changed
+    pub(crate) fn preview_changed(&mut self, changed: bool, operation: Operation) {
+        if self.preview.enabled && changed {
+            self.preview.set(operation);
+        }
+    }
+}
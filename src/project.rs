@@ -0,0 +1,407 @@
+//! Project file: a tar archive with a json manifest and the images of the document
+//!
+//! ```text
+//! manifest.json
+//! exif.bin        raw exif, if any
+//! selection.png   mask of the selection, if not rectangular
+//! layers/<i>.png  image of each layer (.exr for float images)
+//! saved/<i>.png   saved state of each layer
+//! history/<i>.png images of the history
+//! ```
+
+use bladvak::eframe::egui;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+use crate::document::Document;
+use crate::edit_mode::SelectionState;
+use crate::history::{History, StoredHistory};
+use crate::layer::Layer;
+use crate::selection::SelectionMask;
+
+/// Extension of the project files
+pub(crate) const PROJECT_EXTENSION: &str = "tarsier";
+
+/// Version of the project format
+const PROJECT_VERSION: u32 = 1;
+
+/// Name of the manifest in the archive
+const MANIFEST: &str = "manifest.json";
+
+/// Name of the exif blob in the archive
+const EXIF: &str = "exif.bin";
+
+/// Name of the selection mask in the archive, without extension
+const SELECTION_MASK: &str = "selection";
+
+/// Manifest of a project
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+struct Manifest {
+    /// Version of the project format
+    version: u32,
+    /// Path to save the image
+    filename: PathBuf,
+    /// Layers, without their images
+    layers: Vec<Layer>,
+    /// Index of the active layer
+    active_layer: usize,
+    /// Saved state of the layers, without their images
+    saved_layers: Vec<Layer>,
+    /// Selection rectangle
+    selection: Option<egui::Rect>,
+    /// Scene zoom and pan, if already shown
+    scene_rect: Option<egui::Rect>,
+    /// Undo/redo history
+    history: StoredHistory,
+}
+
+/// Format used to store an image: png if possible, `OpenEXR` for float images
+fn image_format(img: &DynamicImage) -> ImageFormat {
+    match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => ImageFormat::OpenExr,
+        _ => ImageFormat::Png,
+    }
+}
+
+/// Builder of the project archive
+struct ProjectWriter {
+    /// Tar archive
+    builder: tar::Builder<Vec<u8>>,
+}
+
+impl ProjectWriter {
+    /// Add a file to the archive
+    fn add_file(&mut self, path: &str, data: &[u8]) -> Result<(), String> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        self.builder
+            .append_data(&mut header, path, data)
+            .map_err(|e| format!("Cannot write {path} in project: {e}"))
+    }
+
+    /// Add an image to the archive
+    fn add_image(&mut self, path: &str, img: &DynamicImage) -> Result<(), String> {
+        let format = image_format(img);
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), format)
+            .map_err(|e| format!("Cannot write image {path}: {e}"))?;
+        self.add_file(&format!("{path}.{}", format.extensions_str()[0]), &bytes)
+    }
+
+    /// Add the images of layers in a folder of the archive
+    fn add_layers(&mut self, folder: &str, layers: &[Layer]) -> Result<(), String> {
+        for (idx, layer) in layers.iter().enumerate() {
+            self.add_image(&format!("{folder}/{idx}"), &layer.img)?;
+        }
+        Ok(())
+    }
+}
+
+/// Files of a project archive
+struct ProjectReader {
+    /// Content of the files, by path
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl ProjectReader {
+    /// Read all the files of the archive
+    fn new(data: &[u8]) -> Result<Self, String> {
+        let mut archive = tar::Archive::new(Cursor::new(data));
+        let mut files = HashMap::new();
+        let entries = archive
+            .entries()
+            .map_err(|e| format!("Invalid project file: {e}"))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| format!("Invalid project file: {e}"))?;
+            let path = entry
+                .path()
+                .map_err(|e| format!("Invalid project file: {e}"))?
+                .to_string_lossy()
+                .into_owned();
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| format!("Cannot read {path} in project: {e}"))?;
+            files.insert(path, content);
+        }
+        Ok(Self { files })
+    }
+
+    /// Read an image, without its extension
+    fn image(&mut self, path: &str) -> Result<DynamicImage, String> {
+        for format in [ImageFormat::Png, ImageFormat::OpenExr] {
+            let name = format!("{path}.{}", format.extensions_str()[0]);
+            if let Some(bytes) = self.files.remove(&name) {
+                let mut reader = ImageReader::new(Cursor::new(bytes));
+                reader.set_format(format);
+                return reader
+                    .decode()
+                    .map_err(|e| format!("Cannot decode {name} in project: {e}"));
+            }
+        }
+        Err(format!("Missing image {path} in project"))
+    }
+
+    /// Read the images of layers from a folder of the archive
+    fn layers(&mut self, folder: &str, layers: Vec<Layer>) -> Result<Vec<Layer>, String> {
+        layers
+            .into_iter()
+            .enumerate()
+            .map(|(idx, mut layer)| {
+                layer.img = self.image(&format!("{folder}/{idx}"))?;
+                Ok(layer)
+            })
+            .collect()
+    }
+}
+
+impl Document {
+    /// Export the document as a project file
+    /// # Errors
+    /// Fails if an image cannot be written
+    pub(crate) fn to_project(&self) -> Result<Vec<u8>, String> {
+        let mut writer = ProjectWriter {
+            builder: tar::Builder::new(Vec::new()),
+        };
+        writer.add_layers("layers", &self.layers)?;
+        writer.add_layers("saved", &self.saved_layers)?;
+        let mut history_count = 0;
+        let history = self.history.store(|img| {
+            writer.add_image(&format!("history/{history_count}"), img)?;
+            history_count += 1;
+            Ok(history_count - 1)
+        })?;
+        if let Some(exif) = &self.exif {
+            writer.add_file(EXIF, exif.buf())?;
+        }
+        if let Some(mask) = &self.selection.mask {
+            writer.add_image(SELECTION_MASK, &DynamicImage::ImageLuma8(mask.img.clone()))?;
+        }
+        let manifest = Manifest {
+            version: PROJECT_VERSION,
+            filename: self.filename.clone(),
+            layers: self.layers.iter().map(Layer::clone_props).collect(),
+            active_layer: self.active_layer,
+            saved_layers: self.saved_layers.iter().map(Layer::clone_props).collect(),
+            selection: self.selection.rectangle,
+            scene_rect: self.scene_rect.is_finite().then_some(self.scene_rect),
+            history,
+        };
+        let manifest = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| format!("Cannot write manifest: {e}"))?;
+        writer.add_file(MANIFEST, &manifest)?;
+        writer
+            .builder
+            .into_inner()
+            .map_err(|e| format!("Cannot write project: {e}"))
+    }
+
+    /// Load a document from a project file
+    /// # Errors
+    /// Fails if the project is invalid
+    pub(crate) fn from_project(data: &[u8]) -> Result<Self, String> {
+        let mut reader = ProjectReader::new(data)?;
+        let manifest = reader
+            .files
+            .remove(MANIFEST)
+            .ok_or_else(|| "Missing manifest in project".to_string())?;
+        let manifest: Manifest =
+            serde_json::from_slice(&manifest).map_err(|e| format!("Invalid manifest: {e}"))?;
+        if manifest.version > PROJECT_VERSION {
+            return Err(format!(
+                "Project version {} is not supported",
+                manifest.version
+            ));
+        }
+        let layers = reader.layers("layers", manifest.layers)?;
+        if layers.is_empty() {
+            return Err("No layer in project".to_string());
+        }
+        let saved_layers = reader.layers("saved", manifest.saved_layers)?;
+        let history = History::restore(manifest.history, |idx| {
+            reader.image(&format!("history/{idx}"))
+        })?;
+        let exif = reader
+            .files
+            .remove(EXIF)
+            .and_then(|buf| exif::Reader::new().read_raw(buf).ok());
+        let mask = reader
+            .image(SELECTION_MASK)
+            .ok()
+            .and_then(|mask| SelectionMask::new(mask.to_luma8()));
+        Ok(Self {
+            active_layer: manifest.active_layer.min(layers.len() - 1),
+            layers,
+            saved_layers,
+            texture: None,
+            exif,
+            filename: manifest.filename,
+            selection: SelectionState {
+                rectangle: manifest.selection,
+                mask,
+                ..Default::default()
+            },
+            scene_rect: manifest.scene_rect.unwrap_or(egui::Rect::NAN),
+            history,
+            floating: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layer::{BlendMode, LayerProps};
+    use crate::metadata;
+    use exif::{Field, In, Tag, Value};
+    use image::{Rgb, Rgb32FImage, RgbImage, Rgba, Rgba32FImage};
+
+    /// 4x3 image with a different value per pixel
+    #[allow(clippy::cast_possible_truncation)]
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(4, 3, |x, y| {
+            Rgb([(10 * x) as u8, (10 * y) as u8, 7])
+        }))
+    }
+
+    /// Document with two layers, a float layer, a selection, an exif and a history
+    fn sample_document() -> Document {
+        let mut document = Document::new("image.png".into(), gradient());
+        document.set_layer_props(
+            0,
+            LayerProps {
+                opacity: 0.8,
+                blend: BlendMode::Screen,
+                offset: (-1, 0),
+                ..document.layers[0].props.clone()
+            },
+            false,
+        );
+        let mut float = Layer::new(
+            "float",
+            DynamicImage::ImageRgba32F(Rgba32FImage::from_fn(2, 2, |x, y| {
+                #[allow(clippy::cast_precision_loss)]
+                Rgba([0.25 * x as f32, 1.5, -0.5 * y as f32, 0.75])
+            })),
+        );
+        float.props.opacity = 0.5;
+        float.props.blend = BlendMode::Multiply;
+        float.props.offset = (1, 2);
+        document.add_layer(float);
+        document.active_layer = 0;
+        document.set_image(DynamicImage::ImageRgb8(RgbImage::new(4, 3)));
+        document.set_image(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            4,
+            3,
+            Rgb([1, 2, 3]),
+        )));
+        // one change to redo
+        document.undo();
+        document.selection.set_rectangle(egui::Rect::from_min_max(
+            egui::pos2(1.0, 0.0),
+            egui::pos2(4.0, 3.0),
+        ));
+        document
+            .selection
+            .set_mask(crate::selection::SelectionMask::ellipse(
+                4,
+                3,
+                egui::Rect::from_min_max(egui::pos2(1.0, 0.0), egui::pos2(4.0, 3.0)),
+            ));
+        let artist = Field {
+            tag: Tag::Artist,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"tarsier".to_vec()]),
+        };
+        let Ok(exif) = metadata::with_field(None, artist) else {
+            panic!("the exif can be written");
+        };
+        document.exif = Some(exif);
+        document.scene_rect =
+            egui::Rect::from_min_size(egui::pos2(-10.0, 5.0), egui::vec2(200.0, 150.0));
+        document
+    }
+
+    /// Check that two documents have the same layers, with their properties
+    fn assert_same_layers(loaded: &[Layer], expected: &[Layer]) {
+        assert_eq!(loaded.len(), expected.len());
+        for (loaded, expected) in loaded.iter().zip(expected) {
+            assert_eq!(loaded.props, expected.props);
+            assert_eq!(loaded.img, expected.img);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut document = sample_document();
+        let Ok(data) = document.to_project() else {
+            panic!("the document can be exported");
+        };
+        let Ok(mut loaded) = Document::from_project(&data) else {
+            panic!("the project can be loaded");
+        };
+        assert_eq!(loaded.filename, document.filename);
+        assert_eq!(loaded.active_layer, document.active_layer);
+        assert_same_layers(&loaded.layers, &document.layers);
+        assert!(matches!(
+            loaded.layers[1].img,
+            DynamicImage::ImageRgba32F(_)
+        ));
+        assert_same_layers(&loaded.saved_layers, &document.saved_layers);
+        assert_eq!(loaded.selection.rectangle, document.selection.rectangle);
+        assert_eq!(
+            loaded.selection.mask.as_ref().map(|mask| &mask.img),
+            document.selection.mask.as_ref().map(|mask| &mask.img)
+        );
+        assert_eq!(
+            loaded.exif.as_ref().map(exif::Exif::buf),
+            document.exif.as_ref().map(exif::Exif::buf)
+        );
+        assert_eq!(loaded.scene_rect, document.scene_rect);
+        // the history goes on after the reload
+        for _ in 0..2 {
+            loaded.redo();
+            document.redo();
+            assert_same_layers(&loaded.layers, &document.layers);
+        }
+        for _ in 0..5 {
+            loaded.undo();
+            document.undo();
+            assert_same_layers(&loaded.layers, &document.layers);
+        }
+        assert!(!loaded.history.can_undo());
+        assert_eq!(loaded.layers.len(), 1);
+        assert_eq!(loaded.img(), &gradient());
+    }
+
+    #[test]
+    fn float_layer_stays_float() {
+        let img = DynamicImage::ImageRgb32F(Rgb32FImage::from_pixel(2, 1, Rgb([2.5, -1.0, 0.5])));
+        assert_eq!(image_format(&img), ImageFormat::OpenExr);
+        let Ok(data) = Document::new("a.exr".into(), img.clone()).to_project() else {
+            panic!("the document can be exported");
+        };
+        let Ok(loaded) = Document::from_project(&data) else {
+            panic!("the project can be loaded");
+        };
+        // OpenEXR keeps the values out of 0..1
+        assert_eq!(loaded.img().to_rgb32f(), img.to_rgb32f());
+    }
+
+    #[test]
+    fn invalid_projects() {
+        assert!(Document::from_project(b"not a tar").is_err());
+        let mut writer = ProjectWriter {
+            builder: tar::Builder::new(Vec::new()),
+        };
+        assert!(writer.add_file(MANIFEST, b"{}").is_ok());
+        let Ok(data) = writer.builder.into_inner() else {
+            panic!("the archive can be written");
+        };
+        assert!(Document::from_project(&data).is_err());
+    }
+}
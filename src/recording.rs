@@ -0,0 +1,375 @@
+//! Macros: record and replay operations
+
+use bladvak::eframe::egui::{self, Color32};
+use bladvak::errors::{AppError, ErrorManager};
+use image::ImageFormat;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::export::ExportOptions;
+use crate::formats::SaveFormat;
+use crate::ops::{Operation, Region};
+
+/// One step of a macro
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub(crate) enum MacroStep {
+    /// Operation, applied on a region if there was a selection
+    Operation {
+        /// operation with its parameters
+        operation: Operation,
+        /// rectangular selection when recorded, masked selections are not recorded
+        region: Option<Region>,
+    },
+    /// Crop to a region
+    Crop(Region),
+    /// Save next to the document with a format
+    Save(ImageFormat),
+}
+
+impl std::fmt::Display for MacroStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Operation {
+                operation,
+                region: None,
+            } => write!(f, "{operation}"),
+            Self::Operation {
+                operation,
+                region: Some(region),
+            } => write!(
+                f,
+                "{operation} in {}x{} at ({}, {})",
+                region.width, region.height, region.x, region.y
+            ),
+            Self::Crop(region) => write!(
+                f,
+                "Crop {}x{} at ({}, {})",
+                region.width, region.height, region.x, region.y
+            ),
+            Self::Save(format) => write!(f, "Save as {format:?}"),
+        }
+    }
+}
+
+/// Recorded macro
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub(crate) struct Macro {
+    /// name of the macro
+    pub(crate) name: String,
+    /// steps of the macro
+    pub(crate) steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    /// Replay the macro on a document, saving with the export options
+    /// # Errors
+    /// Fails if a step fails or if a save would overwrite an existing file
+    pub(crate) fn replay(
+        &self,
+        document: &mut Document,
+        options: &ExportOptions,
+    ) -> Result<(), AppError> {
+        for step in &self.steps {
+            match step {
+                MacroStep::Operation { operation, region } => {
+                    document.apply_operation(*region, None, operation)?;
+                }
+                MacroStep::Crop(region) => {
+                    document.crop(*region);
+                }
+                MacroStep::Save(format) => {
+                    let save_format = SaveFormat::get(*format);
+                    let extension =
+                        save_format.map_or(format.extensions_str()[0], |save| save.extension);
+                    let path = document.filename.with_extension(extension);
+                    // no prompt during a replay: never overwrite a file
+                    if path.exists() {
+                        return Err(AppError::new(format!(
+                            "'{}' already exists, the macro does not overwrite files",
+                            path.display()
+                        )));
+                    }
+                    // no prompt during a replay: use the closest color type of the format
+                    let color_type =
+                        save_format.map(|save| save.closest_color_type(document.color_type()));
+                    document.save_image(*format, &path, color_type, options)?;
+                    document.filename = path;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse a macro from json
+    /// # Errors
+    /// Fails if the json is invalid
+    pub(crate) fn from_json(data: &[u8]) -> Result<Self, AppError> {
+        serde_json::from_slice(data)
+            .map_err(|e| AppError::new_with_source("Invalid macro file", Arc::new(e)))
+    }
+
+    /// Export the macro to json
+    /// # Errors
+    /// Fails if the macro cannot be serialized
+    pub(crate) fn to_json(&self) -> Result<Vec<u8>, AppError> {
+        serde_json::to_vec_pretty(self)
+            .map_err(|e| AppError::new_with_source("Cannot export macro", Arc::new(e)))
+    }
+}
+
+/// Macros state
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub(crate) struct Macros {
+    /// Macro being recorded
+    #[serde(skip)]
+    pub(crate) recording: Option<Macro>,
+    /// Operations not recorded in the macro being recorded
+    #[serde(skip)]
+    pub(crate) skipped: usize,
+    /// Saved macros
+    pub(crate) saved: Vec<Macro>,
+}
+
+impl Macros {
+    /// Record a step if recording
+    pub(crate) fn record(&mut self, step: MacroStep) {
+        if let Some(recording) = &mut self.recording {
+            recording.steps.push(step);
+        }
+    }
+
+    /// Count an operation that cannot be recorded
+    pub(crate) fn skip(&mut self) {
+        if self.recording.is_some() {
+            self.skipped += 1;
+        }
+    }
+}
+
+impl TarsierApp {
+    /// Macros ui
+    pub(crate) fn macros_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        let mut stop_recording = false;
+        match &mut self.macros.recording {
+            Some(recording) => {
+                ui.horizontal(|ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut recording.name);
+                });
+                ui.label(format!("Recording: {} step(s)", recording.steps.len()));
+                if self.macros.skipped > 0 {
+                    ui.colored_label(
+                        Color32::ORANGE,
+                        format!(
+                            "{} operation(s) on a non-rectangular selection not recorded",
+                            self.macros.skipped
+                        ),
+                    );
+                }
+                for step in &recording.steps {
+                    ui.label(format!("• {step}"));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("⏹ Stop").clicked() {
+                        stop_recording = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.macros.recording = None;
+                    }
+                });
+            }
+            None => {
+                if ui.button("⏺ Record macro").clicked() {
+                    self.macros.skipped = 0;
+                    self.macros.recording = Some(Macro {
+                        name: format!("macro {}", self.macros.saved.len() + 1),
+                        steps: Vec::new(),
+                    });
+                }
+            }
+        }
+        if stop_recording
+            && let Some(recording) = self.macros.recording.take()
+            && !recording.steps.is_empty()
+        {
+            self.macros.saved.push(recording);
+        }
+        ui.separator();
+        if self.macros.saved.is_empty() {
+            ui.label("No macro");
+        }
+        let mut to_remove = None;
+        for (idx, one_macro) in self.macros.saved.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(&one_macro.name).on_hover_text(
+                    one_macro
+                        .steps
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                );
+                if ui
+                    .button("▶")
+                    .on_hover_text("Replay on the current image")
+                    .clicked()
+                    && let Some(document) = self.documents.get_current_doc_mut()
+                {
+                    if let Err(e) = one_macro.replay(document, &self.settings.export) {
+                        error_manager.add_error(e);
+                    }
+                    document.texture = None;
+                }
+                if ui
+                    .button("⏩")
+                    .on_hover_text("Replay on every opened image")
+                    .clicked()
+                {
+                    for document in &mut self.documents {
+                        if let Err(e) = one_macro.replay(document, &self.settings.export) {
+                            error_manager
+                                .add_error(format!("{}: {e}", document.filename.display()));
+                        }
+                        document.texture = None;
+                    }
+                }
+                if ui.button("💾").on_hover_text("Export as json").clicked() {
+                    Self::export_macro(one_macro, error_manager);
+                }
+                if ui.button("🗑").on_hover_text("Delete").clicked() {
+                    to_remove = Some(idx);
+                }
+            });
+        }
+        if let Some(idx) = to_remove {
+            self.macros.saved.remove(idx);
+        }
+    }
+
+    /// Export a macro to a json file
+    fn export_macro(one_macro: &Macro, error_manager: &mut ErrorManager) {
+        let data = match one_macro.to_json() {
+            Ok(data) => data,
+            Err(e) => {
+                error_manager.add_error(e);
+                return;
+            }
+        };
+        let default_path = Path::new(&one_macro.name).with_extension("json");
+        match bladvak::utils::get_save_path(Some(&default_path)) {
+            Ok(Some(path)) => {
+                if let Err(e) = bladvak::utils::save_file(&data, &path) {
+                    error_manager.add_error(e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error_manager.add_error(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+
+    /// Macro with one step of each kind
+    fn sample_macro() -> Macro {
+        Macro {
+            name: "sample".to_string(),
+            steps: vec![
+                MacroStep::Operation {
+                    operation: Operation::Invert,
+                    region: Some(Region::new(0, 0, 2, 2)),
+                },
+                MacroStep::Operation {
+                    operation: Operation::Rotate90,
+                    region: None,
+                },
+                MacroStep::Crop(Region::new(1, 0, 2, 3)),
+                MacroStep::Save(ImageFormat::Png),
+            ],
+        }
+    }
+
+    /// Empty temporary directory for a test
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tarsier-macro-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        dir
+    }
+
+    /// 4x3 image where the red channel is `10 * x` and the green channel is `10 * y`
+    #[allow(clippy::cast_possible_truncation)]
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(4, 3, |x, y| {
+            Rgb([(10 * x) as u8, (10 * y) as u8, 0])
+        }))
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let one_macro = sample_macro();
+        let Ok(data) = one_macro.to_json() else {
+            panic!("a macro serializes");
+        };
+        let Ok(parsed) = Macro::from_json(&data) else {
+            panic!("an exported macro parses");
+        };
+        assert_eq!(parsed, one_macro);
+        assert!(Macro::from_json(b"{").is_err());
+    }
+
+    #[test]
+    fn replay_steps() {
+        let dir = temp_dir("replay");
+        let mut document = Document::new(dir.join("img.jpg"), gradient());
+        let options = ExportOptions::default();
+        assert!(sample_macro().replay(&mut document, &options).is_ok());
+        // inverted top left corner, turned to 3x4 then cropped to 2x3
+        assert_eq!(document.size(), (2, 3));
+        let img = document.img().to_rgb8();
+        // (1, 0) of the rotated image is (0, 1) of the original, inverted
+        assert_eq!(img.get_pixel(0, 0), &Rgb([255, 245, 255]));
+        // (2, 0) of the rotated image is (0, 0) of the original, inverted
+        assert_eq!(img.get_pixel(1, 0), &Rgb([255, 255, 255]));
+        // (2, 2) of the rotated image is (2, 0) of the original, not inverted
+        assert_eq!(img.get_pixel(1, 2), &Rgb([20, 0, 0]));
+        assert_eq!(document.filename, dir.join("img.png"));
+        let Ok(saved) = image::open(dir.join("img.png")) else {
+            panic!("the macro saves the image");
+        };
+        assert_eq!(saved.dimensions(), (2, 3));
+        assert_eq!(saved.to_rgb8(), img);
+    }
+
+    #[test]
+    fn replay_does_not_overwrite() {
+        let dir = temp_dir("overwrite");
+        let path = dir.join("img.png");
+        assert!(gradient().save(&path).is_ok());
+        let mut document = Document::new(path.clone(), gradient());
+        let one_macro = Macro {
+            name: "invert".to_string(),
+            steps: vec![
+                MacroStep::Operation {
+                    operation: Operation::Invert,
+                    region: None,
+                },
+                MacroStep::Save(ImageFormat::Png),
+            ],
+        };
+        assert!(
+            one_macro
+                .replay(&mut document, &ExportOptions::default())
+                .is_err()
+        );
+        let Ok(on_disk) = image::open(&path) else {
+            panic!("the original is still there");
+        };
+        assert_eq!(on_disk.to_rgb8(), gradient().to_rgb8());
+    }
+}
@@ -1,10 +1,817 @@
 //! Selection
 
-use bladvak::eframe::egui;
-use egui::{Image, ImageSource};
+use bladvak::eframe::egui::{
+    self, Color32, ColorImage, CursorIcon, Image, ImageSource, Key, Pos2, Rect, Shape, Stroke,
+    TextureHandle, TextureOptions, Vec2,
+};
+use image::{GrayImage, Luma};
+use std::time::Duration;
 
 use crate::TarsierApp;
-use crate::selection::egui::Color32;
+use crate::edit_mode::{SelectionShape, SelectionState};
+use crate::magic_wand::SelectionCombine;
+use crate::ops::Region;
+use crate::recording::MacroStep;
+
+/// Value of the selected pixels in a mask
+const SELECTED: Luma<u8> = Luma([255]);
+
+/// Check if a pixel of a mask is selected
+fn is_selected(pixel: Luma<u8>) -> bool {
+    pixel[0] >= 128
+}
+
+/// Convert a pixel coordinate to f32
+#[allow(clippy::cast_precision_loss)]
+fn to_f32(value: u32) -> f32 {
+    value as f32
+}
+
+/// Pixels of a row between two x positions: the pixels with a center in `[start, end)`
+#[allow(clippy::cast_possible_truncation)]
+#[allow(clippy::cast_sign_loss)]
+fn pixel_span(start: f32, end: f32, width: u32) -> std::ops::Range<u32> {
+    let to_pixel = |x: f32| ((x - 0.5).ceil().max(0.0) as u32).min(width);
+    to_pixel(start)..to_pixel(end)
+}
+
+/// Size of the handles on the screen, in points
+const HANDLE_SIZE: f32 = 8.0;
+
+/// Distance to grab a handle in canvas pixels, the handles keep their size on the screen
+pub(crate) fn handle_radius(ui: &egui::Ui) -> f32 {
+    let scaling = ui
+        .ctx()
+        .layer_transform_from_global(ui.layer_id())
+        .map_or(1.0, |transform| transform.scaling);
+    HANDLE_SIZE * scaling
+}
+
+/// Position of a handle along an axis of the selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edge {
+    /// Left or top edge
+    Min,
+    /// Between the edges
+    Center,
+    /// Right or bottom edge
+    Max,
+}
+
+impl Edge {
+    /// Edge of a coordinate, `None` outside of the selection
+    fn at(value: f32, min: f32, max: f32, radius: f32) -> Option<Self> {
+        let (to_min, to_max) = ((value - min).abs(), (value - max).abs());
+        if to_min <= radius && to_min <= to_max {
+            Some(Self::Min)
+        } else if to_max <= radius {
+            Some(Self::Max)
+        } else if (min..=max).contains(&value) {
+            Some(Self::Center)
+        } else {
+            None
+        }
+    }
+
+    /// Fraction of the size between the minimum and the edge
+    fn fraction(self) -> f32 {
+        match self {
+            Self::Min => 0.0,
+            Self::Center => 0.5,
+            Self::Max => 1.0,
+        }
+    }
+}
+
+/// Handle of a selection: an edge, a corner or the inside to move it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Handle {
+    /// Horizontal position
+    x: Edge,
+    /// Vertical position
+    y: Edge,
+}
+
+impl Handle {
+    /// The corners and the middle of the edges
+    const ALL: [Self; 8] = [
+        Self::new(Edge::Min, Edge::Min),
+        Self::new(Edge::Center, Edge::Min),
+        Self::new(Edge::Max, Edge::Min),
+        Self::new(Edge::Max, Edge::Center),
+        Self::new(Edge::Max, Edge::Max),
+        Self::new(Edge::Center, Edge::Max),
+        Self::new(Edge::Min, Edge::Max),
+        Self::new(Edge::Min, Edge::Center),
+    ];
+
+    /// New handle
+    const fn new(x: Edge, y: Edge) -> Self {
+        Self { x, y }
+    }
+
+    /// Handle of a rectangle at a position, `None` outside
+    fn at(rect: Rect, pos: Pos2, radius: f32) -> Option<Self> {
+        Some(Self {
+            x: Edge::at(pos.x, rect.min.x, rect.max.x, radius)?,
+            y: Edge::at(pos.y, rect.min.y, rect.max.y, radius)?,
+        })
+    }
+
+    /// Check if the handle moves the whole selection
+    fn is_move(self) -> bool {
+        self.x == Edge::Center && self.y == Edge::Center
+    }
+
+    /// Position of the handle on a rectangle
+    fn pos(self, rect: Rect) -> Pos2 {
+        rect.min + rect.size() * Vec2::new(self.x.fraction(), self.y.fraction())
+    }
+
+    /// Cursor when hovering the handle
+    fn cursor(self) -> CursorIcon {
+        match (self.x, self.y) {
+            (Edge::Center, Edge::Center) => CursorIcon::Move,
+            (Edge::Center, _) => CursorIcon::ResizeVertical,
+            (_, Edge::Center) => CursorIcon::ResizeHorizontal,
+            (Edge::Min, Edge::Min) | (Edge::Max, Edge::Max) => CursorIcon::ResizeNwSe,
+            _ => CursorIcon::ResizeNeSw,
+        }
+    }
+}
+
+/// Selection grabbed by a handle
+#[derive(Debug, Clone)]
+pub(crate) struct Grab {
+    /// Grabbed handle
+    handle: Handle,
+    /// Position where the drag started
+    start: Pos2,
+    /// Selection rectangle when the drag started
+    rect: Rect,
+    /// Selection mask when the drag started
+    mask: Option<SelectionMask>,
+}
+
+impl Grab {
+    /// Rectangle with the handle dragged to `pos`, the aspect ratio is kept with `ratio` or `shift`
+    fn target(&self, pos: Pos2, ratio: Option<f32>, shift: bool, canvas: Rect) -> Rect {
+        let delta = (pos - self.start).round();
+        if self.handle.is_move() {
+            return translate_inside(self.rect, delta, canvas);
+        }
+        let ratio = ratio.or_else(|| {
+            (shift && self.rect.height() > 0.0).then(|| self.rect.width() / self.rect.height())
+        });
+        // the opposite edge stays in place, the rectangle is centered on the other axis
+        let axis = |edge: Edge, min: f32, max: f32, delta: f32| match edge {
+            Edge::Min => (max, min + delta),
+            Edge::Max => (min, max + delta),
+            Edge::Center => (f32::midpoint(min, max), f32::NAN),
+        };
+        let (anchor_x, moved_x) = axis(self.handle.x, self.rect.min.x, self.rect.max.x, delta.x);
+        let (anchor_y, moved_y) = axis(self.handle.y, self.rect.min.y, self.rect.max.y, delta.y);
+        let align = |anchor: f32, moved: f32| {
+            if moved.is_nan() {
+                0.5
+            } else if moved < anchor {
+                1.0
+            } else {
+                0.0
+            }
+        };
+        // a centered axis keeps its size, or follows the other one with a ratio
+        let size = |anchor: f32, moved: f32, current: f32| {
+            if !moved.is_nan() {
+                (moved - anchor).abs()
+            } else if ratio.is_some() {
+                0.0
+            } else {
+                current
+            }
+        };
+        fit_rect(
+            Pos2::new(anchor_x, anchor_y),
+            Vec2::new(align(anchor_x, moved_x), align(anchor_y, moved_y)),
+            Vec2::new(
+                size(anchor_x, moved_x, self.rect.width()),
+                size(anchor_y, moved_y, self.rect.height()),
+            ),
+            ratio,
+            canvas,
+        )
+    }
+}
+
+/// Move a rectangle, staying inside `bounds`
+fn translate_inside(rect: Rect, delta: Vec2, bounds: Rect) -> Rect {
+    let clamp_delta = |delta: f32, min: f32, max: f32, bounds_min: f32, bounds_max: f32| {
+        delta.min(bounds_max - max).max(bounds_min - min)
+    };
+    rect.translate(Vec2::new(
+        clamp_delta(delta.x, rect.min.x, rect.max.x, bounds.min.x, bounds.max.x),
+        clamp_delta(delta.y, rect.min.y, rect.max.y, bounds.min.y, bounds.max.y),
+    ))
+}
+
+/// Rectangle of `size` placed on `anchor` by `align` (0: after the anchor, 1: before, 0.5: centered)
+///
+/// With a ratio, the larger side wins and the rectangle shrinks to stay inside `bounds`
+fn fit_rect(anchor: Pos2, align: Vec2, size: Vec2, ratio: Option<f32>, bounds: Rect) -> Rect {
+    let max_size = |anchor: f32, align: f32, min: f32, max: f32| {
+        let (before, after) = (anchor - min, max - anchor);
+        if align <= 0.0 {
+            after
+        } else if align >= 1.0 {
+            before
+        } else {
+            2.0 * before.min(after)
+        }
+    };
+    let max = Vec2::new(
+        max_size(anchor.x, align.x, bounds.min.x, bounds.max.x),
+        max_size(anchor.y, align.y, bounds.min.y, bounds.max.y),
+    )
+    .max(Vec2::ZERO);
+    let mut size = match ratio {
+        Some(ratio) => {
+            let width = size.x.max(size.y * ratio);
+            Vec2::new(width, width / ratio)
+        }
+        None => size,
+    };
+    if let Some(ratio) = ratio {
+        if size.x > max.x {
+            size = Vec2::new(max.x, max.x / ratio);
+        }
+        if size.y > max.y {
+            size = Vec2::new(max.y * ratio, max.y);
+        }
+    } else {
+        size = size.min(max);
+    }
+    let min = anchor - align * size;
+    Rect::from_min_max(min.round(), (min + size).round())
+}
+
+/// Mask of a selection, as large as the canvas
+#[derive(Clone)]
+pub(crate) struct SelectionMask {
+    /// 255 for the selected pixels, 0 for the others
+    pub(crate) img: GrayImage,
+    /// Bounding box of the selected pixels
+    pub(crate) bounds: Region,
+    /// Outline of the selected pixels, as horizontal and vertical segments
+    outline: Vec<[Pos2; 2]>,
+    /// Texture covering the unselected pixels, with its color
+    overlay: Option<(Color32, TextureHandle)>,
+}
+
+impl std::fmt::Debug for SelectionMask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SelectionMask")
+            .field("bounds", &self.bounds)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SelectionMask {
+    /// Mask from an image, `None` if no pixel is selected
+    pub(crate) fn new(img: GrayImage) -> Option<Self> {
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if is_selected(*pixel) {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+        if min_x > max_x {
+            return None;
+        }
+        let bounds = Region::new(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1);
+        let outline = outline(&img, bounds);
+        Some(Self {
+            img,
+            bounds,
+            outline,
+            overlay: None,
+        })
+    }
+
+    /// Ellipse inscribed in a rectangle of the canvas
+    pub(crate) fn ellipse(width: u32, height: u32, rect: Rect) -> Option<Self> {
+        let mut img = GrayImage::new(width, height);
+        let center = rect.center();
+        let radius = rect.size() / 2.0;
+        if radius.x <= 0.0 || radius.y <= 0.0 {
+            return None;
+        }
+        for y in pixel_span(rect.min.y, rect.max.y, height) {
+            // half width of the ellipse at the center of the row
+            let dy = (to_f32(y) + 0.5 - center.y) / radius.y;
+            let half_width = radius.x * (1.0 - dy * dy).max(0.0).sqrt();
+            for x in pixel_span(center.x - half_width, center.x + half_width, width) {
+                img.put_pixel(x, y, SELECTED);
+            }
+        }
+        Self::new(img)
+    }
+
+    /// Polygon of the canvas, with the even-odd rule
+    pub(crate) fn polygon(width: u32, height: u32, points: &[Pos2]) -> Option<Self> {
+        if points.len() < 3 {
+            return None;
+        }
+        let mut img = GrayImage::new(width, height);
+        let (min_y, max_y) = points.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+            (min.min(p.y), max.max(p.y))
+        });
+        let mut crossings = Vec::new();
+        for y in pixel_span(min_y, max_y, height) {
+            let center_y = to_f32(y) + 0.5;
+            crossings.clear();
+            for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+                if (a.y <= center_y) != (b.y <= center_y) {
+                    crossings.push(a.x + (center_y - a.y) * (b.x - a.x) / (b.y - a.y));
+                }
+            }
+            crossings.sort_by(f32::total_cmp);
+            for pair in crossings.chunks_exact(2) {
+                for x in pixel_span(pair[0], pair[1], width) {
+                    img.put_pixel(x, y, SELECTED);
+                }
+            }
+        }
+        Self::new(img)
+    }
+
+    /// Check if a pixel is selected
+    pub(crate) fn contains(&self, x: u32, y: u32) -> bool {
+        self.img
+            .get_pixel_checked(x, y)
+            .is_some_and(|pixel| is_selected(*pixel))
+    }
+
+    /// Part of the mask in a region of the canvas
+    pub(crate) fn crop(&self, region: Region) -> GrayImage {
+        image::imageops::crop_imm(&self.img, region.x, region.y, region.width, region.height)
+            .to_image()
+    }
+
+    /// Mask with its bounding box moved and scaled from `from` to `to`
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn transformed(&self, from: Rect, to: Rect) -> Option<Self> {
+        let (width, height) = self.img.dimensions();
+        let mut img = GrayImage::new(width, height);
+        let scale = from.size() / to.size();
+        for y in pixel_span(to.min.y, to.max.y, height) {
+            let source_y = from.min.y + (to_f32(y) + 0.5 - to.min.y) * scale.y;
+            for x in pixel_span(to.min.x, to.max.x, width) {
+                let source_x = from.min.x + (to_f32(x) + 0.5 - to.min.x) * scale.x;
+                if source_x >= 0.0
+                    && source_y >= 0.0
+                    && self.contains(source_x as u32, source_y as u32)
+                {
+                    img.put_pixel(x, y, SELECTED);
+                }
+            }
+        }
+        Self::new(img)
+    }
+
+    /// Bounding box as a rectangle of the canvas
+    pub(crate) fn rect(&self) -> Rect {
+        Rect::from_min_size(
+            Pos2::new(to_f32(self.bounds.x), to_f32(self.bounds.y)),
+            Vec2::new(to_f32(self.bounds.width), to_f32(self.bounds.height)),
+        )
+    }
+
+    /// Texture covering the unselected pixels with `color`
+    fn overlay(&mut self, ctx: &egui::Context, color: Color32) -> egui::TextureId {
+        if let Some((overlay_color, texture)) = &self.overlay
+            && *overlay_color == color
+        {
+            return texture.id();
+        }
+        let pixels = self
+            .img
+            .pixels()
+            .map(|pixel| {
+                if is_selected(*pixel) {
+                    Color32::TRANSPARENT
+                } else {
+                    color
+                }
+            })
+            .collect();
+        let (width, height) = self.img.dimensions();
+        let image = ColorImage::new([width as usize, height as usize], pixels);
+        let texture = ctx.load_texture("selection", image, TextureOptions::NEAREST);
+        let id = texture.id();
+        self.overlay = Some((color, texture));
+        id
+    }
+}
+
+/// Outline of the selected pixels of a mask, as segments along the pixel edges
+fn outline(img: &GrayImage, bounds: Region) -> Vec<[Pos2; 2]> {
+    let inside = |x: i64, y: i64| {
+        u32::try_from(x)
+            .ok()
+            .zip(u32::try_from(y).ok())
+            .is_some_and(|(x, y)| {
+                img.get_pixel_checked(x, y)
+                    .is_some_and(|pixel| is_selected(*pixel))
+            })
+    };
+    let (left, top) = (i64::from(bounds.x), i64::from(bounds.y));
+    let (right, bottom) = (
+        left + i64::from(bounds.width),
+        top + i64::from(bounds.height),
+    );
+    #[allow(clippy::cast_precision_loss)]
+    let pos = |x: i64, y: i64| Pos2::new(x as f32, y as f32);
+    let mut segments = Vec::new();
+    // edges between a pixel and the one above, merged in runs
+    for y in top..=bottom {
+        let mut start = None;
+        for x in left..=right {
+            let is_edge = x < right && inside(x, y - 1) != inside(x, y);
+            match (is_edge, start) {
+                (true, None) => start = Some(x),
+                (false, Some(start_x)) => {
+                    segments.push([pos(start_x, y), pos(x, y)]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    // edges between a pixel and the one on its left
+    for x in left..=right {
+        let mut start = None;
+        for y in top..=bottom {
+            let is_edge = y < bottom && inside(x - 1, y) != inside(x, y);
+            match (is_edge, start) {
+                (true, None) => start = Some(y),
+                (false, Some(start_y)) => {
+                    segments.push([pos(x, start_y), pos(x, y)]);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    segments
+}
+
+/// Paint animated dashed lines along segments
+fn marching_ants(ui: &egui::Ui, segments: impl Iterator<Item = [Pos2; 2]>) {
+    /// Length of a dash and of a gap
+    const DASH: f32 = 4.0;
+    #[allow(clippy::cast_possible_truncation)]
+    let time_offset = (ui.input(|i| i.time) * 10.0).rem_euclid(f64::from(2.0 * DASH)) as f32;
+    let mut shapes = Vec::new();
+    for [a, b] in segments {
+        shapes.push(Shape::line_segment(
+            [a, b],
+            Stroke::new(1.0, Color32::WHITE),
+        ));
+        // offset by the position, so that the dashes continue from one segment to the next
+        Shape::dashed_line_many_with_offset(
+            &[a, b],
+            Stroke::new(1.0, Color32::BLACK),
+            &[DASH],
+            &[DASH],
+            (time_offset + a.x + a.y).rem_euclid(2.0 * DASH),
+            &mut shapes,
+        );
+    }
+    ui.painter().extend(shapes);
+    ui.ctx().request_repaint_after(Duration::from_millis(100));
+}
+
+/// Cover the canvas outside of a rectangle
+fn paint_outside_rect(ui: &egui::Ui, image_rect: Rect, selection: Rect, color: Color32) {
+    let painter = ui.painter();
+    let bands = [
+        // above selection
+        Rect::from_min_max(image_rect.min, Pos2::new(image_rect.max.x, selection.min.y)),
+        // below selection
+        Rect::from_min_max(Pos2::new(image_rect.min.x, selection.max.y), image_rect.max),
+        // left of selection
+        Rect::from_min_max(
+            Pos2::new(image_rect.min.x, selection.min.y),
+            Pos2::new(selection.min.x, selection.max.y),
+        ),
+        // right of selection
+        Rect::from_min_max(
+            Pos2::new(selection.max.x, selection.min.y),
+            Pos2::new(image_rect.max.x, selection.max.y),
+        ),
+    ];
+    for band in bands {
+        painter.rect_filled(band, 0.0, color);
+    }
+}
+
+/// Paint the handles to resize a rectangle
+fn paint_handles(ui: &egui::Ui, rect: Rect) {
+    let size = Vec2::splat(handle_radius(ui));
+    for handle in Handle::ALL {
+        ui.painter().rect(
+            Rect::from_center_size(handle.pos(rect), size),
+            0.0,
+            Color32::WHITE,
+            Stroke::new(size.x / HANDLE_SIZE, Color32::BLACK),
+            egui::StrokeKind::Middle,
+        );
+    }
+}
+
+impl SelectionState {
+    /// Remove the selection
+    pub(crate) fn clear(&mut self) {
+        self.rectangle = None;
+        self.mask = None;
+        self.path.clear();
+    }
+
+    /// Select the pixels of a mask, or nothing
+    pub(crate) fn set_mask(&mut self, mask: Option<SelectionMask>) {
+        self.rectangle = mask.as_ref().map(SelectionMask::rect);
+        self.mask = mask;
+    }
+
+    /// Move or resize the selection, the mask follows its bounding box
+    pub(crate) fn set_rectangle(&mut self, rect: Rect) {
+        match (&self.mask, self.rectangle) {
+            (Some(mask), Some(previous)) if previous != rect => {
+                let mask = mask.transformed(previous, rect);
+                self.set_mask(mask);
+            }
+            _ => self.rectangle = Some(rect),
+        }
+    }
+
+    /// Combine a mask as large as the canvas with the current selection
+    pub(crate) fn combine(&mut self, new: GrayImage, combine: SelectionCombine) {
+        let current = match (&self.mask, self.rectangle) {
+            (Some(mask), _) => Some(mask.img.clone()),
+            (None, Some(rect)) => {
+                let (width, height) = new.dimensions();
+                let mut img = GrayImage::new(width, height);
+                for y in pixel_span(rect.min.y, rect.max.y, height) {
+                    for x in pixel_span(rect.min.x, rect.max.x, width) {
+                        img.put_pixel(x, y, SELECTED);
+                    }
+                }
+                Some(img)
+            }
+            (None, None) => None,
+        };
+        let result = match current {
+            Some(mut current) if current.dimensions() == new.dimensions() => {
+                combine.apply(&mut current, &new);
+                current
+            }
+            // nothing to combine with: subtracting or intersecting selects nothing
+            _ if matches!(
+                combine,
+                SelectionCombine::Subtract | SelectionCombine::Intersect
+            ) =>
+            {
+                self.clear();
+                return;
+            }
+            _ => new,
+        };
+        self.path.clear();
+        self.set_mask(SelectionMask::new(result));
+    }
+
+    /// Check if a pixel of the canvas is selected, everything is selected without selection
+    pub(crate) fn contains(&self, x: u32, y: u32) -> bool {
+        match (&self.mask, self.rectangle) {
+            (Some(mask), _) => mask.contains(x, y),
+            (None, Some(rect)) => rect.contains(Pos2::new(to_f32(x), to_f32(y))),
+            (None, None) => true,
+        }
+    }
+
+    /// Handle of the selection at a position of the canvas
+    fn handle_at(&self, pos: Pos2, radius: f32) -> Option<Handle> {
+        if !self.path.is_empty() {
+            return None;
+        }
+        Handle::at(self.rectangle?, pos, radius)
+    }
+
+    /// Cursor to show when hovering a position of the canvas
+    pub(crate) fn cursor_at(&self, pos: Pos2, radius: f32) -> Option<CursorIcon> {
+        self.handle_at(pos, radius).map(Handle::cursor)
+    }
+
+    /// Start of a drag on the canvas: grab a handle of the selection, or start a new one
+    pub(crate) fn drag_started(&mut self, shape: SelectionShape, start: Pos2, radius: f32) {
+        self.grab = self.handle_at(start, radius).and_then(|handle| {
+            Some(Grab {
+                handle,
+                start,
+                rect: self.rectangle?,
+                mask: self.mask.clone(),
+            })
+        });
+        if self.grab.is_some() {
+            return;
+        }
+        match shape {
+            SelectionShape::Rectangle | SelectionShape::Ellipse | SelectionShape::Lasso => {
+                self.clear();
+                self.start_selection = start;
+            }
+            SelectionShape::Polygon => {}
+        }
+    }
+
+    /// Drag on the canvas, at a position of the canvas
+    ///
+    /// The new rectangles and the resized ones keep `ratio`, shift keeps the ratio too
+    pub(crate) fn drag(
+        &mut self,
+        shape: SelectionShape,
+        pos: Pos2,
+        ratio: Option<f32>,
+        shift: bool,
+        (width, height): (u32, u32),
+    ) {
+        let canvas = Rect::from_min_size(Pos2::ZERO, Vec2::new(to_f32(width), to_f32(height)));
+        if let Some(grab) = &self.grab {
+            let rect = grab.target(pos, ratio, shift, canvas);
+            match &grab.mask {
+                Some(mask) => {
+                    let mask = mask.transformed(grab.rect, rect);
+                    self.set_mask(mask);
+                }
+                None => self.rectangle = Some(rect),
+            }
+            return;
+        }
+        match shape {
+            SelectionShape::Rectangle | SelectionShape::Ellipse => {
+                let start = self.start_selection;
+                let ratio = ratio.or(shift.then_some(1.0));
+                let align = Vec2::new(
+                    if pos.x < start.x { 1.0 } else { 0.0 },
+                    if pos.y < start.y { 1.0 } else { 0.0 },
+                );
+                let size = (pos - start).abs();
+                self.rectangle = Some(fit_rect(start, align, size, ratio, canvas));
+            }
+            SelectionShape::Lasso => {
+                if self.path.last() != Some(&pos) {
+                    self.path.push(pos);
+                }
+            }
+            SelectionShape::Polygon => {}
+        }
+    }
+
+    /// End of a drag on the canvas
+    pub(crate) fn drag_stopped(&mut self, shape: SelectionShape, (width, height): (u32, u32)) {
+        if self.grab.take().is_some() {
+            return;
+        }
+        match shape {
+            SelectionShape::Ellipse => {
+                if let Some(rect) = self.rectangle
+                    && self.mask.is_none()
+                {
+                    self.set_mask(SelectionMask::ellipse(width, height, rect));
+                }
+            }
+            SelectionShape::Lasso => {
+                let path = std::mem::take(&mut self.path);
+                self.set_mask(SelectionMask::polygon(width, height, &path));
+            }
+            SelectionShape::Rectangle | SelectionShape::Polygon => {}
+        }
+    }
+
+    /// Move the selection by a number of pixels, staying inside the canvas
+    pub(crate) fn nudge(&mut self, delta: Vec2, (width, height): (u32, u32)) {
+        if let Some(rect) = self.rectangle {
+            let canvas = Rect::from_min_size(Pos2::ZERO, Vec2::new(to_f32(width), to_f32(height)));
+            self.set_rectangle(translate_inside(rect, delta, canvas));
+        }
+    }
+
+    /// Click on the canvas, at a position of the canvas
+    pub(crate) fn click(
+        &mut self,
+        shape: SelectionShape,
+        pos: Pos2,
+        double_click: bool,
+        (width, height): (u32, u32),
+    ) {
+        if shape != SelectionShape::Polygon {
+            self.clear();
+            return;
+        }
+        if self.path.is_empty() {
+            self.clear();
+        }
+        let closes = self
+            .path
+            .first()
+            .is_some_and(|first| first.distance(pos) < 3.0);
+        if self.path.len() >= 3 && (double_click || closes) {
+            let path = std::mem::take(&mut self.path);
+            self.set_mask(SelectionMask::polygon(width, height, &path));
+        } else if !double_click {
+            self.path.push(pos);
+        }
+    }
+
+    /// Paint the selection over the image, at `image_rect` on the screen
+    pub(crate) fn paint(
+        &mut self,
+        ui: &egui::Ui,
+        shape: SelectionShape,
+        image_rect: Rect,
+        dragged: bool,
+        handles: bool,
+        color: Color32,
+    ) {
+        #[allow(clippy::cast_possible_truncation)]
+        #[allow(clippy::cast_sign_loss)]
+        let canvas_size = (image_rect.width() as u32, image_rect.height() as u32);
+        if self
+            .mask
+            .as_ref()
+            .is_some_and(|mask| mask.img.dimensions() != canvas_size)
+        {
+            // the canvas was cropped or resized
+            self.clear();
+        }
+        if ui.input(|i| i.key_pressed(Key::Escape)) {
+            self.path.clear();
+        }
+        let to_screen = |pos: Pos2| pos + image_rect.min.to_vec2();
+        if !self.path.is_empty() {
+            let mut points: Vec<Pos2> = self.path.iter().copied().map(to_screen).collect();
+            if shape == SelectionShape::Polygon
+                && let Some(hover) = ui.ctx().pointer_hover_pos()
+            {
+                points.push(hover);
+            }
+            marching_ants(ui, points.windows(2).map(|pair| [pair[0], pair[1]]));
+        }
+        let Some(selection) = self.rectangle else {
+            return;
+        };
+        let screen_rect = Rect::from_min_max(to_screen(selection.min), to_screen(selection.max));
+        if let Some(mask) = &mut self.mask {
+            let texture = mask.overlay(ui.ctx(), color);
+            ui.painter().image(
+                texture,
+                image_rect,
+                Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                Color32::WHITE,
+            );
+            marching_ants(
+                ui,
+                mask.outline
+                    .iter()
+                    .map(|[a, b]| [to_screen(*a), to_screen(*b)]),
+            );
+        } else {
+            paint_outside_rect(ui, image_rect, screen_rect, color);
+            if shape == SelectionShape::Ellipse && dragged && self.grab.is_none() {
+                ui.painter().add(Shape::ellipse_stroke(
+                    screen_rect.center(),
+                    screen_rect.size() / 2.0,
+                    Stroke::new(1.0, Color32::BLACK),
+                ));
+            }
+            let corners = [
+                screen_rect.left_top(),
+                screen_rect.right_top(),
+                screen_rect.right_bottom(),
+                screen_rect.left_bottom(),
+                screen_rect.left_top(),
+            ];
+            marching_ants(ui, corners.windows(2).map(|pair| [pair[0], pair[1]]));
+        }
+        if handles && self.path.is_empty() {
+            paint_handles(ui, screen_rect);
+        }
+    }
+}
 
 impl TarsierApp {
     /// Crop icon
@@ -15,9 +822,19 @@ impl TarsierApp {
         let Some(document) = self.documents.get_current_doc_mut() else {
             return;
         };
-        let mut reset_rect = false;
-        match &mut document.selection.rectangle {
-            Some(rect) => {
+        ui.horizontal(|ui| {
+            for shape in SelectionShape::ALL {
+                ui.selectable_value(&mut self.mode.selection_shape, shape, shape.to_string());
+            }
+        });
+        if self.mode.selection_shape == SelectionShape::Polygon {
+            ui.label("Click to add points, double click or click the first point to close");
+        }
+        self.mode.selection_ratio.show(ui);
+        let ratio = self.mode.selection_ratio.value();
+        let (img_width, img_height) = document.size();
+        match document.selection.rectangle {
+            Some(mut rect) => {
                 if ui
                     .label(format!(
                         "Selection: {:.0}x{:.0}",
@@ -27,75 +844,225 @@ impl TarsierApp {
                     .on_hover_text("Click to clear selection")
                     .clicked()
                 {
-                    reset_rect = true;
+                    document.selection.clear();
+                    return;
                 }
                 let right = rect.right();
                 let bottom = rect.bottom();
-                #[allow(clippy::cast_precision_loss)]
                 ui.horizontal(|ui| {
                     ui.label("Min: ");
                     ui.add(egui::DragValue::new(rect.left_mut()).range(0.0..=right));
                     ui.add(egui::DragValue::new(rect.top_mut()).range(0.0..=bottom));
                 });
-                #[allow(clippy::cast_precision_loss)]
                 ui.horizontal(|ui| {
                     ui.label("Size");
                     let mut width = rect.width().abs();
-                    let left = rect.left();
-                    let max_width = (document.img.width() as f32) - left;
+                    let max_width = to_f32(img_width) - rect.left();
                     if ui
                         .add(egui::DragValue::new(&mut width).range(0.0..=max_width))
                         .changed()
                     {
                         rect.set_width(width);
+                        if let Some(ratio) = ratio {
+                            rect.set_height((width / ratio).round());
+                        }
                     }
                     ui.label("x");
                     let mut height = rect.height().abs();
-                    let top = rect.top();
-                    let max_height = (document.img.height() as f32) - top;
+                    let max_height = to_f32(img_height) - rect.top();
                     if ui
                         .add(egui::DragValue::new(&mut height).range(0.0..=max_height))
                         .changed()
                     {
                         rect.set_height(height);
+                        if let Some(ratio) = ratio {
+                            rect.set_width((height * ratio).round());
+                        }
                     }
                 });
+                let canvas = Rect::from_min_size(
+                    Pos2::ZERO,
+                    Vec2::new(to_f32(img_width), to_f32(img_height)),
+                );
+                document.selection.set_rectangle(rect.intersect(canvas));
             }
             None => {
                 ui.label("No selection");
             }
         }
-        if reset_rect {
-            document.selection.rectangle = None;
-        }
-        if let Some(selection) = document.selection.rectangle {
+        if let Some(region) = document.selection_region() {
             let icon_image = Image::new(Self::CROP_ICON);
             let icon = if ui.ctx().global_style().visuals.dark_mode {
                 icon_image
             } else {
                 icon_image.tint(Color32::BLACK)
             };
-            #[allow(clippy::cast_sign_loss)]
-            #[allow(clippy::cast_possible_truncation)]
             if ui
                 .add(egui::Button::image_and_text(icon, "Crop"))
-                .on_hover_text("Crop the image")
+                .on_hover_text(
+                    "Crop the image to the selection bounds, the pixels outside of the shape are kept",
+                )
                 .clicked()
             {
-                let min_pos = selection.min;
-                let max_pos = selection.max;
-                let min_x = min_pos.x as u32;
-                let min_y = min_pos.y as u32;
-                let max_x = max_pos.x as u32;
-                let max_y = max_pos.y as u32;
-                let cropped_img = document
-                    .img
-                    .crop_imm(min_x, min_y, max_x - min_x, max_y - min_y);
-                self.update_image(cropped_img);
+                document.crop(region);
+                self.macros.record(MacroStep::Crop(region));
+                self.updated_image();
                 if let Some(document) = self.documents.get_current_doc_mut() {
-                    document.selection.rectangle = None;
+                    document.selection.clear();
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    /// Rows of '#' (selected) and '.' (not selected) of a mask
+    fn rows(mask: &SelectionMask) -> Vec<String> {
+        mask.img
+            .rows()
+            .map(|row| {
+                row.map(|pixel| if is_selected(*pixel) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Rectangle of the canvas
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect::from_min_size(Pos2::new(x, y), Vec2::new(width, height))
+    }
+
+    #[test]
+    fn ellipse_mask() {
+        let Some(mask) = SelectionMask::ellipse(8, 7, rect(1.0, 1.0, 6.0, 5.0)) else {
+            panic!("the ellipse is not empty");
+        };
+        assert_eq!(
+            rows(&mask),
+            [
+                "........", "..####..", ".######.", ".######.", ".######.", "..####..", "........",
+            ]
+        );
+        assert_eq!(mask.bounds, Region::new(1, 1, 6, 5));
+        assert_eq!(mask.rect(), rect(1.0, 1.0, 6.0, 5.0));
+        // clipped to the canvas
+        let Some(mask) = SelectionMask::ellipse(4, 4, rect(-4.0, -4.0, 8.0, 8.0)) else {
+            panic!("the ellipse is on the canvas");
+        };
+        assert_eq!(mask.bounds, Region::new(0, 0, 4, 4));
+        assert!(SelectionMask::ellipse(8, 8, rect(2.0, 2.0, 0.0, 4.0)).is_none());
+        assert!(SelectionMask::ellipse(8, 8, rect(10.0, 10.0, 4.0, 4.0)).is_none());
+    }
+
+    #[test]
+    fn polygon_mask() {
+        let triangle = [
+            Pos2::new(0.0, 0.0),
+            Pos2::new(4.0, 0.0),
+            Pos2::new(0.0, 4.0),
+        ];
+        let Some(mask) = SelectionMask::polygon(5, 5, &triangle) else {
+            panic!("the triangle is not empty");
+        };
+        // the pixels are selected when their center is inside
+        assert_eq!(rows(&mask), ["###..", "##...", "#....", ".....", "....."]);
+        assert_eq!(mask.bounds, Region::new(0, 0, 3, 3));
+        // even-odd rule: the overlap of the two loops is not selected
+        let loops = [
+            Pos2::new(0.0, 0.0),
+            Pos2::new(4.0, 0.0),
+            Pos2::new(4.0, 4.0),
+            Pos2::new(2.0, 4.0),
+            Pos2::new(2.0, 2.0),
+            Pos2::new(6.0, 2.0),
+            Pos2::new(6.0, 6.0),
+            Pos2::new(0.0, 6.0),
+        ];
+        let Some(mask) = SelectionMask::polygon(6, 6, &loops) else {
+            panic!("the loops are not empty");
+        };
+        assert_eq!(
+            rows(&mask),
+            ["####..", "####..", "##..##", "##..##", "######", "######"]
+        );
+        assert!(SelectionMask::polygon(5, 5, &triangle[..2]).is_none());
+    }
+
+    #[test]
+    fn mask_outline() {
+        let mut img = GrayImage::new(4, 3);
+        img.put_pixel(1, 1, SELECTED);
+        img.put_pixel(2, 1, SELECTED);
+        let Some(mask) = SelectionMask::new(img) else {
+            panic!("two pixels are selected");
+        };
+        assert_eq!(mask.bounds, Region::new(1, 1, 2, 1));
+        let segment = |x0: f32, y0: f32, x1: f32, y1: f32| [Pos2::new(x0, y0), Pos2::new(x1, y1)];
+        // the horizontal edges, then the vertical ones
+        assert_eq!(
+            mask.outline,
+            [
+                segment(1.0, 1.0, 3.0, 1.0),
+                segment(1.0, 2.0, 3.0, 2.0),
+                segment(1.0, 1.0, 1.0, 2.0),
+                segment(3.0, 1.0, 3.0, 2.0),
+            ]
+        );
+        assert!(SelectionMask::new(GrayImage::new(4, 3)).is_none());
+    }
+
+    #[test]
+    fn transformed_mask() {
+        let triangle = [
+            Pos2::new(0.0, 0.0),
+            Pos2::new(4.0, 0.0),
+            Pos2::new(0.0, 4.0),
+        ];
+        let Some(mask) = SelectionMask::polygon(6, 6, &triangle) else {
+            panic!("the triangle is not empty");
+        };
+        let Some(moved) = mask.transformed(mask.rect(), rect(3.0, 1.0, 3.0, 3.0)) else {
+            panic!("the moved triangle is on the canvas");
+        };
+        assert_eq!(moved.bounds, Region::new(3, 1, 3, 3));
+        assert!(moved.contains(3, 1) && moved.contains(5, 1) && moved.contains(3, 3));
+        assert!(!moved.contains(5, 3) && !moved.contains(0, 0));
+        let Some(scaled) = mask.transformed(mask.rect(), rect(0.0, 0.0, 6.0, 6.0)) else {
+            panic!("the scaled triangle is on the canvas");
+        };
+        assert_eq!(scaled.bounds, Region::new(0, 0, 6, 6));
+        assert!(scaled.contains(5, 0) && scaled.contains(0, 5) && scaled.contains(3, 3));
+        assert!(!scaled.contains(5, 5) && !scaled.contains(4, 3));
+        assert!(
+            mask.transformed(mask.rect(), rect(8.0, 8.0, 2.0, 2.0))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn crop_keeps_the_pixels_outside_of_the_mask() {
+        let img = RgbImage::from_fn(8, 8, |x, y| {
+            #[allow(clippy::cast_possible_truncation)]
+            Rgb([(x * 10) as u8, (y * 10) as u8, 0])
+        });
+        let mut document = Document::new("a.png".into(), DynamicImage::ImageRgb8(img));
+        document
+            .selection
+            .set_mask(SelectionMask::ellipse(8, 8, rect(2.0, 1.0, 4.0, 6.0)));
+        let Some(region) = document.selection_region() else {
+            panic!("there is a selection");
+        };
+        assert_eq!(region, Region::new(2, 1, 4, 6));
+        document.crop(region);
+        let cropped = document.img().to_rgb8();
+        assert_eq!(cropped.dimensions(), (4, 6));
+        // the corner is outside of the ellipse but is kept
+        assert_eq!(cropped.get_pixel(0, 0), &Rgb([20, 10, 0]));
+        assert_eq!(cropped.get_pixel(3, 5), &Rgb([50, 60, 0]));
+    }
+}
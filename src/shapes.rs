@@ -0,0 +1,396 @@
+//! Shape tools: lines, rectangles, ellipses, polygons and arrows rasterized into the active layer
+
+use bladvak::eframe::egui::{self, Color32, Pos2, Rect, Stroke, Vec2};
+use bladvak::errors::AppError;
+use image::{DynamicImage, Rgba32FImage};
+
+use crate::document::Document;
+use crate::ops::{self, Region};
+
+/// Kind of shape
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum ShapeKind {
+    /// Straight line, dragged
+    #[default]
+    Line,
+    /// Rectangle, dragged from a corner to the other
+    Rectangle,
+    /// Ellipse inside the dragged rectangle
+    Ellipse,
+    /// Polygon, one click per vertex
+    Polygon,
+    /// Line with a head at its end, dragged
+    Arrow,
+}
+
+impl ShapeKind {
+    /// All the kinds
+    const ALL: [Self; 5] = [
+        Self::Line,
+        Self::Rectangle,
+        Self::Ellipse,
+        Self::Polygon,
+        Self::Arrow,
+    ];
+
+    /// Check if the shape has an inside to fill
+    fn is_closed(self) -> bool {
+        matches!(self, Self::Rectangle | Self::Ellipse | Self::Polygon)
+    }
+}
+
+impl std::fmt::Display for ShapeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Line => write!(f, "Line"),
+            Self::Rectangle => write!(f, "Rectangle"),
+            Self::Ellipse => write!(f, "Ellipse"),
+            Self::Polygon => write!(f, "Polygon"),
+            Self::Arrow => write!(f, "Arrow"),
+        }
+    }
+}
+
+/// Shape tool settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone, Copy)]
+pub(crate) struct ShapeTool {
+    /// Kind of shape
+    pub(crate) kind: ShapeKind,
+    /// Width of the outline, in pixels
+    pub(crate) stroke_width: f32,
+    /// Color of the outline
+    pub(crate) stroke_color: Color32,
+    /// Fill the inside of the closed shapes
+    pub(crate) filled: bool,
+    /// Color of the inside
+    pub(crate) fill_color: Color32,
+    /// Smooth edges
+    pub(crate) anti_alias: bool,
+}
+
+impl Default for ShapeTool {
+    fn default() -> Self {
+        Self {
+            kind: ShapeKind::default(),
+            stroke_width: 4.0,
+            stroke_color: Color32::RED,
+            filled: false,
+            fill_color: Color32::from_rgba_unmultiplied(255, 0, 0, 64),
+            anti_alias: true,
+        }
+    }
+}
+
+impl ShapeTool {
+    /// Settings ui
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal_wrapped(|ui| {
+            for kind in ShapeKind::ALL {
+                ui.selectable_value(&mut self.kind, kind, kind.to_string());
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("Stroke");
+            ui.add(
+                egui::DragValue::new(&mut self.stroke_width)
+                    .range(0.0..=200.0)
+                    .speed(0.1)
+                    .suffix(" px"),
+            );
+            ui.color_edit_button_srgba(&mut self.stroke_color);
+        });
+        ui.add_enabled_ui(self.kind.is_closed(), |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.filled, "Fill");
+                ui.color_edit_button_srgba(&mut self.fill_color);
+            });
+        });
+        ui.checkbox(&mut self.anti_alias, "Anti-aliasing");
+        ui.label(if self.kind == ShapeKind::Polygon {
+            "Click to add a vertex, double click or Enter to finish, Shift for 45° angles"
+        } else {
+            "Shift for 45° angles, squares and circles"
+        });
+    }
+
+    /// Point where the shape ends when dragged from `start` to `end`, with Shift held if `constrain`
+    pub(crate) fn end_point(self, start: Pos2, end: Pos2, constrain: bool) -> Pos2 {
+        if !constrain {
+            return end;
+        }
+        let delta = end - start;
+        match self.kind {
+            ShapeKind::Rectangle | ShapeKind::Ellipse => {
+                let side = delta.x.abs().max(delta.y.abs());
+                start + Vec2::new(side.copysign(delta.x), side.copysign(delta.y))
+            }
+            ShapeKind::Line | ShapeKind::Polygon | ShapeKind::Arrow => {
+                let step = std::f32::consts::FRAC_PI_4;
+                let angle = (delta.angle() / step).round() * step;
+                start + Vec2::angled(angle) * delta.length()
+            }
+        }
+    }
+
+    /// Outline of the shape defined by `points`, in canvas coordinates
+    fn outline(self, points: &[Pos2]) -> Outline {
+        let (Some(&start), Some(&end)) = (points.first(), points.last()) else {
+            return Outline::Polyline(Vec::new(), false);
+        };
+        match self.kind {
+            ShapeKind::Line => Outline::Polyline(vec![start, end], false),
+            ShapeKind::Rectangle => Outline::Rectangle(Rect::from_two_pos(start, end)),
+            ShapeKind::Ellipse => Outline::Ellipse(Rect::from_two_pos(start, end)),
+            ShapeKind::Polygon => Outline::Polyline(points.to_vec(), true),
+            ShapeKind::Arrow => {
+                let direction = (end - start).normalized();
+                let length = (self.stroke_width * 3.0 + 6.0).min((end - start).length());
+                let base = end - direction * length;
+                let side = direction.rot90() * length / 2.0;
+                Outline::Arrow([start, base], [end, base + side, base - side])
+            }
+        }
+    }
+
+    /// Paint the shape being drawn, over the image at `image_rect` on the screen
+    pub(crate) fn paint(self, ui: &egui::Ui, points: &[Pos2], image_rect: Rect) {
+        let offset = image_rect.min.to_vec2();
+        let stroke = Stroke::new(self.stroke_width, self.stroke_color);
+        let fill = if self.filled && self.kind.is_closed() {
+            self.fill_color
+        } else {
+            Color32::TRANSPARENT
+        };
+        let painter = ui.painter();
+        match self.outline(points) {
+            Outline::Polyline(points, closed) => {
+                let points = points.into_iter().map(|point| point + offset).collect();
+                if closed {
+                    painter.add(egui::epaint::PathShape::closed_line(points, stroke));
+                } else {
+                    painter.add(egui::Shape::line(points, stroke));
+                }
+            }
+            Outline::Rectangle(rect) => {
+                painter.rect(
+                    rect.translate(offset),
+                    0.0,
+                    fill,
+                    stroke,
+                    egui::StrokeKind::Middle,
+                );
+            }
+            Outline::Ellipse(rect) => {
+                let (center, radius) = (rect.center() + offset, rect.size() / 2.0);
+                painter.add(egui::Shape::ellipse_filled(center, radius, fill));
+                painter.add(egui::Shape::ellipse_stroke(center, radius, stroke));
+            }
+            Outline::Arrow(line, head) => {
+                painter.line_segment(line.map(|point| point + offset), stroke);
+                painter.add(egui::Shape::convex_polygon(
+                    head.map(|point| point + offset).to_vec(),
+                    self.stroke_color,
+                    Stroke::NONE,
+                ));
+            }
+        }
+    }
+}
+
+/// Geometry of a shape
+enum Outline {
+    /// Vertices, closed or not
+    Polyline(Vec<Pos2>, bool),
+    /// Axis aligned rectangle
+    Rectangle(Rect),
+    /// Ellipse inside a rectangle
+    Ellipse(Rect),
+    /// Line and the triangle of the head
+    Arrow([Pos2; 2], [Pos2; 3]),
+}
+
+impl Outline {
+    /// Bounding box, without the stroke
+    fn bounds(&self) -> Rect {
+        match self {
+            Self::Polyline(points, _) => Rect::from_points(points),
+            Self::Rectangle(rect) | Self::Ellipse(rect) => *rect,
+            Self::Arrow(line, head) => Rect::from_points(line).union(Rect::from_points(head)),
+        }
+    }
+
+    /// Distance from the outline, negative inside the closed shapes
+    fn signed_distance(&self, pos: Pos2) -> f32 {
+        match self {
+            Self::Polyline(points, closed) => polygon_distance(points, *closed, pos),
+            Self::Rectangle(rect) => {
+                let outside = ((pos - rect.center()).abs() - rect.size() / 2.0).max(Vec2::ZERO);
+                let inside = ((pos - rect.center()).abs() - rect.size() / 2.0).max_elem();
+                outside.length() + inside.min(0.0)
+            }
+            Self::Ellipse(rect) => ellipse_distance(rect.center(), rect.size() / 2.0, pos),
+            Self::Arrow(line, _) => polygon_distance(line, false, pos),
+        }
+    }
+
+    /// Signed distance from the arrow head, filled with the stroke color, `None` for the other shapes
+    fn head_distance(&self, pos: Pos2) -> Option<f32> {
+        match self {
+            Self::Arrow(_, head) => Some(polygon_distance(head, true, pos)),
+            _ => None,
+        }
+    }
+}
+
+/// Distance from the segment `[start, end]`
+fn segment_distance(start: Pos2, end: Pos2, pos: Pos2) -> f32 {
+    let segment = end - start;
+    let length = segment.length_sq();
+    let t = if length > 0.0 {
+        ((pos - start).dot(segment) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    pos.distance(start + segment * t)
+}
+
+/// Distance from a polyline, negative inside when closed (even-odd rule)
+fn polygon_distance(points: &[Pos2], closed: bool, pos: Pos2) -> f32 {
+    let edges = points.windows(2).map(|edge| (edge[0], edge[1]));
+    let closing = closed
+        .then(|| points.last().copied().zip(points.first().copied()))
+        .flatten();
+    let mut distance = f32::INFINITY;
+    let mut inside = false;
+    for (start, end) in edges.chain(closing) {
+        distance = distance.min(segment_distance(start, end, pos));
+        if (start.y > pos.y) != (end.y > pos.y)
+            && pos.x < start.x + (pos.y - start.y) / (end.y - start.y) * (end.x - start.x)
+        {
+            inside = !inside;
+        }
+    }
+    if points.len() == 1 {
+        distance = pos.distance(points[0]);
+    }
+    if closed && inside {
+        -distance
+    } else {
+        distance
+    }
+}
+
+/// Approximate distance from an ellipse, negative inside
+fn ellipse_distance(center: Pos2, radius: Vec2, pos: Pos2) -> f32 {
+    let radius = radius.max(Vec2::splat(1e-3));
+    let pos = pos - center;
+    let k0 = (pos / radius).length();
+    let k1 = (pos / (radius * radius)).length();
+    if k1 > 0.0 {
+        k0 * (k0 - 1.0) / k1
+    } else {
+        -radius.min_elem()
+    }
+}
+
+/// Part of a pixel covered at a signed distance from an edge, the pixel is covered when negative
+fn coverage(distance: f32, anti_alias: bool) -> f32 {
+    if anti_alias {
+        (0.5 - distance).clamp(0.0, 1.0)
+    } else if distance < 0.0 {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Blend `color` over a pixel with an opacity
+pub(crate) fn blend(pixel: &mut [f32; 4], color: Color32, opacity: f32) {
+    let [red, green, blue, alpha] = color.to_srgba_unmultiplied().map(|c| f32::from(c) / 255.0);
+    over(pixel, [red, green, blue, alpha * opacity]);
+}
+
+/// Blend a pixel over another, both not premultiplied
+pub(crate) fn over(pixel: &mut [f32; 4], src: [f32; 4]) {
+    let src_alpha = src[3];
+    if src_alpha <= 0.0 {
+        return;
+    }
+    let dst_alpha = pixel[3];
+    let out_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+    for (channel, src) in pixel[..3].iter_mut().zip(src) {
+        *channel = (src * src_alpha + *channel * dst_alpha * (1.0 - src_alpha)) / out_alpha;
+    }
+    pixel[3] = out_alpha;
+}
+
+impl ShapeTool {
+    /// Draw the shape on `img`, whose top left corner is at `origin` on the canvas
+    fn rasterize(self, img: &DynamicImage, origin: Pos2, outline: &Outline) -> DynamicImage {
+        let mut pixels: Rgba32FImage = img.to_rgba32f();
+        let half_width = self.stroke_width / 2.0;
+        let fill = self.filled && self.kind.is_closed();
+        for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+            #[allow(clippy::cast_precision_loss)]
+            let pos = origin + Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let distance = outline.signed_distance(pos);
+            if fill {
+                blend(
+                    &mut pixel.0,
+                    self.fill_color,
+                    coverage(distance, self.anti_alias),
+                );
+            }
+            let mut stroke = if half_width > 0.0 {
+                coverage(distance.abs() - half_width, self.anti_alias)
+            } else {
+                0.0
+            };
+            if let Some(head) = outline.head_distance(pos) {
+                stroke = stroke.max(coverage(head, self.anti_alias));
+            }
+            blend(&mut pixel.0, self.stroke_color, stroke);
+        }
+        ops::convert(&DynamicImage::ImageRgba32F(pixels), img.color())
+    }
+}
+
+impl Document {
+    /// Draw a shape in the active layer, inside the selection
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn draw_shape(&mut self, tool: ShapeTool, points: &[Pos2]) -> Result<(), AppError> {
+        let outline = tool.outline(points);
+        // the anti-aliased edge is half a pixel outside the stroke
+        let bounds = outline.bounds().expand(tool.stroke_width / 2.0 + 1.0);
+        let (width, height) = self.size();
+        let canvas = Region::new(0, 0, width, height);
+        let min = bounds.min.max(Pos2::ZERO).floor();
+        let max = bounds.max.ceil();
+        let mut region = Region::new(
+            min.x as u32,
+            min.y as u32,
+            (max.x - min.x).max(0.0) as u32,
+            (max.y - min.y).max(0.0) as u32,
+        );
+        region = region.intersection(self.selection_region().unwrap_or(canvas));
+        if region.is_empty() {
+            return Ok(());
+        }
+        let mask = self.selection_mask(region);
+        let layer = self.layer();
+        #[allow(clippy::cast_precision_loss)]
+        let origin = {
+            let layer_region = layer.to_layer_region(region);
+            Pos2::new(
+                (i64::from(layer_region.x) + i64::from(layer.props.offset.0)) as f32,
+                (i64::from(layer_region.y) + i64::from(layer.props.offset.1)) as f32,
+            )
+        };
+        self.apply_op(Some(region), mask.as_ref(), |before| {
+            tool.rasterize(before, origin, &outline)
+        })
+    }
+}
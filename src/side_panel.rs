@@ -1,15 +1,14 @@
 //! Side panel
 
-use bladvak::eframe::egui::{self, Color32, Pos2};
-use bladvak::egui_extras::{Column, TableBuilder};
-use bladvak::errors::{AppError, ErrorManager};
-use image::Rgba;
-use image::{ColorType, DynamicImage, GenericImage, GenericImageView, Pixel, imageops::FilterType};
-use imageproc::filter::median_filter;
-use std::sync::Arc;
+use bladvak::eframe::egui::{self, Color32};
+use bladvak::errors::ErrorManager;
+use image::{ColorType, imageops::FilterType};
 
 use crate::TarsierApp;
-use crate::document::Document;
+use crate::edit_mode::EditMode;
+use crate::exif_editor::exif_ui;
+use crate::ops::{self, Anchor, Channel, Interpolation, Operation, ResizeMode, RotateFit};
+use crate::recording::MacroStep;
 
 /// Image settings
 #[derive(Debug)]
@@ -26,13 +25,57 @@ impl Default for Others {
     }
 }
 
+/// What a resize targets
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum ResizeKind {
+    /// Width and height, reached with a mode
+    Size(ResizeMode),
+    /// Percentage of the current size
+    #[default]
+    Percent,
+    /// Size of the longest edge
+    LongEdge,
+}
+
+impl ResizeKind {
+    /// All the kinds
+    const ALL: [Self; 5] = [
+        Self::Size(ResizeMode::Fit),
+        Self::Size(ResizeMode::Exact),
+        Self::Size(ResizeMode::Fill),
+        Self::Percent,
+        Self::LongEdge,
+    ];
+}
+
+impl std::fmt::Display for ResizeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Size(ResizeMode::Fit) => write!(f, "Fit within"),
+            Self::Size(ResizeMode::Exact) => write!(f, "Exact"),
+            Self::Size(ResizeMode::Fill) => write!(f, "Fill and crop"),
+            Self::Percent => write!(f, "Percentage"),
+            Self::LongEdge => write!(f, "Long edge"),
+        }
+    }
+}
+
 /// resize settings
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
 pub(crate) struct Resize {
+    /// what the resize targets
+    pub(crate) kind: ResizeKind,
     /// new width
     pub(crate) nwidth: u32,
     /// new height
     pub(crate) nheight: u32,
+    /// keep the ratio of the image when editing the width or the height
+    pub(crate) lock_ratio: bool,
+    /// scale, in percent
+    pub(crate) percent: f32,
+    /// size of the longest edge
+    pub(crate) long_edge: u32,
     /// filter
     pub(crate) filter: FilterType,
 }
@@ -40,13 +83,227 @@ pub(crate) struct Resize {
 impl Default for Resize {
     fn default() -> Self {
         Self {
+            kind: ResizeKind::default(),
             nwidth: 500,
             nheight: 500,
+            lock_ratio: true,
+            percent: 50.0,
+            long_edge: 1024,
             filter: FilterType::Lanczos3,
         }
     }
 }
 
+impl Resize {
+    /// Resize operation with these settings
+    pub(crate) fn operation(&self) -> Operation {
+        match self.kind {
+            ResizeKind::Size(mode) => Operation::Resize {
+                width: self.nwidth,
+                height: self.nheight,
+                filter: self.filter,
+                mode,
+            },
+            ResizeKind::Percent => Operation::Scale {
+                percent: self.percent,
+                filter: self.filter,
+            },
+            ResizeKind::LongEdge => Operation::LongEdge {
+                size: self.long_edge,
+                filter: self.filter,
+            },
+        }
+    }
+
+    /// Width and height, the other one follows the ratio of `size` when locked
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn show_size(&mut self, ui: &mut egui::Ui, (width, height): (u32, u32)) {
+        let ratio = f64::from(width.max(1)) / f64::from(height.max(1));
+        ui.horizontal(|ui| {
+            let width_changed = ui
+                .add(egui::DragValue::new(&mut self.nwidth).range(1..=16384))
+                .changed();
+            ui.label("x");
+            let height_changed = ui
+                .add(egui::DragValue::new(&mut self.nheight).range(1..=16384))
+                .changed();
+            if self.lock_ratio && width_changed {
+                self.nheight = ((f64::from(self.nwidth) / ratio).round() as u32).max(1);
+            } else if self.lock_ratio && height_changed {
+                self.nwidth = ((f64::from(self.nheight) * ratio).round() as u32).max(1);
+            }
+            if ui
+                .toggle_value(&mut self.lock_ratio, "🔗")
+                .on_hover_text("Lock the aspect ratio")
+                .clicked()
+                && self.lock_ratio
+            {
+                self.nheight = ((f64::from(self.nwidth) / ratio).round() as u32).max(1);
+            }
+        });
+    }
+
+    /// Settings ui, with the size of the result for an image of `size`
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, size: (u32, u32)) {
+        egui::ComboBox::from_id_salt("resize_kind")
+            .selected_text(self.kind.to_string())
+            .show_ui(ui, |ui| {
+                for kind in ResizeKind::ALL {
+                    ui.selectable_value(&mut self.kind, kind, kind.to_string());
+                }
+            });
+        match self.kind {
+            ResizeKind::Size(_) => self.show_size(ui, size),
+            ResizeKind::Percent => {
+                ui.add(
+                    egui::DragValue::new(&mut self.percent)
+                        .range(1.0..=1000.0)
+                        .suffix("%"),
+                );
+            }
+            ResizeKind::LongEdge => {
+                ui.add(egui::DragValue::new(&mut self.long_edge).range(1..=16384));
+            }
+        }
+        egui::ComboBox::from_id_salt("resize_filter")
+            .selected_text(ops::display_filter_type(&self.filter))
+            .show_ui(ui, |ui| {
+                for filter in [
+                    FilterType::Nearest,
+                    FilterType::Triangle,
+                    FilterType::CatmullRom,
+                    FilterType::Gaussian,
+                    FilterType::Lanczos3,
+                ] {
+                    ui.selectable_value(&mut self.filter, filter, format!("{filter:?}"));
+                }
+            });
+        if let Some((width, height)) = self.operation().resized_dimensions(size) {
+            ui.label(format!("{}x{} → {width}x{height}", size.0, size.1));
+        }
+    }
+}
+
+/// Free rotation settings
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct Rotate {
+    /// Angle in degrees, clockwise
+    pub(crate) degrees: f32,
+    /// Interpolation of the pixels
+    pub(crate) interpolation: Interpolation,
+    /// Size of the result
+    pub(crate) fit: RotateFit,
+    /// Color of the uncovered pixels
+    pub(crate) fill: Color32,
+}
+
+impl Default for Rotate {
+    fn default() -> Self {
+        Self {
+            degrees: 0.0,
+            interpolation: Interpolation::Bilinear,
+            fit: RotateFit::Expand,
+            fill: Color32::TRANSPARENT,
+        }
+    }
+}
+
+impl Rotate {
+    /// Rotation by an angle with these settings
+    pub(crate) fn operation(&self, degrees: f32) -> Operation {
+        Operation::Rotate {
+            degrees,
+            interpolation: self.interpolation,
+            fit: self.fit,
+            fill: self.fill.to_srgba_unmultiplied(),
+        }
+    }
+
+    /// Interpolation, fit and fill settings
+    pub(crate) fn show_options(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for interpolation in Interpolation::ALL {
+                ui.selectable_value(
+                    &mut self.interpolation,
+                    interpolation,
+                    format!("{interpolation:?}"),
+                );
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.fit, RotateFit::Expand, "Expand")
+                .on_hover_text("Expand the canvas to keep the whole image");
+            ui.selectable_value(&mut self.fit, RotateFit::Crop, "Crop")
+                .on_hover_text("Crop to the largest rectangle without uncovered pixels");
+            ui.selectable_value(&mut self.fit, RotateFit::Keep, "Keep size");
+        });
+        if self.fit != RotateFit::Crop {
+            ui.horizontal(|ui| {
+                ui.label("Fill");
+                ui.color_edit_button_srgba(&mut self.fill);
+            });
+        }
+    }
+}
+
+/// Canvas size and trim settings
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub(crate) struct CanvasSize {
+    /// New width
+    pub(crate) width: u32,
+    /// New height
+    pub(crate) height: u32,
+    /// Position of the image in the new canvas
+    pub(crate) anchor: Anchor,
+    /// Color of the added pixels
+    pub(crate) fill: Color32,
+    /// Tolerance of the trim, in percent
+    pub(crate) trim_tolerance: f32,
+}
+
+impl Default for CanvasSize {
+    fn default() -> Self {
+        Self {
+            width: 500,
+            height: 500,
+            anchor: Anchor::Center,
+            fill: Color32::TRANSPARENT,
+            trim_tolerance: 0.0,
+        }
+    }
+}
+
+impl CanvasSize {
+    /// Arrow shown for an anchor in the grid
+    fn anchor_label(anchor: Anchor) -> &'static str {
+        match anchor {
+            Anchor::TopLeft => "↖",
+            Anchor::Top => "↑",
+            Anchor::TopRight => "↗",
+            Anchor::Left => "←",
+            Anchor::Center => "•",
+            Anchor::Right => "→",
+            Anchor::BottomLeft => "↙",
+            Anchor::Bottom => "↓",
+            Anchor::BottomRight => "↘",
+        }
+    }
+
+    /// Grid of the 9 anchors
+    fn anchor_grid(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("canvas_anchor").show(ui, |ui| {
+            for row in Anchor::ALL.chunks(3) {
+                for anchor in row {
+                    ui.selectable_value(&mut self.anchor, *anchor, Self::anchor_label(*anchor))
+                        .on_hover_text(format!("{anchor:?}"));
+                }
+                ui.end_row();
+            }
+        });
+    }
+}
+
 /// Image opterations settings
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub(crate) struct ImageOperations {
@@ -62,14 +319,28 @@ pub(crate) struct ImageOperations {
     #[serde(skip)]
     pub(crate) other: Others,
     /// resize
-    #[serde(skip)]
+    #[serde(default)]
     pub(crate) resize: Resize,
+    /// free rotation
+    #[serde(default)]
+    pub(crate) rotate: Rotate,
+    /// canvas size and trim
+    #[serde(default)]
+    pub(crate) canvas: CanvasSize,
+    /// Median filter radius
+    #[serde(default = "default_median_radius")]
+    pub(crate) median_radius: u32,
     /// cut color
     cut_color: egui::Color32,
     /// tolerance
     cut_tolerance: i16,
 }
 
+/// Default radius of the median filter
+fn default_median_radius() -> u32 {
+    3
+}
+
 impl Default for ImageOperations {
     fn default() -> Self {
         Self {
@@ -79,6 +350,9 @@ impl Default for ImageOperations {
             contrast: 1.0,
             other: Others::default(),
             resize: Resize::default(),
+            rotate: Rotate::default(),
+            canvas: CanvasSize::default(),
+            median_radius: default_median_radius(),
             cut_color: Color32::from_rgb_additive(50, 50, 50),
             cut_tolerance: i16::MAX,
         }
@@ -96,63 +370,21 @@ impl TarsierApp {
             return;
         };
         ui.heading("Image Info");
-        ui.label(format!(
-            "Size: {}x{}",
-            document.img.width(),
-            document.img.height()
-        ));
-        ui.label(format!("Format: {:?}", document.img.color()));
-        match &document.exif {
-            Some(exif) => {
-                ui.collapsing("Exif info", |ui| {
-                    TableBuilder::new(ui)
-                        .max_scroll_height(100.0)
-                        .striped(true)
-                        .column(Column::auto())
-                        .column(Column::auto())
-                        .column(Column::remainder())
-                        .header(20.0, |mut header| {
-                            header.col(|ui| {
-                                ui.label("Exif tag");
-                            });
-                            header.col(|ui| {
-                                ui.label("IFD idx");
-                            });
-                            header.col(|ui| {
-                                ui.label("exif value");
-                            });
-                        })
-                        .body(|mut body| {
-                            for field in exif.fields() {
-                                body.row(30.0, |mut row| {
-                                    row.col(|ui| {
-                                        ui.label(format!("{}", field.tag));
-                                    });
-                                    row.col(|ui| {
-                                        ui.label(format!("{}", field.ifd_num));
-                                    });
-                                    row.col(|ui| {
-                                        ui.label(format!(
-                                            "{}",
-                                            field.display_value().with_unit(exif)
-                                        ));
-                                    });
-                                });
-                            }
-                        });
-                });
-            }
-            None => {
-                ui.label("No exif detected");
-            }
-        }
+        let (width, height) = document.size();
+        ui.label(format!("Size: {width}x{height}"));
+        ui.label(format!("Format: {:?}", document.img().color()));
+        ui.label(format!("Layers: {}", document.layers.len()));
+        ui.collapsing("Exif info", |ui| {
+            exif_ui(ui, document, &mut self.exif_editor);
+        });
         if let Some(document) = self.documents.get_current_doc_mut()
             && ui.button("Copy image").clicked()
+            && let flattened = document.composite()
             && let Err(e) = bladvak::utils::set_image_in_clipboard(
                 ui.ctx(),
-                document.img.width() as usize,
-                document.img.height() as usize,
-                document.img.to_rgba8().as_flat_samples().as_slice(),
+                flattened.width() as usize,
+                flattened.height() as usize,
+                flattened.to_rgba8().as_flat_samples().as_slice(),
             )
         {
             error_manager.add_error(e);
@@ -182,73 +414,40 @@ impl TarsierApp {
     }
 
     /// Button for convert
-    fn button_convert(&mut self, ui: &mut egui::Ui) {
-        let Some(document) = self.documents.get_current_doc_mut() else {
-            return;
-        };
+    fn button_convert(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         ui.label("Convert");
         Self::combo_box_color_type(ui, &mut self.image_operations.other.convert_to);
         if ui.button("Convert").clicked() {
-            let new_img = match self.image_operations.other.convert_to {
-                ColorType::L8 => document.img.to_luma8().into(),
-                ColorType::L16 => document.img.to_luma16().into(),
-                ColorType::La8 => document.img.to_luma_alpha8().into(),
-                ColorType::La16 => document.img.to_luma_alpha16().into(),
-                ColorType::Rgb8 => document.img.to_rgb8().into(),
-                ColorType::Rgb16 => document.img.to_rgb16().into(),
-                ColorType::Rgb32F => document.img.to_rgb32f().into(),
-                ColorType::Rgba16 => document.img.to_rgba16().into(),
-                ColorType::Rgba32F => document.img.to_rgba32f().into(),
-                ColorType::Rgba8 | _ => document.img.to_rgba8().into(),
-            };
-            self.update_image(new_img);
+            let operation = Operation::Convert(self.image_operations.other.convert_to);
+            self.run_operation(operation, error_manager);
         }
     }
 
     /// Side panel content
     pub(crate) fn image_operations(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         self.quick_operations(ui, error_manager);
-        self.button_convert(ui);
+        self.preview_ui(ui, error_manager);
+        ui.separator();
+        self.button_convert(ui, error_manager);
         ui.separator();
         self.button_outline(ui, error_manager);
         ui.separator();
         if ui.button("edge detection").clicked() {
-            self.apply_op(
-                |img| {
-                    img.filter3x3(&[
-                        0.0, -1.0, 0.0, //
-                        -1.0, 4.0, -1.0, //
-                        0.0, -1.0, 0.0, //
-                    ])
-                },
-                error_manager,
-            );
+            self.run_operation(Operation::EdgeDetection, error_manager);
         }
         ui.separator();
         if ui.button("Grayscale").clicked() {
-            self.apply_op(
-                |img| {
-                    let color = img.color();
-                    let inner = img.grayscale();
-                    match color {
-                        ColorType::L8 => inner.to_luma8().into(),
-                        ColorType::L16 => inner.to_luma16().into(),
-                        ColorType::La8 => inner.to_luma_alpha8().into(),
-                        ColorType::La16 => inner.to_luma_alpha16().into(),
-                        ColorType::Rgb8 => inner.to_rgb8().into(),
-                        ColorType::Rgb16 => inner.to_rgb16().into(),
-                        ColorType::Rgba16 => inner.to_rgba16().into(),
-                        ColorType::Rgba8 | _ => inner.to_rgba8().into(),
-                    }
-                },
-                error_manager,
-            );
+            self.run_operation(Operation::Grayscale, error_manager);
         }
         ui.separator();
         self.show_basic_ops(ui, error_manager);
         ui.separator();
         self.show_resize(ui, error_manager);
         ui.separator();
+        self.show_rotate(ui, error_manager);
+        ui.separator();
+        self.show_canvas_size(ui, error_manager);
+        ui.separator();
         self.show_channels(ui, error_manager);
         ui.separator();
         self.show_median_filter(ui, error_manager);
@@ -259,104 +458,92 @@ impl TarsierApp {
     /// show basic operations
     pub(crate) fn show_basic_ops(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         if ui.button("invert").clicked() {
-            self.apply_op(
-                |img| {
-                    let mut copied_img = img.clone();
-                    copied_img.invert();
-                    copied_img
-                },
-                error_manager,
-            );
+            self.run_operation(Operation::Invert, error_manager);
         }
         ui.separator();
-        ui.add(egui::Slider::new(
-            &mut self.image_operations.blur,
-            0.0..=100.0,
-        ));
+        let changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.blur,
+                0.0..=100.0,
+            ))
+            .changed();
+        let blur = Operation::Blur(self.image_operations.blur);
+        self.preview_changed(changed, blur.clone());
         if ui.button("Blur").clicked() {
-            let blur = self.image_operations.blur;
-            self.apply_op(|img| img.blur(blur), error_manager);
+            self.run_operation(blur, error_manager);
         }
         ui.separator();
-        ui.add(egui::Slider::new(
-            &mut self.image_operations.hue_rotation,
-            0..=360,
-        ));
-
+        let changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.hue_rotation,
+                0..=360,
+            ))
+            .changed();
+        let hue_rotate = Operation::HueRotate(self.image_operations.hue_rotation);
+        self.preview_changed(changed, hue_rotate.clone());
         if ui.button("hue rotate").clicked() {
-            let hue_rotation = self.image_operations.hue_rotation;
-            self.apply_op(|img| img.huerotate(hue_rotation), error_manager);
+            self.run_operation(hue_rotate, error_manager);
         }
         ui.separator();
-        ui.add(egui::Slider::new(
-            &mut self.image_operations.brighten,
-            -100..=100,
-        ));
+        let changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.brighten,
+                -100..=100,
+            ))
+            .changed();
+        let brighten = Operation::Brighten(self.image_operations.brighten);
+        self.preview_changed(changed, brighten.clone());
         if ui.button("brighten").clicked() {
-            let brighten = self.image_operations.brighten;
-            self.apply_op(|img| img.brighten(brighten), error_manager);
+            self.run_operation(brighten, error_manager);
         }
         ui.separator();
-        ui.add(egui::Slider::new(
-            &mut self.image_operations.contrast,
-            -50.0..=50.0,
-        ));
+        let changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.contrast,
+                -50.0..=50.0,
+            ))
+            .changed();
+        let contrast = Operation::Contrast(self.image_operations.contrast);
+        self.preview_changed(changed, contrast.clone());
         if ui.button("contrast").clicked() {
-            let contrast = self.image_operations.contrast;
-            self.apply_op(|img| img.adjust_contrast(contrast), error_manager);
+            self.run_operation(contrast, error_manager);
         }
     }
 
     /// show median filter
     fn show_median_filter(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        let changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.median_radius,
+                1..=20,
+            ))
+            .changed();
+        let median_filter = Operation::MedianFilter(self.image_operations.median_radius);
+        self.preview_changed(changed, median_filter.clone());
         if ui.button("Median filter").clicked() {
-            let radius = 3;
-            self.apply_op(
-                |img| {
-                    let inner = img.to_luma8();
-                    let inner = median_filter(&inner, radius, radius);
-                    DynamicImage::ImageLuma8(inner)
-                },
-                error_manager,
-            );
+            self.run_operation(median_filter, error_manager);
         }
     }
 
     /// show cut color
     fn show_cut_color(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
-        ui.add(egui::Slider::new(
-            &mut self.image_operations.cut_tolerance,
-            0..=254,
-        ));
+        let mut changed = ui
+            .add(egui::Slider::new(
+                &mut self.image_operations.cut_tolerance,
+                0..=254,
+            ))
+            .changed();
         let [r, g, b, _r] = self.image_operations.cut_color.to_array();
         let mut color = [r, g, b];
-        ui.color_edit_button_srgb(&mut color);
+        changed |= ui.color_edit_button_srgb(&mut color).changed();
         self.image_operations.cut_color = Color32::from_rgb(color[0], color[1], color[2]);
+        let cut_color = Operation::CutColor {
+            color,
+            tolerance: self.image_operations.cut_tolerance,
+        };
+        self.preview_changed(changed, cut_color.clone());
         if ui.button("Cut color").clicked() {
-            let tolerance = self.image_operations.cut_tolerance;
-            let target = self.image_operations.cut_color.to_array();
-            self.apply_op(
-                |img| {
-                    let mut res = img.clone().to_rgba8();
-                    for (x, y, pixel) in img.pixels() {
-                        let [r, g, b, a] = pixel.0;
-                        let diff_r = (i16::from(r) - i16::from(target[0])).abs();
-                        let diff_g = (i16::from(g) - i16::from(target[1])).abs();
-                        let diff_b = (i16::from(b) - i16::from(target[2])).abs();
-                        let matches =
-                            diff_r <= tolerance && diff_b <= tolerance && diff_g <= tolerance;
-
-                        let color = if matches {
-                            Rgba([r, g, b, 0]) // transparent
-                        } else {
-                            Rgba([r, g, b, a])
-                        };
-                        res.put_pixel(x, y, color);
-                    }
-                    DynamicImage::ImageRgba8(res)
-                },
-                error_manager,
-            );
+            self.run_operation(cut_color, error_manager);
         }
     }
 
@@ -364,272 +551,148 @@ impl TarsierApp {
     fn show_channels(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         ui.collapsing("Channels", |ui| {
             if ui.button("Red").clicked() {
-                self.apply_op(
-                    |img| -> DynamicImage {
-                        let mut c = img.clone();
-                        for x in 0..c.width() {
-                            for y in 0..c.height() {
-                                let mut px = c.get_pixel(x, y);
-                                px.channels_mut()[1] = 0;
-                                px.channels_mut()[2] = 0;
-                                c.put_pixel(x, y, px);
-                            }
-                        }
-                        c
-                    },
-                    error_manager,
-                );
+                self.run_operation(Operation::Channel(Channel::Red), error_manager);
             }
             if ui.button("Green").clicked() {
-                self.apply_op(
-                    |img| -> DynamicImage {
-                        let mut c = img.clone();
-                        for x in 0..c.width() {
-                            for y in 0..c.height() {
-                                let mut px = c.get_pixel(x, y);
-                                px.channels_mut()[0] = 0;
-                                px.channels_mut()[2] = 0;
-                                c.put_pixel(x, y, px);
-                            }
-                        }
-                        c
-                    },
-                    error_manager,
-                );
+                self.run_operation(Operation::Channel(Channel::Green), error_manager);
             }
             if ui.button("Blue").clicked() {
-                self.apply_op(
-                    |img| -> DynamicImage {
-                        let mut c = img.clone();
-                        for x in 0..c.width() {
-                            for y in 0..c.height() {
-                                let mut px = c.get_pixel(x, y);
-                                px.channels_mut()[0] = 0;
-                                px.channels_mut()[1] = 0;
-                                c.put_pixel(x, y, px);
-                            }
-                        }
-                        c
-                    },
-                    error_manager,
-                );
+                self.run_operation(Operation::Channel(Channel::Blue), error_manager);
             }
         });
     }
 
-    /// show resize ui
-    fn show_resize(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
-        ui.collapsing("Resize", |ui| {
-            ui.add(egui::DragValue::new(
-                &mut self.image_operations.resize.nwidth,
-            ));
-            ui.add(egui::DragValue::new(
-                &mut self.image_operations.resize.nheight,
-            ));
-            egui::ComboBox::from_id_salt("convert_box")
-                .selected_text(display_filter_type(&self.image_operations.resize.filter))
-                .show_ui(ui, |ui| {
-                    ui.selectable_value(
-                        &mut self.image_operations.resize.filter,
-                        FilterType::Nearest,
-                        format!("{:?}", FilterType::Nearest),
-                    );
-                    ui.selectable_value(
-                        &mut self.image_operations.resize.filter,
-                        FilterType::Triangle,
-                        format!("{:?}", FilterType::Triangle),
-                    );
-                    ui.selectable_value(
-                        &mut self.image_operations.resize.filter,
-                        FilterType::CatmullRom,
-                        format!("{:?}", FilterType::CatmullRom),
-                    );
-                    ui.selectable_value(
-                        &mut self.image_operations.resize.filter,
-                        FilterType::Gaussian,
-                        format!("{:?}", FilterType::Gaussian),
-                    );
-                    ui.selectable_value(
-                        &mut self.image_operations.resize.filter,
-                        FilterType::Lanczos3,
-                        format!("{:?}", FilterType::Lanczos3),
-                    );
-                });
-            if ui.button("Resize").clicked() {
-                let resize = self.image_operations.resize.clone();
-                self.apply_op(
-                    |img| img.resize(resize.nwidth, resize.nheight, resize.filter),
-                    error_manager,
-                );
-            }
+    /// show free rotation ui
+    fn show_rotate(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        ui.collapsing("Rotate", |ui| {
+            let rotate = &mut self.image_operations.rotate;
+            ui.add(
+                egui::DragValue::new(&mut rotate.degrees)
+                    .range(-180.0..=180.0)
+                    .speed(0.1)
+                    .suffix("°"),
+            )
+            .on_hover_text("Angle, clockwise");
+            rotate.show_options(ui);
+            ui.horizontal(|ui| {
+                if ui.button("Rotate").clicked() {
+                    let rotate = &self.image_operations.rotate;
+                    let operation = rotate.operation(rotate.degrees);
+                    self.run_operation(operation, error_manager);
+                }
+                if ui
+                    .button("Straighten")
+                    .on_hover_text("Draw a line that should be horizontal")
+                    .clicked()
+                {
+                    self.mode.current = EditMode::Straighten;
+                }
+            });
         });
     }
 
-    /// Apply operation
-    #[allow(clippy::cast_possible_truncation)]
-    #[allow(clippy::cast_sign_loss)]
-    pub(crate) fn apply_op<F>(&mut self, func: F, error_manager: &mut ErrorManager)
-    where
-        F: Fn(&DynamicImage) -> DynamicImage,
-    {
+    /// show canvas size and trim ui
+    fn show_canvas_size(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         let Some(document) = self.documents.get_current_doc_mut() else {
             return;
         };
-        let current_selection = document.selection.rectangle.map(|selection| {
-            (
-                selection.min.x as u32,
-                selection.min.y as u32,
-                selection.max.x as u32,
-                selection.max.y as u32,
-            )
-        });
-        if let Some(selection) = current_selection {
-            let cropped_img = document.img.crop(
-                selection.0,
-                selection.1,
-                selection.2 - selection.0,
-                selection.3 - selection.1,
-            );
-            let img_color = document.img.color();
-            let inner = func(&cropped_img);
-            let new_color = inner.color();
-            if new_color != img_color {
-                document.img = match new_color {
-                    ColorType::L8 => document.img.to_luma8().into(),
-                    ColorType::L16 => document.img.to_luma16().into(),
-                    ColorType::La8 => document.img.to_luma_alpha8().into(),
-                    ColorType::La16 => document.img.to_luma_alpha16().into(),
-                    ColorType::Rgb8 => document.img.to_rgb8().into(),
-                    ColorType::Rgb16 => document.img.to_rgb16().into(),
-                    ColorType::Rgba16 => document.img.to_rgba16().into(),
-                    ColorType::Rgba8 | _ => document.img.to_rgba8().into(),
+        let current_size = document.size();
+        ui.collapsing("Canvas size", |ui| {
+            let canvas = &mut self.image_operations.canvas;
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut canvas.width).range(1..=16384));
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut canvas.height).range(1..=16384));
+                if ui.button("Current").clicked() {
+                    (canvas.width, canvas.height) = current_size;
+                }
+            });
+            canvas.anchor_grid(ui);
+            ui.horizontal(|ui| {
+                ui.label("Fill");
+                ui.color_edit_button_srgba(&mut canvas.fill)
+                    .on_hover_text("A transparent fill adds an alpha channel");
+                if ui.button("Transparent").clicked() {
+                    canvas.fill = Color32::TRANSPARENT;
+                }
+            });
+            if ui.button("Resize canvas").clicked() {
+                let canvas = &self.image_operations.canvas;
+                let operation = Operation::CanvasSize {
+                    width: canvas.width,
+                    height: canvas.height,
+                    anchor: canvas.anchor,
+                    fill: canvas.fill.to_srgba_unmultiplied(),
                 };
+                self.run_operation(operation, error_manager);
             }
-
-            if let Err(e) = document.img.copy_from(&inner, selection.0, selection.1) {
-                error_manager.add_error(AppError::new_with_source(
-                    "Cannot update selected image part",
-                    Arc::new(e),
-                ));
+            ui.separator();
+            ui.add(
+                egui::Slider::new(
+                    &mut self.image_operations.canvas.trim_tolerance,
+                    0.0..=100.0,
+                )
+                .text("Tolerance"),
+            );
+            if ui
+                .button("Trim")
+                .on_hover_text("Remove the borders of the color of the top left pixel")
+                .clicked()
+            {
+                let tolerance = self.image_operations.canvas.trim_tolerance;
+                self.run_operation(Operation::Trim { tolerance }, error_manager);
             }
-            self.updated_image();
-        } else {
-            let new_img = func(&document.img);
-            self.update_image(new_img);
-        }
+        });
     }
 
-    /// Button to show the outline
-    #[allow(clippy::similar_names)]
-    pub(crate) fn button_outline(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
-        if ui.button("sobel outline").clicked() {
-            self.apply_op(
-                |img| {
-                    let mut img = img.clone();
-                    let sobel_x = img.filter3x3(&[
-                        -1.0, 0.0, 1.0, //
-                        -2.0, 0.0, 2.0, //
-                        -1.0, 0.0, 1.0, //
-                    ]);
-                    let sobel_x2 = img.filter3x3(&[
-                        1.0, 0.0, -1.0, //
-                        2.0, 0.0, -2.0, //
-                        1.0, 0.0, -1.0, //
-                    ]);
-                    let sobel_y = img.filter3x3(&[
-                        -1.0, -2.0, -1.0, //
-                        0.0, 0.0, 0.0, //
-                        1.0, 2.0, 1.0, //
-                    ]);
-                    let sobel_y2 = img.filter3x3(&[
-                        1.0, 2.0, 1.0, //
-                        0.0, 0.0, 0.0, //
-                        -1.0, -2.0, -1.0, //
-                    ]);
-                    for y in 0..img.height() {
-                        for x in 0..img.width() {
-                            let mut pixel = sobel_x.get_pixel(x, y);
-                            let pixel_y = sobel_y.get_pixel(x, y);
-                            pixel.blend(&pixel_y);
-                            let pixel_x2 = sobel_x2.get_pixel(x, y);
-                            pixel.blend(&pixel_x2);
-                            let pixel_y2 = sobel_y2.get_pixel(x, y);
-                            pixel.blend(&pixel_y2);
-                            img.put_pixel(x, y, pixel);
-                        }
-                    }
-                    img
-                },
-                error_manager,
-            );
-        }
+    /// show resize ui
+    fn show_resize(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        // the operation is applied to the selection if any
+        let size = document
+            .selection_region()
+            .map_or(document.size(), |region| (region.width, region.height));
+        ui.collapsing("Resize", |ui| {
+            self.image_operations.resize.show(ui, size);
+            if ui.button("Resize").clicked() {
+                let operation = self.image_operations.resize.operation();
+                self.run_operation(operation, error_manager);
+            }
+        });
     }
 
-    /// Draw a point
-    #[allow(clippy::cast_sign_loss)]
-    pub(crate) fn draw_point(&mut self, x_center: u32, y_center: u32) {
+    /// Apply an operation
+    pub(crate) fn apply_operation(
+        &mut self,
+        operation: &Operation,
+        error_manager: &mut ErrorManager,
+    ) {
         let Some(document) = self.documents.get_current_doc_mut() else {
             return;
         };
-        let radius = self.mode.drawing.pen_radius;
-        let min_y = y_center.saturating_sub(radius);
-        for y in min_y..=(y_center + radius) {
-            let min_x = x_center.saturating_sub(radius);
-            for x in min_x..=(x_center + radius) {
-                if x.saturating_sub(x_center).pow(2)
-                    + y.saturating_sub(y_center).pow(2)
-                    + x_center.saturating_sub(x).pow(2)
-                    + y_center.saturating_sub(y).pow(2)
-                    <= radius.pow(2)
-                {
-                    // Ensure pixel is within bounds
-                    if x < document.img.width() && y < document.img.height() {
-                        #[allow(clippy::cast_precision_loss)]
-                        if let Some(rect) = document.selection.rectangle
-                            && !rect.contains(Pos2::new(x as f32, y as f32))
-                        {
-                            continue;
-                        }
-                        draw_single_point(
-                            document,
-                            x,
-                            y,
-                            self.mode.drawing.pen_color,
-                            self.mode.drawing.drawing_blend,
-                        );
-                    }
-                }
-            }
+        let region = document.selection_region();
+        let mask = region.and_then(|region| document.selection_mask(region));
+        // a macro only replays rectangular regions
+        if mask.is_some() {
+            self.macros.skip();
+        } else {
+            self.macros.record(MacroStep::Operation {
+                operation: operation.clone(),
+                region,
+            });
+        }
+        if let Err(e) = document.apply_operation(region, mask.as_ref(), operation) {
+            error_manager.add_error(e);
         }
         self.updated_image();
     }
-}
-
-/// draw a single point
-fn draw_single_point(
-    document: &mut Document,
-    x: u32,
-    y: u32,
-    pen_color: [u8; 4],
-    drawing_blend: bool,
-) {
-    let color = image::Rgba(pen_color);
-    if drawing_blend {
-        let mut current_pixel = document.img.get_pixel(x, y);
-        current_pixel.blend(&color);
-        document.img.put_pixel(x, y, current_pixel);
-    } else {
-        document.img.put_pixel(x, y, color);
-    }
-}
 
-/// display a `FilterType`
-fn display_filter_type(filter_type: &FilterType) -> &str {
-    match filter_type {
-        FilterType::Nearest => "Nearest",
-        FilterType::Triangle => "Triangle",
-        FilterType::CatmullRom => "CatmullRom",
-        FilterType::Gaussian => "Gaussian",
-        FilterType::Lanczos3 => "Lanczos3",
+    /// Button to show the outline
+    pub(crate) fn button_outline(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        if ui.button("sobel outline").clicked() {
+            self.run_operation(Operation::SobelOutline, error_manager);
+        }
     }
 }
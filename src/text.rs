@@ -0,0 +1,689 @@
+//! Text tool: editable text boxes rasterized into the active layer
+
+use ab_glyph::{Font, FontArc, Glyph, OutlinedGlyph, PxScale, ScaleFont, point};
+use bladvak::eframe::egui::{
+    self, Color32, Id, Key, KeyboardShortcut, Modifiers, Pos2, Rect, Stroke, TextureHandle,
+    TextureOptions, Vec2,
+};
+use bladvak::errors::{AppError, ErrorManager};
+use image::{DynamicImage, Rgba32FImage};
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::TarsierApp;
+use crate::document::Document;
+use crate::layer::Layer;
+use crate::ops::{self, Region};
+use crate::selection;
+use crate::shapes;
+
+/// Extensions of the font files
+const FONT_EXTENSIONS: [&str; 2] = ["ttf", "otf"];
+
+/// Fonts shipped with the app, with their name in the egui font definitions
+const BUNDLED_FONTS: [(&str, &str); 2] = [("Ubuntu Light", "Ubuntu-Light"), ("Hack", "Hack")];
+
+/// Id of the text field of the text box
+const TEXT_EDIT_ID: &str = "text_tool_content";
+
+/// Shortcut to draw the text box
+const APPLY_SHORTCUT: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Enter);
+
+/// Check if a file is a font
+pub(crate) fn is_font_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            FONT_EXTENSIONS
+                .iter()
+                .any(|font| ext.eq_ignore_ascii_case(font))
+        })
+}
+
+/// Alignment of the lines
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub(crate) enum TextAlign {
+    /// Lines start on the left of the box
+    #[default]
+    Left,
+    /// Lines are centered in the box
+    Center,
+    /// Lines end on the right of the box
+    Right,
+}
+
+impl TextAlign {
+    /// All the alignments
+    const ALL: [Self; 3] = [Self::Left, Self::Center, Self::Right];
+
+    /// Part of the free space left of a line
+    fn factor(self) -> f32 {
+        match self {
+            Self::Left => 0.0,
+            Self::Center => 0.5,
+            Self::Right => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for TextAlign {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Left => write!(f, "Left"),
+            Self::Center => write!(f, "Center"),
+            Self::Right => write!(f, "Right"),
+        }
+    }
+}
+
+/// Text tool settings
+#[derive(serde::Deserialize, serde::Serialize, PartialEq, Debug, Clone)]
+pub(crate) struct TextTool {
+    /// Name of the font
+    pub(crate) font: String,
+    /// Height of the font, in pixels
+    pub(crate) size: f32,
+    /// Color of the glyphs
+    pub(crate) color: Color32,
+    /// Space between the lines, relative to the font height
+    pub(crate) line_spacing: f32,
+    /// Alignment of the lines
+    pub(crate) align: TextAlign,
+    /// Width of the outline around the glyphs, in pixels
+    pub(crate) outline_width: f32,
+    /// Color of the outline
+    pub(crate) outline_color: Color32,
+    /// Draw a shadow below the text
+    pub(crate) shadow: bool,
+    /// Offset of the shadow, in pixels
+    pub(crate) shadow_offset: [i32; 2],
+    /// Color of the shadow
+    pub(crate) shadow_color: Color32,
+    /// Draw the text in a new layer instead of the active one
+    pub(crate) new_layer: bool,
+}
+
+impl Default for TextTool {
+    fn default() -> Self {
+        Self {
+            font: BUNDLED_FONTS[0].0.to_string(),
+            size: 48.0,
+            color: Color32::WHITE,
+            line_spacing: 1.0,
+            align: TextAlign::default(),
+            outline_width: 0.0,
+            outline_color: Color32::BLACK,
+            shadow: false,
+            shadow_offset: [3, 3],
+            shadow_color: Color32::from_black_alpha(160),
+            new_layer: false,
+        }
+    }
+}
+
+impl TextTool {
+    /// Settings ui, with the fonts to choose from
+    pub(crate) fn show(&mut self, ui: &mut egui::Ui, fonts: &Fonts) {
+        egui::ComboBox::from_label("Font")
+            .selected_text(&self.font)
+            .show_ui(ui, |ui| {
+                for (name, _) in &fonts.loaded {
+                    ui.selectable_value(&mut self.font, name.clone(), name);
+                }
+            })
+            .response
+            .on_hover_text("Open a TTF or OTF file to add a font");
+        ui.horizontal(|ui| {
+            ui.label("Size");
+            ui.add(
+                egui::DragValue::new(&mut self.size)
+                    .range(1.0..=1000.0)
+                    .speed(0.5)
+                    .suffix(" px"),
+            );
+            ui.color_edit_button_srgba(&mut self.color);
+        });
+        ui.horizontal(|ui| {
+            for align in TextAlign::ALL {
+                ui.selectable_value(&mut self.align, align, align.to_string());
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut self.line_spacing, 0.5..=3.0)
+                .text("Line spacing")
+                .fixed_decimals(2),
+        );
+        ui.horizontal(|ui| {
+            ui.label("Outline");
+            ui.add(
+                egui::DragValue::new(&mut self.outline_width)
+                    .range(0.0..=20.0)
+                    .speed(0.1)
+                    .suffix(" px"),
+            );
+            ui.color_edit_button_srgba(&mut self.outline_color);
+        });
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.shadow, "Shadow");
+            ui.add_enabled_ui(self.shadow, |ui| {
+                ui.add(egui::DragValue::new(&mut self.shadow_offset[0]).range(-100..=100));
+                ui.add(egui::DragValue::new(&mut self.shadow_offset[1]).range(-100..=100));
+                ui.color_edit_button_srgba(&mut self.shadow_color);
+            });
+        });
+        ui.checkbox(&mut self.new_layer, "In a new layer")
+            .on_hover_text("Otherwise the text is drawn in the active layer, inside the selection");
+    }
+
+    /// Glyphs of the lines, relative to the top left corner of the text box, with the size of the box
+    #[allow(clippy::cast_precision_loss)]
+    fn layout(&self, font: &FontArc, content: &str) -> (Vec<Glyph>, Vec2) {
+        let scale = PxScale::from(self.size);
+        let scaled = font.as_scaled(scale);
+        let line_height = (scaled.height() + scaled.line_gap()) * self.line_spacing;
+        let lines: Vec<(Vec<Glyph>, f32)> = content
+            .split('\n')
+            .enumerate()
+            .map(|(idx, line)| {
+                let baseline = scaled.ascent() + idx as f32 * line_height;
+                let mut caret = 0.0;
+                let mut previous = None;
+                let mut glyphs = Vec::new();
+                for c in line.chars().filter(|c| !c.is_control()) {
+                    let id = scaled.glyph_id(c);
+                    if let Some(previous) = previous {
+                        caret += scaled.kern(previous, id);
+                    }
+                    glyphs.push(id.with_scale_and_position(scale, point(caret, baseline)));
+                    caret += scaled.h_advance(id);
+                    previous = Some(id);
+                }
+                (glyphs, caret)
+            })
+            .collect();
+        let width = lines.iter().map(|(_, width)| *width).fold(0.0, f32::max);
+        let height = scaled.height() + lines.len().saturating_sub(1) as f32 * line_height;
+        let glyphs = lines
+            .into_iter()
+            .flat_map(|(glyphs, line_width)| {
+                let shift = (width - line_width) * self.align.factor();
+                glyphs.into_iter().map(move |mut glyph| {
+                    glyph.position.x += shift;
+                    glyph
+                })
+            })
+            .collect();
+        (glyphs, Vec2::new(width, height))
+    }
+
+    /// Rasterize the text with its outline and shadow on a transparent background
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    #[allow(clippy::cast_possible_wrap)]
+    fn render(&self, font: &FontArc, content: &str) -> Rendered {
+        let (glyphs, size) = self.layout(font, content);
+        let outlined: Vec<OutlinedGlyph> = glyphs
+            .into_iter()
+            .filter_map(|glyph| font.outline_glyph(glyph))
+            .collect();
+        let bounds = outlined
+            .iter()
+            .map(|glyph| {
+                let bounds = glyph.px_bounds();
+                Rect::from_min_max(
+                    Pos2::new(bounds.min.x, bounds.min.y),
+                    Pos2::new(bounds.max.x, bounds.max.y),
+                )
+            })
+            .fold(Rect::from_min_size(Pos2::ZERO, size), Rect::union);
+        let pad = self.outline_width.ceil() as i32 + 1;
+        let min = (
+            bounds.min.x.floor() as i32 - pad,
+            bounds.min.y.floor() as i32 - pad,
+        );
+        let max = (
+            bounds.max.x.ceil() as i32 + pad,
+            bounds.max.y.ceil() as i32 + pad,
+        );
+        let mut fill = Mask::new(min, (max.0 - min.0) as usize, (max.1 - min.1) as usize);
+        for glyph in &outlined {
+            let bounds = glyph.px_bounds();
+            let (left, top) = (bounds.min.x as i32, bounds.min.y as i32);
+            glyph.draw(|x, y, coverage| {
+                fill.add(left + x as i32, top + y as i32, coverage);
+            });
+        }
+        let outline = (self.outline_width > 0.0).then(|| fill.dilate(self.outline_width));
+        let shadow = outline.as_ref().unwrap_or(&fill);
+        let [shadow_x, shadow_y] = if self.shadow {
+            self.shadow_offset
+        } else {
+            [0, 0]
+        };
+        let origin = (min.0 + shadow_x.min(0), min.1 + shadow_y.min(0));
+        let end = (max.0 + shadow_x.max(0), max.1 + shadow_y.max(0));
+        let mut image = Rgba32FImage::new((end.0 - origin.0) as u32, (end.1 - origin.1) as u32);
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let (x, y) = (origin.0 + x as i32, origin.1 + y as i32);
+            if self.shadow {
+                shapes::blend(
+                    &mut pixel.0,
+                    self.shadow_color,
+                    shadow.get(x - shadow_x, y - shadow_y),
+                );
+            }
+            if let Some(outline) = &outline {
+                shapes::blend(&mut pixel.0, self.outline_color, outline.get(x, y));
+            }
+            shapes::blend(&mut pixel.0, self.color, fill.get(x, y));
+        }
+        Rendered {
+            origin,
+            size,
+            image,
+        }
+    }
+}
+
+/// Coverage of the pixels, from 0 to 1
+struct Mask {
+    /// Position of the first pixel
+    origin: (i32, i32),
+    /// Width of the mask
+    width: usize,
+    /// Height of the mask
+    height: usize,
+    /// Coverage, row by row
+    values: Vec<f32>,
+}
+
+impl Mask {
+    /// Empty mask
+    fn new(origin: (i32, i32), width: usize, height: usize) -> Self {
+        Self {
+            origin,
+            width,
+            height,
+            values: vec![0.0; width * height],
+        }
+    }
+
+    /// Index of a pixel, `None` outside of the mask
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let x = usize::try_from(x - self.origin.0).ok()?;
+        let y = usize::try_from(y - self.origin.1).ok()?;
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    /// Coverage of a pixel
+    fn get(&self, x: i32, y: i32) -> f32 {
+        self.index(x, y).map_or(0.0, |idx| self.values[idx])
+    }
+
+    /// Cover more of a pixel
+    fn add(&mut self, x: i32, y: i32, coverage: f32) {
+        if let Some(idx) = self.index(x, y) {
+            self.values[idx] = (self.values[idx] + coverage).min(1.0);
+        }
+    }
+
+    /// Mask grown by `radius` pixels, with smooth edges
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_possible_wrap)]
+    #[allow(clippy::cast_precision_loss)]
+    fn dilate(&self, radius: f32) -> Self {
+        let reach = radius.ceil() as i32 + 1;
+        let disk: Vec<(i32, i32, f32)> = (-reach..=reach)
+            .flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| {
+                let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                (dx, dy, (radius + 0.5 - distance).clamp(0.0, 1.0))
+            })
+            .filter(|(_, _, weight)| *weight > 0.0)
+            .collect();
+        let mut grown = Self::new(self.origin, self.width, self.height);
+        for (idx, &coverage) in self.values.iter().enumerate() {
+            if coverage <= 0.0 {
+                continue;
+            }
+            let x = self.origin.0 + (idx % self.width) as i32;
+            let y = self.origin.1 + (idx / self.width) as i32;
+            for &(dx, dy, weight) in &disk {
+                if let Some(target) = grown.index(x + dx, y + dy) {
+                    let value = &mut grown.values[target];
+                    *value = value.max(coverage * weight);
+                }
+            }
+        }
+        grown
+    }
+}
+
+/// Text rasterized on a transparent background
+pub(crate) struct Rendered {
+    /// Position of the top left pixel, relative to the text box
+    origin: (i32, i32),
+    /// Size of the text box
+    size: Vec2,
+    /// Pixels, not premultiplied
+    image: Rgba32FImage,
+}
+
+/// Text box being edited
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TextBox {
+    /// Top left corner, in canvas coordinates
+    pub(crate) pos: Pos2,
+    /// Text, one line per row
+    pub(crate) content: String,
+    /// Offset from the pointer to the corner while the box is dragged
+    grab: Option<Vec2>,
+}
+
+impl TextBox {
+    /// Empty text box
+    pub(crate) fn new(pos: Pos2) -> Self {
+        Self {
+            pos: pos.round(),
+            content: String::new(),
+            grab: None,
+        }
+    }
+
+    /// Start dragging the box from a position
+    pub(crate) fn grab(&mut self, pos: Pos2) {
+        self.grab = Some(self.pos - pos);
+    }
+
+    /// Move the box with the pointer
+    pub(crate) fn drag(&mut self, pos: Pos2) {
+        if let Some(grab) = self.grab {
+            self.pos = (pos + grab).round();
+        }
+    }
+
+    /// Stop dragging the box
+    pub(crate) fn release(&mut self) {
+        self.grab = None;
+    }
+}
+
+/// Fonts of the text tool, and the text box rasterized with them
+pub(crate) struct Fonts {
+    /// Fonts with their name, bundled ones first
+    loaded: Vec<(String, FontArc)>,
+    /// Last rasterized text, with its settings
+    rendered: Option<(TextTool, String, Rendered)>,
+    /// Texture of the last rasterized text
+    texture: Option<TextureHandle>,
+}
+
+impl std::fmt::Debug for Fonts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&String> = self.loaded.iter().map(|(name, _)| name).collect();
+        f.debug_struct("Fonts")
+            .field("loaded", &names)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Fonts {
+    /// Fonts shipped with the app
+    fn default() -> Self {
+        let definitions = egui::FontDefinitions::default();
+        let loaded = BUNDLED_FONTS
+            .iter()
+            .filter_map(|(name, key)| {
+                let font = match &definitions.font_data.get(*key)?.font {
+                    Cow::Borrowed(data) => FontArc::try_from_slice(data).ok()?,
+                    Cow::Owned(data) => FontArc::try_from_vec(data.clone()).ok()?,
+                };
+                Some(((*name).to_string(), font))
+            })
+            .collect();
+        Self {
+            loaded,
+            rendered: None,
+            texture: None,
+        }
+    }
+}
+
+impl Fonts {
+    /// Add a font read from a TTF or OTF file, replacing the font with the same name
+    /// # Errors
+    /// Fails if the file is not a font
+    pub(crate) fn load(&mut self, name: String, data: Vec<u8>) -> Result<(), AppError> {
+        let font = FontArc::try_from_vec(data).map_err(|e| {
+            AppError::new_with_source(format!("Cannot read the font '{name}'"), Arc::new(e))
+        })?;
+        self.loaded.retain(|(loaded, _)| *loaded != name);
+        self.loaded.push((name, font));
+        Ok(())
+    }
+
+    /// Rasterize the text box if the text or the settings changed
+    fn update(&mut self, tool: &TextTool, content: &str) -> Option<&Rendered> {
+        let up_to_date = self
+            .rendered
+            .as_ref()
+            .is_some_and(|(rendered_tool, text, _)| rendered_tool == tool && text == content);
+        if !up_to_date {
+            let font = self
+                .loaded
+                .iter()
+                .find(|(name, _)| *name == tool.font)
+                .or(self.loaded.first())
+                .map(|(_, font)| font)?;
+            let rendered = tool.render(font, content);
+            self.rendered = Some((tool.clone(), content.to_string(), rendered));
+            self.texture = None;
+        }
+        self.rendered.as_ref().map(|(_, _, rendered)| rendered)
+    }
+
+    /// Area of the text box on the canvas, as last painted
+    pub(crate) fn bounds(&self, text: &TextBox) -> Option<Rect> {
+        let (_, _, rendered) = self.rendered.as_ref()?;
+        Some(Rect::from_min_size(text.pos, rendered.size))
+    }
+
+    /// Paint the text box, over the image at `image_rect` on the screen
+    #[allow(clippy::cast_precision_loss)]
+    pub(crate) fn paint(
+        &mut self,
+        ui: &egui::Ui,
+        tool: &TextTool,
+        text: &TextBox,
+        image_rect: Rect,
+        color: Color32,
+    ) {
+        let offset = image_rect.min.to_vec2() + text.pos.to_vec2();
+        let Some(rendered) = self.update(tool, &text.content) else {
+            return;
+        };
+        let (width, height) = rendered.image.dimensions();
+        let image_min = Pos2::new(rendered.origin.0 as f32, rendered.origin.1 as f32) + offset;
+        let image_size = Vec2::new(width as f32, height as f32);
+        let frame = Rect::from_min_size(offset.to_pos2(), rendered.size);
+        if self.texture.is_none()
+            && let Some((_, _, rendered)) = &self.rendered
+        {
+            let rgba = DynamicImage::ImageRgba32F(rendered.image.clone()).to_rgba8();
+            let image = egui::ColorImage::from_rgba_unmultiplied(
+                [width as usize, height as usize],
+                rgba.as_flat_samples().as_slice(),
+            );
+            self.texture = Some(
+                ui.ctx()
+                    .load_texture("text", image, TextureOptions::default()),
+            );
+        }
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let painter = ui.painter();
+        painter.image(
+            texture.id(),
+            Rect::from_min_size(image_min, image_size),
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        let stroke = selection::handle_radius(ui) / 8.0;
+        painter.rect_stroke(
+            frame.expand(stroke),
+            0.0,
+            Stroke::new(stroke, color),
+            egui::StrokeKind::Outside,
+        );
+    }
+
+    /// Draw a text box in the document, with the current settings
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    pub(crate) fn commit(
+        &mut self,
+        document: &mut Document,
+        tool: &TextTool,
+        text: &TextBox,
+    ) -> Result<(), AppError> {
+        if text.content.trim().is_empty() {
+            return Ok(());
+        }
+        let Some(rendered) = self.update(tool, &text.content) else {
+            return Err(AppError::new("No font to draw the text".to_string()));
+        };
+        document.draw_text(text, rendered, tool.new_layer)
+    }
+}
+
+impl Document {
+    /// Draw rasterized text in a new layer, or in the active layer inside the selection
+    /// # Errors
+    /// Fails if the pixels cannot be updated
+    #[allow(clippy::cast_possible_truncation)]
+    #[allow(clippy::cast_sign_loss)]
+    fn draw_text(
+        &mut self,
+        text: &TextBox,
+        rendered: &Rendered,
+        new_layer: bool,
+    ) -> Result<(), AppError> {
+        let left = text.pos.x as i32 + rendered.origin.0;
+        let top = text.pos.y as i32 + rendered.origin.1;
+        if new_layer {
+            let img = ops::convert(
+                &DynamicImage::ImageRgba32F(rendered.image.clone()),
+                ops::with_alpha(self.color_type()),
+            );
+            let name: String = text
+                .content
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take(32)
+                .collect();
+            let mut layer = Layer::new(name, img);
+            layer.props.offset = (left, top);
+            self.add_layer(layer);
+            return Ok(());
+        }
+        let (width, height) = self.size();
+        let (text_width, text_height) = rendered.image.dimensions();
+        let clamp = |value: i64, max: u32| value.clamp(0, i64::from(max)) as u32;
+        let (min_x, min_y) = (clamp(left.into(), width), clamp(top.into(), height));
+        let max_x = clamp(i64::from(left) + i64::from(text_width), width);
+        let max_y = clamp(i64::from(top) + i64::from(text_height), height);
+        let canvas = Region::new(0, 0, width, height);
+        let region = Region::new(min_x, min_y, max_x - min_x, max_y - min_y)
+            .intersection(self.selection_region().unwrap_or(canvas));
+        if region.is_empty() {
+            return Ok(());
+        }
+        let mask = self.selection_mask(region);
+        let layer = self.layer();
+        let layer_region = layer.to_layer_region(region);
+        let origin = (
+            i64::from(layer_region.x) + i64::from(layer.props.offset.0) - i64::from(left),
+            i64::from(layer_region.y) + i64::from(layer.props.offset.1) - i64::from(top),
+        );
+        self.apply_op(Some(region), mask.as_ref(), |before| {
+            let mut pixels = before.to_rgba32f();
+            for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+                let (Ok(x), Ok(y)) = (
+                    u32::try_from(origin.0 + i64::from(x)),
+                    u32::try_from(origin.1 + i64::from(y)),
+                ) else {
+                    continue;
+                };
+                if let Some(src) = rendered.image.get_pixel_checked(x, y) {
+                    shapes::over(&mut pixel.0, src.0);
+                }
+            }
+            ops::convert(&DynamicImage::ImageRgba32F(pixels), before.color())
+        })
+    }
+}
+
+impl TarsierApp {
+    /// Text tool ui, with the content of the text box
+    pub(crate) fn text_ui(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
+        self.mode.text.show(ui, &self.fonts);
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return;
+        };
+        let Some(text) = &mut document.selection.text else {
+            ui.label("Click on the canvas to add a text");
+            return;
+        };
+        let response = ui.add(
+            egui::TextEdit::multiline(&mut text.content)
+                .id(Id::new(TEXT_EDIT_ID))
+                .hint_text("Text")
+                .desired_rows(3),
+        );
+        let mut apply =
+            response.has_focus() && ui.input_mut(|i| i.consume_shortcut(&APPLY_SHORTCUT));
+        let mut cancel = false;
+        ui.horizontal(|ui| {
+            apply |= ui
+                .button("Apply")
+                .on_hover_text(ui.ctx().format_shortcut(&APPLY_SHORTCUT))
+                .clicked();
+            cancel = ui.button("Cancel").clicked();
+        });
+        ui.label("Drag to move the text, click elsewhere to start another one");
+        if cancel {
+            document.selection.text = None;
+        } else if apply
+            && let Some(text) = document.selection.text.take()
+            && let Err(e) = self.fonts.commit(document, &self.mode.text, &text)
+        {
+            error_manager.add_error(e);
+        }
+    }
+
+    /// Draw the current text box, and start a new one at `pos` on the canvas
+    /// unless `pos` is in the current box
+    pub(crate) fn place_text(&mut self, ui: &egui::Ui, pos: Pos2) -> Result<(), AppError> {
+        let Some(document) = self.documents.get_current_doc_mut() else {
+            return Ok(());
+        };
+        if let Some(text) = &document.selection.text
+            && self
+                .fonts
+                .bounds(text)
+                .is_some_and(|bounds| bounds.contains(pos))
+        {
+            return Ok(());
+        }
+        ui.memory_mut(|memory| memory.request_focus(Id::new(TEXT_EDIT_ID)));
+        let previous = document.selection.text.replace(TextBox::new(pos));
+        match previous {
+            Some(text) => self.fonts.commit(document, &self.mode.text, &text),
+            None => Ok(()),
+        }
+    }
+}
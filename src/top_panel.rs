@@ -1,13 +1,29 @@
 //! Top panel
 use bladvak::eframe::egui::{self, Color32, TextFormat, text::LayoutJob};
 use bladvak::errors::ErrorManager;
-use image::ImageFormat;
-use std::io::Cursor;
-use std::path::Path;
+use image::{ColorType, DynamicImage, GenericImageView, ImageFormat};
+use std::path::{Path, PathBuf};
 
 use crate::document::Document;
+use crate::export::ExportOptions;
+use crate::formats::{SAVE_FORMATS, SaveFormat};
+use crate::layer::Layer;
+use crate::ops;
+use crate::project::PROJECT_EXTENSION;
 use crate::{TarsierApp, edit_mode::EditMode};
 
+/// Where to paste the clipboard image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum PasteTarget {
+    /// In a new document
+    #[default]
+    NewDocument,
+    /// In a new layer of the current document
+    Layer,
+    /// As a floating selection of the current document
+    Selection,
+}
+
 impl TarsierApp {
     /// Show the file menu
     pub(crate) fn app_menu_file(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
@@ -18,37 +34,20 @@ impl TarsierApp {
         self.menu_clipboard(ui, error_manager);
         if self.documents.is_some() {
             ui.menu_button("Save", |ui| {
-                if ui.button("PNG").clicked() {
-                    ui.close();
-                    self.save_image_with_format(
-                        ImageFormat::Png.extensions_str()[0],
-                        ImageFormat::Png,
-                        error_manager,
-                    );
-                }
-                if ui.button("JPEG").clicked() {
-                    ui.close();
-                    self.save_image_with_format(
-                        ImageFormat::Jpeg.extensions_str()[0],
-                        ImageFormat::Jpeg,
-                        error_manager,
-                    );
-                }
-                if ui.button("BMP").clicked() {
-                    ui.close();
-                    self.save_image_with_format(
-                        ImageFormat::Bmp.extensions_str()[0],
-                        ImageFormat::Bmp,
-                        error_manager,
-                    );
+                for save_format in SAVE_FORMATS {
+                    if ui.button(save_format.name).clicked() {
+                        ui.close();
+                        self.save_as(save_format);
+                    }
                 }
-                if ui.button("GIF").clicked() {
+                ui.separator();
+                if ui
+                    .button("Project")
+                    .on_hover_text("Save layers, selection and history")
+                    .clicked()
+                {
                     ui.close();
-                    self.save_image_with_format(
-                        ImageFormat::Gif.extensions_str()[0],
-                        ImageFormat::Gif,
-                        error_manager,
-                    );
+                    self.save_project(error_manager);
                 }
             });
         }
@@ -57,76 +56,151 @@ impl TarsierApp {
     /// Show the clipboard menu
     pub fn menu_clipboard(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         let is_document = self.documents.is_some();
+        let has_selection = self
+            .documents
+            .get_current_doc_mut()
+            .is_some_and(|document| document.selection.rectangle.is_some());
         ui.menu_button("Clipboard", |ui| {
             if is_document
-                && ui.button("Copy").clicked()
+                && ui
+                    .button("Copy")
+                    .on_hover_text("Copy the selection, or the whole image")
+                    .clicked()
                 && let Some(document) = self.documents.get_current_doc_mut()
-                && let Err(e) = bladvak::utils::set_image_in_clipboard(
-                    ui.ctx(),
-                    document.img.width() as usize,
-                    document.img.height() as usize,
-                    document.img.to_rgba8().as_flat_samples().as_slice(),
-                )
             {
-                error_manager.add_error(e);
+                let copied = if has_selection {
+                    document.copy_selection()
+                } else {
+                    Some(document.composite())
+                };
+                if let Some(copied) = copied
+                    && let Err(e) = self.copy_to_clipboard(ui.ctx(), copied)
+                {
+                    error_manager.add_error(e);
+                }
             }
-
-            if ui.button("Paste").clicked()
-                && let Err(err) = self.clipboard.launch_get_image()
+            if has_selection
+                && ui.button("Cut").clicked()
+                && let Some(document) = self.documents.get_current_doc_mut()
             {
-                error_manager.add_error(err);
+                match document.cut_selection() {
+                    Ok(Some(copied)) => {
+                        if let Err(e) = self.copy_to_clipboard(ui.ctx(), copied) {
+                            error_manager.add_error(e);
+                        }
+                        self.updated_image();
+                    }
+                    Ok(None) => {}
+                    Err(e) => error_manager.add_error(e),
+                }
+            }
+            let targets = [
+                (
+                    PasteTarget::Selection,
+                    "Paste",
+                    "Paste as a floating selection",
+                ),
+                (PasteTarget::NewDocument, "Paste as new image", ""),
+                (PasteTarget::Layer, "Paste as layer", ""),
+            ];
+            for (target, label, hover) in targets {
+                let available = match target {
+                    PasteTarget::NewDocument => true,
+                    PasteTarget::Selection | PasteTarget::Layer => is_document,
+                };
+                if available && ui.button(label).on_hover_text(hover).clicked() {
+                    self.paste_target = target;
+                    if let Err(err) = self.clipboard.launch_get_image() {
+                        error_manager.add_error(err);
+                    }
+                }
             }
         });
     }
 
-    /// Save the current image with the specified format
-    pub(crate) fn save_image_with_format(
+    /// Put an image in the clipboard, and keep it to paste it without loss
+    fn copy_to_clipboard(&mut self, ctx: &egui::Context, img: DynamicImage) -> Result<(), String> {
+        let rgba = img.to_rgba8();
+        bladvak::utils::set_image_in_clipboard(
+            ctx,
+            rgba.width() as usize,
+            rgba.height() as usize,
+            rgba.as_flat_samples().as_slice(),
+        )?;
+        self.copied = Some(img);
+        Ok(())
+    }
+
+    /// Paste an image from the clipboard, the last copied image is used if it is the same
+    fn paste(
         &mut self,
-        extension: &str,
-        image_format: ImageFormat,
+        rgba_data: Vec<u8>,
+        width: u32,
+        height: u32,
         error_manager: &mut ErrorManager,
     ) {
+        let img = match &self.copied {
+            Some(copied)
+                if copied.dimensions() == (width, height)
+                    && copied.to_rgba8().as_raw() == &rgba_data =>
+            {
+                copied.clone()
+            }
+            _ => {
+                let Some(buffer) = image::ImageBuffer::from_raw(width, height, rgba_data) else {
+                    error_manager.add_error("Invalid image data from clipboard".to_string());
+                    return;
+                };
+                DynamicImage::ImageRgba8(buffer)
+            }
+        };
+        let document = self.documents.get_current_doc_mut();
+        match (self.paste_target, document) {
+            (PasteTarget::Layer, Some(document)) => {
+                document.add_layer(Layer::new("Pasted", img));
+                self.updated_image();
+            }
+            (PasteTarget::Selection, Some(document)) => {
+                if let Err(e) = document.paste_floating(img) {
+                    error_manager.add_error(e);
+                }
+                self.mode.current = EditMode::Selection;
+            }
+            _ => self.new_file(PathBuf::from("pasted.png"), img, None),
+        }
+    }
+
+    /// Save the current document as a project file
+    pub(crate) fn save_project(&mut self, error_manager: &mut ErrorManager) {
         let Some(document) = self.documents.get_current_doc_mut() else {
             error_manager.add_error("No document to save");
             return;
         };
-        let current_save_path =
-            if document.filename.extension().and_then(|e| e.to_str()) == Some(extension) {
-                document.filename.clone()
-            } else {
-                document.filename.with_extension(extension)
-            };
-        let save_path = bladvak::utils::get_save_path(Some(&current_save_path));
-        match save_path {
-            Ok(save_p) => {
-                if let Some(path_to_save) = save_p {
-                    document.filename.clone_from(&path_to_save);
-                    if let Err(err) = document.save_image(image_format, &path_to_save) {
-                        error_manager.add_error(err);
-                    }
-                }
-            }
+        let data = match document.to_project() {
+            Ok(data) => data,
             Err(e) => {
                 error_manager.add_error(e);
+                return;
+            }
+        };
+        let default_path = document.filename.with_extension(PROJECT_EXTENSION);
+        match bladvak::utils::get_save_path(Some(&default_path)) {
+            Ok(Some(path)) => {
+                if let Err(e) = bladvak::utils::save_file(&data, &path) {
+                    error_manager.add_error(e);
+                }
             }
+            Ok(None) => {}
+            Err(e) => error_manager.add_error(e),
         }
     }
 
     /// Show the top panel
     pub(crate) fn app_top_panel(&mut self, ui: &mut egui::Ui, error_manager: &mut ErrorManager) {
         match self.clipboard.image(ui.ctx()) {
+            #[allow(clippy::cast_possible_truncation)]
             Some(Ok((rgba_data, width, height))) => {
-                #[allow(clippy::cast_possible_truncation)]
-                if let Some(buffer) =
-                    image::ImageBuffer::from_raw(width as u32, height as u32, rgba_data)
-                {
-                    use std::path::PathBuf;
-
-                    let img = image::DynamicImage::ImageRgba8(buffer);
-                    self.new_file(PathBuf::from("pasted.png"), img, None);
-                } else {
-                    error_manager.add_error("Invalid image data from clipboard".to_string());
-                }
+                self.paste(rgba_data, width as u32, height as u32, error_manager);
             }
             Some(Err(err)) => {
                 error_manager.add_error(err);
@@ -162,30 +236,16 @@ impl TarsierApp {
         );
         ui.menu_button(job, |ui| {
             let previous_state = self.mode.current;
-            ui.selectable_value(
-                &mut self.mode.current,
-                EditMode::Cursor,
-                EditMode::Cursor.to_string(),
-            );
-            ui.selectable_value(
-                &mut self.mode.current,
-                EditMode::Selection,
-                EditMode::Selection.to_string(),
-            );
-            ui.selectable_value(
-                &mut self.mode.current,
-                EditMode::Drawing,
-                EditMode::Drawing.to_string(),
-            );
-            ui.selectable_value(
-                &mut self.mode.current,
-                EditMode::ColorSelection,
-                EditMode::ColorSelection.to_string(),
-            );
+            for mode in EditMode::ALL {
+                ui.selectable_value(&mut self.mode.current, mode, mode.to_string());
+            }
             if self.mode.current != previous_state {
                 ui.close();
+                if let Err(e) = document.commit_floating() {
+                    error_manager.add_error(e);
+                }
                 if self.mode.current == EditMode::Cursor {
-                    document.selection.rectangle = None;
+                    document.selection.clear();
                 }
             }
         });
@@ -195,12 +255,50 @@ impl TarsierApp {
 }
 
 impl Document {
-    /// Save the current image
-    fn save_image(&mut self, format: ImageFormat, path_file: &Path) -> Result<(), String> {
-        let mut bytes: Vec<u8> = Vec::new();
-        self.img
-            .write_to(&mut Cursor::new(&mut bytes), format)
-            .map_err(|e| format!("Cannot write image: {e}"))?;
+    /// Encode the current image, with the layers flattened and converted to `color_type` if any
+    /// # Errors
+    /// Fails if the format cannot store the color type or the size, or if the image cannot be encoded
+    pub(crate) fn encode(
+        &self,
+        format: ImageFormat,
+        color_type: Option<ColorType>,
+        options: &ExportOptions,
+    ) -> Result<Vec<u8>, String> {
+        let mut img = self.composite();
+        if let Some(color_type) = color_type {
+            img = ops::convert(&img, color_type);
+        }
+        if let Some(save_format) = SaveFormat::get(format) {
+            if !save_format.supports(img.color()) {
+                return Err(format!(
+                    "{} cannot store {:?} images, convert the image first",
+                    save_format.name,
+                    img.color()
+                ));
+            }
+            if let Some(max_size) = save_format.max_size
+                && !save_format.fits(img.dimensions())
+            {
+                return Err(format!(
+                    "{} images are at most {max_size}x{max_size} pixels, resize the image first",
+                    save_format.name
+                ));
+            }
+        }
+        options.encode(&img, format, self.exif.as_ref())
+    }
+
+    /// Save the current image, with the layers flattened and converted to `color_type` if any
+    /// # Errors
+    /// Fails if the image cannot be encoded or written
+    pub(crate) fn save_image(
+        &self,
+        format: ImageFormat,
+        path_file: &Path,
+        color_type: Option<ColorType>,
+        options: &ExportOptions,
+    ) -> Result<(), String> {
+        let bytes = self.encode(format, color_type, options)?;
         bladvak::utils::save_file(&bytes, path_file)
     }
 }